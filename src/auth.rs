@@ -0,0 +1,88 @@
+//! API keys and the per-key token buckets that rate limit them.
+
+use crate::*;
+
+// Paths served without a key, so load balancer health checks keep working.
+pub(crate) const UNAUTHENTICATED_PATHS: [&str; 2] = [LIVENESS_PATH, "/ready"];
+
+// API_KEYS is either a list of keys (comma- or newline-separated) or the path of a file holding
+// one. An entry may override the default limits as key:rps:burst. Unset means no key is needed.
+// A set but unusable list rejects every request rather than opening the API up.
+pub(crate) static API_KEYS: Lazy<Option<HashMap<String, KeyLimit>>> = Lazy::new(|| {
+    let setting = std::env::var("API_KEYS").ok()?;
+    let list = std::fs::read_to_string(&setting).unwrap_or(setting);
+    let mut keys = HashMap::new();
+    for entry in list.split([',', '\n']).map(str::trim).filter(|entry| !entry.is_empty() && !entry.starts_with('#')) {
+        match KeyLimit::parse(entry) {
+            Some((key, limit)) => {
+                keys.insert(key.to_string(), limit);
+            }
+            None => tracing::warn!("Ignoring malformed API_KEYS entry (expected key or key:rps:burst)"),
+        }
+    }
+    if keys.is_empty() {
+        tracing::warn!("API_KEYS is set but holds no keys; every request will be refused");
+    }
+    Some(keys)
+});
+
+pub(crate) static KEY_BUCKETS: Lazy<std::sync::Mutex<HashMap<String, TokenBucket>>> = Lazy::new(Default::default);
+
+#[derive(Clone, Copy)]
+pub(crate) struct KeyLimit {
+    pub(crate) rps: f64,
+    pub(crate) burst: f64,
+}
+
+impl KeyLimit {
+    pub(crate) fn parse(entry: &str) -> Option<(&str, KeyLimit)> {
+        let mut parts = entry.split(':');
+        let key = parts.next().filter(|key| !key.is_empty())?;
+        let rps = match parts.next() {
+            Some(rps) => rps.parse().ok().filter(|rps: &f64| *rps > 0.0)?,
+            None => *API_RATE_LIMIT_RPS,
+        };
+        let burst = match parts.next() {
+            Some(burst) => burst.parse().ok().filter(|burst: &f64| *burst >= 1.0)?,
+            None => *API_RATE_LIMIT_BURST,
+        };
+        parts.next().is_none().then_some((key, KeyLimit { rps, burst }))
+    }
+}
+
+// Starts full, so a new key can burst straight away, and refills at `rps` up to `burst`.
+pub(crate) struct TokenBucket {
+    pub(crate) tokens: f64,
+    pub(crate) updated: Instant,
+}
+
+impl TokenBucket {
+    // Takes one token, or says how long until one is available.
+    pub(crate) fn take(&mut self, limit: KeyLimit) -> Result<(), Duration> {
+        let now = Instant::now();
+        self.tokens = (self.tokens + now.duration_since(self.updated).as_secs_f64() * limit.rps).min(limit.burst);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / limit.rps))
+        }
+    }
+}
+
+// Checks the request's X-API-Key and charges it one request. Passes everything when API_KEYS
+// is unset.
+pub(crate) fn authorize(path: &str, headers: &warp::http::HeaderMap) -> Result<(), ApiError> {
+    let Some(keys) = API_KEYS.as_ref() else {
+        return Ok(());
+    };
+    if UNAUTHENTICATED_PATHS.contains(&path) {
+        return Ok(());
+    }
+    let key = headers.get("x-api-key").and_then(|value| value.to_str().ok()).ok_or(ApiError::Unauthorized)?;
+    let limit = *keys.get(key).ok_or(ApiError::Unauthorized)?;
+    let mut buckets = KEY_BUCKETS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let bucket = buckets.entry(key.to_string()).or_insert_with(|| TokenBucket { tokens: limit.burst, updated: Instant::now() });
+    bucket.take(limit).map_err(|retry_after| ApiError::RateLimited { retry_after })
+}
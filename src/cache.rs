@@ -0,0 +1,321 @@
+//! Background refresh loops and the optional Redis tier shared between replicas.
+
+use crate::*;
+
+// A single cached value and the time it was fetched.
+pub(crate) type TimedCache<T> = PerCluster<RwLock<Option<(T, SystemTime)>>>;
+
+// Each run happens in its own task so a panicking refresh is logged and retried on the next tick.
+pub(crate) fn spawn_refresh_loop<N, F, Fut, E>(name: N, every: Duration, job: F)
+where
+    N: std::fmt::Display + Send + 'static,
+    F: Fn() -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = Result<(), E>> + Send + 'static,
+    E: std::fmt::Display + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+        loop {
+            interval.tick().await;
+            match tokio::spawn(job()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => tracing::warn!("background refresh of {} failed: {}", name, e),
+                Err(e) => tracing::warn!("background refresh of {} panicked: {}", name, e),
+            }
+        }
+    });
+}
+
+// A refresh loop for each cluster, with the job run against that cluster. The cluster's name is
+// added to log lines when there is more than one.
+pub(crate) fn spawn_cluster_refresh_loop<F, Fut, E>(name: &'static str, every: Duration, job: F)
+where
+    F: Fn() -> Fut + Clone + Send + 'static,
+    Fut: std::future::Future<Output = Result<(), E>> + Send + 'static,
+    E: std::fmt::Display + Send + 'static,
+{
+    for cluster in clusters() {
+        spawn_cluster_job(cluster, name, every, job.clone());
+    }
+}
+
+pub(crate) fn spawn_cluster_job<F, Fut, E>(cluster: &'static Cluster, name: &'static str, every: Duration, job: F)
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = Result<(), E>> + Send + 'static,
+    E: std::fmt::Display + Send + 'static,
+{
+    let name = if clusters().len() > 1 { format!("{} ({})", name, cluster.name) } else { name.to_string() };
+    spawn_refresh_loop(name, every, move || in_cluster(cluster, job()));
+}
+
+// Recent /tokens and /balance answers keyed by endpoint and wallet, kept for their TTL (and
+// the stale window, for endpoints that serve stale answers).
+pub(crate) static WALLET_RESPONSES: PerCluster<RwLock<HashMap<String, (Value, SystemTime)>>> = PerCluster::new(|| RwLock::new(HashMap::new()));
+// Wallet answers being refreshed in the background, so a burst of stale hits starts one fetch.
+pub(crate) static WALLET_RESPONSE_REFRESHES: RefreshSet = PerCluster::new(Default::default);
+
+// Keys of background refreshes in progress.
+pub(crate) type RefreshSet = PerCluster<std::sync::Mutex<HashSet<String>>>;
+
+// A claimed key in a RefreshSet. It is released when dropped, so a refresh that panics or is
+// cancelled doesn't keep later ones from starting.
+pub(crate) struct RefreshClaim {
+    refreshes: &'static std::sync::Mutex<HashSet<String>>,
+    key: String,
+}
+
+impl RefreshClaim {
+    // None when a refresh of `key` is already running.
+    pub(crate) fn new(refreshes: &'static RefreshSet, key: &str) -> Option<Self> {
+        let refreshes: &'static std::sync::Mutex<HashSet<String>> = refreshes;
+        let claimed = refreshes.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(key.to_string());
+        claimed.then(|| RefreshClaim { refreshes, key: key.to_string() })
+    }
+}
+
+impl Drop for RefreshClaim {
+    fn drop(&mut self) {
+        self.refreshes.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(&self.key);
+    }
+}
+
+// How old a cached wallet answer is and how long it is good for, for with_cache_headers.
+#[derive(Clone, Copy)]
+pub(crate) struct CachedAge {
+    pub(crate) age: Duration,
+    pub(crate) ttl: Duration,
+}
+
+// CACHE_TTL, or EMPTY_RESULT_TTL when that is longer and the answer is a /tokens answer for a
+// wallet without token accounts: pollers of unused wallets are the ones worth shielding the
+// RPC from, and a wallet holding tokens changes more often than one that never did.
+pub(crate) fn wallet_response_ttl(value: &Value) -> Duration {
+    let empty = value["tokens"].as_array().is_some_and(Vec::is_empty) && value["skipped_count"] == 0;
+    match empty {
+        true => CACHE_TTL.max(*EMPTY_RESULT_TTL),
+        false => *CACHE_TTL,
+    }
+}
+
+// The longest any wallet answer is kept for.
+pub(crate) fn longest_wallet_response_ttl() -> Duration {
+    CACHE_TTL.max(*EMPTY_RESULT_TTL) + *STALE_WHILE_REVALIDATE
+}
+
+// Serves `key` from WALLET_RESPONSES unless it has expired or `fresh` is set, and otherwise
+// stores what `fetch` returns. Also returns the answer's age and TTL (see wallet_response_ttl),
+// or None when caching is off.
+// Errors are never cached. A request with ?min_context_slot= always fetches, since a cached
+// answer may be from before that slot. Answers are cached per commitment level, since a
+// processed answer can't stand in for a finalized one.
+// When `endpoint` is in STALE_WHILE_REVALIDATE_ENDPOINTS, an answer up to STALE_WHILE_REVALIDATE
+// past its TTL is still served, with its age, while `fetch` runs in the background to replace
+// it. Only the in-memory tier serves stale answers; Redis entries expire at the TTL.
+pub(crate) async fn cached_wallet_response<Fut>(
+    key: String,
+    fresh: bool,
+    endpoint: &str,
+    fetch: Fut,
+) -> Result<(Value, Option<CachedAge>), ApiError>
+where
+    Fut: std::future::Future<Output = Result<Value, ApiError>> + Send + 'static,
+{
+    if CACHE_TTL.is_zero() {
+        return Ok((fetch.await?, None));
+    }
+    let stale_window = match STALE_WHILE_REVALIDATE_ENDPOINTS.contains(endpoint) {
+        true => *STALE_WHILE_REVALIDATE,
+        false => Duration::ZERO,
+    };
+    let commitment = request_commitment();
+    let key = format!("{}:{}", commitment.as_str(), key);
+    // Boxed so the cache lookups awaited ahead of it don't each carry a copy of the (large)
+    // fetch future on the stack.
+    let fetch = Box::pin(fetch);
+    let fresh = fresh || request_min_context_slot().is_some();
+    let cached = match fresh {
+        true => None,
+        false => WALLET_RESPONSES.read().await.get(&key).and_then(|(value, fetched)| Some((value.clone(), fetched.elapsed().ok()?))),
+    };
+    if let Some((value, age)) = cached {
+        let ttl = wallet_response_ttl(&value);
+        if age < ttl {
+            record_cache_lookup("wallet_responses", true);
+            return Ok((value, Some(CachedAge { age, ttl })));
+        }
+        if age < ttl + stale_window {
+            if let Some(claim) = RefreshClaim::new(&WALLET_RESPONSE_REFRESHES, &key) {
+                // Spawned tasks start outside the request's scopes, so the refresh is told the
+                // cluster and commitment the answer is cached for.
+                let refresh = async move {
+                    let _claim = claim;
+                    match fetch.await {
+                        Ok(value) => store_wallet_response(key, value).await,
+                        Err(e) => tracing::warn!("background refresh of {} failed: {}", key, e),
+                    }
+                };
+                tokio::spawn(in_cluster(cluster(), REQUEST_COMMITMENT.scope(Some(commitment), refresh)));
+            }
+            record_cache_lookup("wallet_responses", true);
+            return Ok((value, Some(CachedAge { age, ttl })));
+        }
+    }
+    let shared_key = format!("wallet-response:{}", key);
+    if !fresh
+        && let Some((value, fetched)) = shared_json_get(&shared_key, CACHE_TTL.max(*EMPTY_RESULT_TTL)).await
+        && let age = fetched.elapsed().unwrap_or_default()
+        && age < wallet_response_ttl(&value)
+    {
+        record_cache_lookup("wallet_responses", true);
+        let ttl = wallet_response_ttl(&value);
+        WALLET_RESPONSES.write().await.insert(key, (value.clone(), fetched));
+        return Ok((value, Some(CachedAge { age, ttl })));
+    }
+    record_cache_lookup("wallet_responses", false);
+    let value = fetch.await?;
+    let ttl = wallet_response_ttl(&value);
+    store_wallet_response(key, value.clone()).await;
+    Ok((value, Some(CachedAge { age: Duration::ZERO, ttl })))
+}
+
+// Entries are kept for the longest any endpoint may serve them: stale answers that outlive the
+// TTL are only served by endpoints that opted in.
+pub(crate) async fn store_wallet_response(key: String, value: Value) {
+    let now = SystemTime::now();
+    shared_json_set(&format!("wallet-response:{}", key), &value, now, wallet_response_ttl(&value)).await;
+    let keep = longest_wallet_response_ttl();
+    let mut responses = WALLET_RESPONSES.write().await;
+    responses.retain(|_, (_, fetched)| fetched.elapsed().unwrap_or(keep) < keep);
+    responses.insert(key, (value, now));
+}
+
+// An optional second tier behind the in-memory caches, shared by every replica pointed at the
+// same REDIS_URL, that also survives restarts. It is only built with `--features redis`; without
+// it, or without REDIS_URL, these are no-ops. Redis errors are logged and treated as misses.
+#[cfg(feature = "redis")]
+pub(crate) static REDIS: tokio::sync::OnceCell<Option<redis::aio::ConnectionManager>> = tokio::sync::OnceCell::const_new();
+
+#[cfg(feature = "redis")]
+pub(crate) async fn redis_connection() -> Option<redis::aio::ConnectionManager> {
+    REDIS
+        .get_or_init(|| async {
+            let url = std::env::var("REDIS_URL").ok()?;
+            let connect = async { redis::aio::ConnectionManager::new(redis::Client::open(url)?).await };
+            match connect.await {
+                Ok(manager) => Some(manager),
+                Err(e) => {
+                    tracing::warn!("Failed to connect to Redis, caches stay in memory: {}", e);
+                    None
+                }
+            }
+        })
+        .await
+        .clone()
+}
+
+#[cfg(feature = "redis")]
+pub(crate) async fn shared_cache_get(key: &str) -> Option<String> {
+    let mut conn = redis_connection().await?;
+    redis::AsyncCommands::get(&mut conn, format!("solana-api:{}", cluster_cache_key(key))).await.unwrap_or_else(|e| {
+        tracing::warn!("Redis GET {} failed: {}", key, e);
+        None
+    })
+}
+
+#[cfg(feature = "redis")]
+pub(crate) async fn shared_cache_set(key: &str, value: &str, ttl: Duration) {
+    let Some(mut conn) = redis_connection().await else {
+        return;
+    };
+    let result: redis::RedisResult<()> =
+        redis::AsyncCommands::set_ex(&mut conn, format!("solana-api:{}", cluster_cache_key(key)), value, ttl.as_secs().max(1)).await;
+    if let Err(e) = result {
+        tracing::warn!("Redis SET {} failed: {}", key, e);
+    }
+}
+
+// One MGET for many keys, in order.
+#[cfg(feature = "redis")]
+pub(crate) async fn shared_cache_get_many(keys: &[String]) -> Vec<Option<String>> {
+    let Some(mut conn) = redis_connection().await.filter(|_| !keys.is_empty()) else {
+        return vec![None; keys.len()];
+    };
+    let prefixed: Vec<String> = keys.iter().map(|key| format!("solana-api:{}", cluster_cache_key(key))).collect();
+    redis::AsyncCommands::mget(&mut conn, prefixed).await.unwrap_or_else(|e| {
+        tracing::warn!("Redis MGET of {} keys failed: {}", keys.len(), e);
+        vec![None; keys.len()]
+    })
+}
+
+#[cfg(not(feature = "redis"))]
+pub(crate) async fn shared_cache_get(_key: &str) -> Option<String> {
+    None
+}
+
+#[cfg(not(feature = "redis"))]
+pub(crate) async fn shared_cache_set(_key: &str, _value: &str, _ttl: Duration) {}
+
+#[cfg(not(feature = "redis"))]
+pub(crate) async fn shared_cache_get_many(keys: &[String]) -> Vec<Option<String>> {
+    vec![None; keys.len()]
+}
+
+// JSON values are stored with the time they were fetched, so a replica that picks one up
+// expires it when the replica that fetched it does, and reports the same age. The Redis calls
+// are boxed: their futures are large, and these sit inside already deep request futures.
+pub(crate) fn shared_json_entry(stored: Option<String>, ttl: Duration) -> Option<(Value, SystemTime)> {
+    let mut entry: Value = serde_json::from_str(&stored?).ok()?;
+    let fetched = SystemTime::UNIX_EPOCH + Duration::from_millis(entry["fetched_ms"].as_u64()?);
+    (fetched.elapsed().unwrap_or_default() < ttl).then(|| (entry["value"].take(), fetched))
+}
+
+pub(crate) async fn shared_json_get(key: &str, ttl: Duration) -> Option<(Value, SystemTime)> {
+    shared_json_entry(Box::pin(shared_cache_get(key)).await, ttl)
+}
+
+pub(crate) async fn shared_json_get_many(keys: &[String], ttl: Duration) -> Vec<Option<(Value, SystemTime)>> {
+    Box::pin(shared_cache_get_many(keys)).await.into_iter().map(|stored| shared_json_entry(stored, ttl)).collect()
+}
+
+pub(crate) async fn shared_json_set(key: &str, value: &Value, fetched: SystemTime, ttl: Duration) {
+    if !cfg!(feature = "redis") {
+        return;
+    }
+    let Some(remaining) = ttl.checked_sub(fetched.elapsed().unwrap_or_default()).filter(|remaining| !remaining.is_zero()) else {
+        return;
+    };
+    let fetched_ms = fetched.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+    let entry = serde_json::json!({ "fetched_ms": fetched_ms, "value": value });
+    Box::pin(shared_cache_set(key, &entry.to_string(), remaining)).await;
+}
+
+pub(crate) fn spawn_background_refresh() {
+    spawn_cluster_refresh_loop("token map", TOKEN_MAP_TTL, refresh_token_map);
+    spawn_cluster_refresh_loop("vote accounts", VOTE_ACCOUNTS_TTL, refresh_vote_accounts);
+    spawn_cluster_refresh_loop("epoch info", EPOCH_INFO_TTL, refresh_epoch_info);
+    // Twice per TTL, so a price is refetched before it expires rather than just after.
+    spawn_cluster_refresh_loop("prices", PRICE_TTL / 2, refresh_prices);
+    if ADDRESS_LABELS_URL.is_some() {
+        spawn_refresh_loop("address labels", ADDRESS_LABELS_TTL, refresh_address_labels);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_token_answers_get_the_longer_ttl() {
+        let empty = serde_json::json!({ "tokens": [], "skipped_count": 0 });
+        assert_eq!(wallet_response_ttl(&empty), CACHE_TTL.max(*EMPTY_RESULT_TTL));
+
+        // Accounts that were skipped mean the wallet isn't empty.
+        let skipped = serde_json::json!({ "tokens": [], "skipped_count": 1 });
+        let held = serde_json::json!({ "tokens": [{ "mint": "MintA" }], "skipped_count": 0 });
+        let balance = serde_json::json!({ "lamports": 0, "sol": 0.0, "sol_string": "0" });
+        for value in [skipped, held, balance] {
+            assert_eq!(wallet_response_ttl(&value), *CACHE_TTL);
+        }
+    }
+}
@@ -0,0 +1,1637 @@
+//! Chain data other than tokens: balances, blocks, epochs, fees, accounts and transaction history.
+
+use crate::*;
+
+// Validator identity -> leader slot indices within the epoch.
+pub(crate) type LeaderSchedule = std::sync::Arc<serde_json::Map<String, Value>>;
+
+pub(crate) static ADDRESS_LABELS: Lazy<RwLock<(HashMap<String, String>, SystemTime)>> =
+    Lazy::new(|| RwLock::new((HashMap::new(), SystemTime::now())));
+
+pub(crate) static VOTE_ACCOUNTS: TimedCache<Vec<Value>> = PerCluster::new(|| RwLock::new(None));
+pub(crate) static EPOCH_INFO: TimedCache<Value> = PerCluster::new(|| RwLock::new(None));
+
+// A node's genesis hash never changes, so the first answer is kept for the process lifetime.
+pub(crate) static GENESIS_HASH: PerCluster<RwLock<Option<String>>> = PerCluster::new(|| RwLock::new(None));
+// Rent-exempt minimum for a 0-byte account. Rent parameters only change by feature activation,
+// so it is fetched once per process like the genesis hash.
+pub(crate) static RENT_EXEMPT_MINIMUM: PerCluster<RwLock<Option<u64>>> = PerCluster::new(|| RwLock::new(None));
+// The epoch schedule is fixed at genesis, so the first answer is kept for the process lifetime.
+pub(crate) static EPOCH_SCHEDULE: PerCluster<RwLock<Option<EpochSchedule>>> = PerCluster::new(|| RwLock::new(None));
+// Leader schedules by epoch. A schedule never changes once the epoch has one, so entries are
+// only evicted (oldest first) to keep a few epochs' worth in memory.
+pub(crate) static LEADER_SCHEDULES: PerCluster<RwLock<BTreeMap<u64, LeaderSchedule>>> = PerCluster::new(|| RwLock::new(BTreeMap::new()));
+
+// (epoch, lamports): the minimum delegation only changes with feature activations at epoch boundaries.
+pub(crate) static MINIMUM_DELEGATION: PerCluster<RwLock<Option<(u64, u64)>>> = PerCluster::new(|| RwLock::new(None));
+
+#[derive(Clone, Copy)]
+pub(crate) struct EpochSchedule {
+    pub(crate) slots_per_epoch: u64,
+    pub(crate) leader_schedule_slot_offset: u64,
+    pub(crate) warmup: bool,
+    pub(crate) first_normal_epoch: u64,
+    pub(crate) first_normal_slot: u64,
+}
+
+// Lossy past 2^53 lamports (about 9 million SOL); use lamports_to_sol_string where exactness matters.
+pub(crate) fn lamports_to_sol(lamports: u64) -> f64 {
+    lamports as f64 / LAMPORTS_PER_SOL as f64
+}
+
+// Exact decimal SOL amount without trailing zeros, e.g. 2500000000 -> "2.5".
+pub(crate) fn lamports_to_sol_string(lamports: u64) -> String {
+    let whole = lamports / LAMPORTS_PER_SOL;
+    let fraction = lamports % LAMPORTS_PER_SOL;
+    if fraction == 0 {
+        return whole.to_string();
+    }
+    let fraction = format!("{:09}", fraction);
+    format!("{}.{}", whole, fraction.trim_end_matches('0'))
+}
+
+pub(crate) fn sol_amount(lamports: u64) -> Value {
+    serde_json::json!(SolBalance::from_lamports(lamports))
+}
+
+pub(crate) async fn refresh_address_labels() -> Result<(), ApiError> {
+    let Some(source) = ADDRESS_LABELS_URL.as_deref() else {
+        return Ok(());
+    };
+    let labels: Value = if source.starts_with("http://") || source.starts_with("https://") {
+        CLIENT.get(source).send().await?.json().await?
+    } else {
+        match tokio::fs::read_to_string(source).await.map(|raw| serde_json::from_str(&raw)) {
+            Ok(Ok(labels)) => labels,
+            Ok(Err(e)) => {
+                tracing::warn!("address labels refresh failed: invalid JSON in {}: {}", source, e);
+                return Ok(());
+            }
+            Err(e) => {
+                tracing::warn!("address labels refresh failed: cannot read {}: {}", source, e);
+                return Ok(());
+            }
+        }
+    };
+
+    let label_map: HashMap<String, String> = labels
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(address, label)| Some((address.clone(), label.as_str()?.to_string())))
+        .collect();
+    *ADDRESS_LABELS.write().await = (label_map, SystemTime::now());
+    Ok(())
+}
+
+pub(crate) async fn label_for(address: &str) -> Option<String> {
+    ADDRESS_LABELS_URL.as_ref()?;
+    let cache = ADDRESS_LABELS.read().await;
+    let stale = cache.0.is_empty() || cache.1.elapsed().unwrap_or(Duration::from_secs(0)) > ADDRESS_LABELS_TTL;
+    if stale && !*BACKGROUND_REFRESH {
+        drop(cache);
+        if let Err(e) = refresh_address_labels().await {
+            tracing::warn!("address labels refresh failed: {}", e);
+        }
+        return ADDRESS_LABELS.read().await.0.get(address).cloned();
+    }
+    cache.0.get(address).cloned()
+}
+
+pub(crate) async fn fetch_account_data(pubkey: &str) -> Result<Vec<u8>, ApiError> {
+    let resp = rpc_call("getAccountInfo", serde_json::json!([pubkey, { "encoding": "base64" }])).await?;
+    extract_value(&resp)?["data"][0]
+        .as_str()
+        .and_then(|data| base64::engine::general_purpose::STANDARD.decode(data).ok())
+        .ok_or_else(|| ApiError::NotFound("Account has no data".to_string()))
+}
+
+// Lamports and the slot the RPC read them at.
+pub(crate) async fn balance_at_slot(wallet: &str) -> Result<(u64, Option<u64>), ApiError> {
+    let resp = rpc_call("getBalance", serde_json::json!([wallet])).await?;
+    let lamports = extract_value(&resp)?.as_u64().unwrap_or(0);
+    let slot = extract_result(&resp)?["context"]["slot"].as_u64();
+    Ok((lamports, slot))
+}
+
+pub(crate) async fn get_sol_balance(wallet: &str) -> Result<Value, ApiError> {
+    let (lamports, _) = balance_at_slot(wallet).await?;
+
+    let mut balance = sol_amount(lamports);
+    if let Some(label) = label_for(wallet).await {
+        balance["label"] = Value::String(label);
+    }
+
+    Ok(balance)
+}
+
+// The balance split into the rent-exempt reserve for the account's data size and what can be
+// spent while keeping the account open. An account that doesn't exist has no reserve.
+pub(crate) async fn get_sol_balance_breakdown(wallet: &str) -> Result<Value, ApiError> {
+    let config = serde_json::json!({ "encoding": "base64", "dataSlice": { "offset": 0, "length": 0 } });
+    let resp = rpc_call("getAccountInfo", serde_json::json!([wallet, config])).await?;
+    let account = &extract_result(&resp)?["value"];
+    let lamports = account["lamports"].as_u64().unwrap_or(0);
+    let reserve = match account["space"].as_u64() {
+        _ if account.is_null() => 0,
+        Some(0) | None => rent_exempt_minimum().await?,
+        Some(space) => {
+            let resp = rpc_call("getMinimumBalanceForRentExemption", serde_json::json!([space])).await?;
+            extract_result(&resp)?.as_u64().ok_or_else(|| ApiError::NotFound("Rent-exempt minimum not available".to_string()))?
+        }
+    };
+
+    let mut balance = sol_amount(lamports);
+    balance["total_lamports"] = lamports.into();
+    balance["rent_exempt_reserve"] = reserve.into();
+    balance["spendable_lamports"] = lamports.saturating_sub(reserve).into();
+    if let Some(label) = label_for(wallet).await {
+        balance["label"] = Value::String(label);
+    }
+    Ok(balance)
+}
+
+// An SSE stream of "balance" events: one with the balance when the client connects, then one
+// each time the lamports change. The polling task exits as soon as the client disconnects;
+// failed polls are logged and retried on the next tick.
+pub(crate) fn balance_events(wallet: String) -> impl futures::Stream<Item = Result<warp::sse::Event, std::convert::Infallible>> {
+    let (events, receiver) = tokio::sync::mpsc::channel(1);
+    tokio::spawn(in_cluster(cluster(), async move {
+        let mut interval = tokio::time::interval(*SSE_POLL_INTERVAL);
+        let mut last = None;
+        loop {
+            tokio::select! {
+                _ = events.closed() => return,
+                _ = interval.tick() => {}
+            }
+            match balance_at_slot(&wallet).await {
+                Ok((lamports, slot)) if last != Some(lamports) => {
+                    let mut balance = sol_amount(lamports);
+                    balance["slot"] = serde_json::json!(slot);
+                    let event = warp::sse::Event::default().event("balance").data(balance.to_string());
+                    if events.send(event).await.is_err() {
+                        return;
+                    }
+                    last = Some(lamports);
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("balance stream for {} failed to poll: {}", wallet, e),
+            }
+        }
+    }));
+    futures::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|event| (Ok(event), receiver))
+    })
+}
+
+// GET /ws/balance relays the RPC's own subscriptions instead of polling. accountSubscribe on the
+// wallet reports SOL changes as they land. logsSubscribe for transactions mentioning the wallet
+// only says *something* happened, so token balances are then re-read, at most once per
+// WS_TOKEN_REFRESH_INTERVAL and only pushed when they changed.
+pub(crate) async fn balance_socket(socket: warp::ws::WebSocket, wallet: String) {
+    let (mut client, mut incoming) = socket.split();
+    if let Err(message) = relay_balances(&mut client, &mut incoming, &wallet).await {
+        tracing::warn!("balance socket for {} ended: {}", wallet, message);
+        let _ = send_socket_event(&mut client, serde_json::json!({ "type": "error", "message": message })).await;
+    }
+    let _ = client.close().await;
+}
+
+type SocketSink = futures::stream::SplitSink<warp::ws::WebSocket, warp::ws::Message>;
+
+async fn send_socket_event(client: &mut SocketSink, event: Value) -> Result<(), String> {
+    client.send(warp::ws::Message::text(event.to_string())).await.map_err(|e| format!("client write failed: {}", e))
+}
+
+fn sol_event(lamports: u64, slot: Value) -> Value {
+    let mut event = sol_amount(lamports);
+    event["type"] = Value::String("sol".to_string());
+    event["slot"] = slot;
+    event
+}
+
+async fn token_snapshot(wallet: &str) -> Result<Value, String> {
+    get_spl_tokens(wallet).await.map(|(tokens, _)| tokens).map_err(|e| format!("token refresh failed: {}", e))
+}
+
+// Ok means the client went away; Err is a message worth passing on before closing.
+async fn relay_balances(
+    client: &mut SocketSink,
+    incoming: &mut futures::stream::SplitStream<warp::ws::WebSocket>,
+    wallet: &str,
+) -> Result<(), String> {
+    let (lamports, slot) = balance_at_slot(wallet).await.map_err(|e| format!("balance fetch failed: {}", e))?;
+    send_socket_event(client, sol_event(lamports, serde_json::json!(slot))).await?;
+    let mut tokens = token_snapshot(wallet).await?;
+    send_socket_event(client, serde_json::json!({ "type": "tokens", "tokens": tokens })).await?;
+
+    // Fixtures never change, so there is nothing to subscribe to; just hold the socket open.
+    if *MOCK_MODE {
+        while let Some(Ok(message)) = incoming.next().await {
+            if message.is_close() {
+                break;
+            }
+        }
+        return Ok(());
+    }
+
+    let (mut upstream, _) = tokio_tungstenite::connect_async(rpc_ws_request()?)
+        .await
+        .map_err(|e| format!("RPC WebSocket connection failed: {}", e))?;
+    let commitment = DEFAULT_COMMITMENT.as_str();
+    let subscriptions = [
+        serde_json::json!({
+            "jsonrpc": "2.0", "id": 1, "method": "accountSubscribe",
+            "params": [wallet, { "encoding": "base64", "commitment": commitment }]
+        }),
+        serde_json::json!({
+            "jsonrpc": "2.0", "id": 2, "method": "logsSubscribe",
+            "params": [{ "mentions": [wallet] }, { "commitment": commitment }]
+        }),
+    ];
+    for subscription in subscriptions {
+        upstream
+            .send(tokio_tungstenite::tungstenite::Message::Text(subscription.to_string()))
+            .await
+            .map_err(|e| format!("RPC subscription failed: {}", e))?;
+    }
+
+    let mut tokens_stale = false;
+    let mut token_refresh = tokio::time::interval(WS_TOKEN_REFRESH_INTERVAL);
+    loop {
+        tokio::select! {
+            message = incoming.next() => match message {
+                Some(Ok(message)) if !message.is_close() => {}
+                _ => return Ok(()),
+            },
+            message = upstream.next() => {
+                let text = match message {
+                    Some(Ok(tokio_tungstenite::tungstenite::Message::Text(text))) => text,
+                    Some(Ok(tokio_tungstenite::tungstenite::Message::Close(_))) | None => {
+                        return Err("RPC closed the subscription".to_string());
+                    }
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(format!("RPC subscription failed: {}", e)),
+                };
+                let Ok(notification) = serde_json::from_str::<Value>(&text) else {
+                    continue;
+                };
+                match notification["method"].as_str() {
+                    Some("accountNotification") => {
+                        let result = &notification["params"]["result"];
+                        if let Some(lamports) = result["value"]["lamports"].as_u64() {
+                            send_socket_event(client, sol_event(lamports, result["context"]["slot"].clone())).await?;
+                        }
+                    }
+                    Some("logsNotification") => tokens_stale = true,
+                    _ => {
+                        if let Some(error) = notification.get("error") {
+                            return Err(format!("RPC refused the subscription: {}", error["message"]));
+                        }
+                    }
+                }
+            }
+            _ = token_refresh.tick(), if tokens_stale => {
+                tokens_stale = false;
+                let latest = token_snapshot(wallet).await?;
+                if latest != tokens {
+                    send_socket_event(client, serde_json::json!({ "type": "tokens", "tokens": latest })).await?;
+                    tokens = latest;
+                }
+            }
+        }
+    }
+}
+
+// The same balance seen at each commitment level. processed minus finalized is SOL that has
+// landed but could still be rolled back.
+pub(crate) async fn get_sol_balance_by_commitment(wallet: &str) -> Result<Value, ApiError> {
+    let at = |commitment: Commitment| async move {
+        let resp = rpc_call("getBalance", serde_json::json!([wallet, { "commitment": commitment.as_str() }])).await?;
+        Ok::<_, ApiError>(sol_amount(extract_value(&resp)?.as_u64().unwrap_or(0)))
+    };
+    let (processed, confirmed, finalized) =
+        tokio::join!(at(Commitment::Processed), at(Commitment::Confirmed), at(Commitment::Finalized));
+
+    let mut balances = serde_json::json!({
+        "processed": processed?,
+        "confirmed": confirmed?,
+        "finalized": finalized?
+    });
+    if let Some(label) = label_for(wallet).await {
+        balances["label"] = Value::String(label);
+    }
+    Ok(balances)
+}
+
+// Genesis hashes of the public clusters, for naming the one the RPC serves.
+pub(crate) const KNOWN_CLUSTERS: [(&str, &str); 3] = [
+    ("5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d", "mainnet-beta"),
+    ("EtWTRABZaYq6iMfeYKouRu166VU2xqa1wcaWoxPkrZBG", "devnet"),
+    ("4uhcVJyU9pJkvQyS88uRDiswHXSCkY3zQawwpjk2NsNY", "testnet"),
+];
+
+// Skipped slots and blocks pruned from the node's ledger are reported either as a null result
+// or as one of these RPC errors; all of them mean the block is not available.
+pub(crate) const BLOCK_NOT_AVAILABLE_CODES: [i64; 3] = [-32004, -32007, -32009];
+
+pub(crate) async fn get_block(slot: u64, full: bool) -> Result<Value, ApiError> {
+    let params = serde_json::json!([
+        slot,
+        {
+            "encoding": "jsonParsed",
+            "maxSupportedTransactionVersion": 0,
+            "transactionDetails": if full { "full" } else { "signatures" },
+            "rewards": false
+        }
+    ]);
+
+    let resp = rpc_call("getBlock", params).await?;
+    let not_available = || ApiError::NotFound("Block not available".to_string());
+    let block = match extract_result(&resp) {
+        Ok(block) => block,
+        Err(ApiError::NotFound(_)) => return Err(not_available()),
+        Err(ApiError::Rpc { code, .. }) if BLOCK_NOT_AVAILABLE_CODES.contains(&code) => return Err(not_available()),
+        Err(e) => return Err(e),
+    };
+
+    let signature_count = if full {
+        block["transactions"].as_array().map_or(0, Vec::len)
+    } else {
+        block["signatures"].as_array().map_or(0, Vec::len)
+    };
+
+    let mut details = serde_json::json!({
+        "blockhash": block["blockhash"],
+        "parent_slot": block["parentSlot"],
+        "block_time": block["blockTime"],
+        "block_height": block["blockHeight"],
+        "signature_count": signature_count
+    });
+    if full {
+        details["transactions"] = block["transactions"].clone();
+    }
+
+    Ok(details)
+}
+
+pub(crate) async fn refresh_vote_accounts() -> Result<(), ApiError> {
+    let resp = rpc_call("getVoteAccounts", serde_json::json!([])).await?;
+    let result = extract_result(&resp)?;
+    let mut accounts = Vec::new();
+    for (key, delinquent) in [("current", false), ("delinquent", true)] {
+        for account in result[key].as_array().into_iter().flatten() {
+            accounts.push(serde_json::json!({
+                "vote_pubkey": account["votePubkey"],
+                "node_pubkey": account["nodePubkey"],
+                "commission": account["commission"],
+                "activated_stake": account["activatedStake"],
+                "last_vote": account["lastVote"],
+                "delinquent": delinquent
+            }));
+        }
+    }
+    accounts.sort_by_key(|a| std::cmp::Reverse(a["activated_stake"].as_u64().unwrap_or(0)));
+
+    *VOTE_ACCOUNTS.write().await = Some((accounts, SystemTime::now()));
+    Ok(())
+}
+
+pub(crate) async fn get_vote_accounts(delinquent_only: bool) -> Result<Value, ApiError> {
+    let fresh = matches!(
+        &*VOTE_ACCOUNTS.read().await,
+        Some((_, fetched)) if fetched.elapsed().unwrap_or(Duration::from_secs(0)) <= VOTE_ACCOUNTS_TTL
+    );
+    if !fresh && !*BACKGROUND_REFRESH {
+        refresh_vote_accounts().await?;
+    }
+
+    let cache = VOTE_ACCOUNTS.read().await;
+    let accounts: Vec<&Value> = cache
+        .iter()
+        .flat_map(|(accounts, _)| accounts)
+        .filter(|a| !delinquent_only || a["delinquent"] == true)
+        .collect();
+    Ok(serde_json::json!(accounts))
+}
+
+pub(crate) async fn refresh_epoch_info() -> Result<(), ApiError> {
+    let resp = rpc_call("getEpochInfo", serde_json::json!([])).await?;
+    let info = extract_result(&resp)?.clone();
+    *EPOCH_INFO.write().await = Some((info, SystemTime::now()));
+    Ok(())
+}
+
+pub(crate) async fn get_epoch_info() -> Result<Value, ApiError> {
+    let fresh = matches!(
+        &*EPOCH_INFO.read().await,
+        Some((_, fetched)) if fetched.elapsed().unwrap_or(Duration::from_secs(0)) <= EPOCH_INFO_TTL
+    );
+    if !fresh && !*BACKGROUND_REFRESH {
+        refresh_epoch_info().await?;
+    }
+    Ok(EPOCH_INFO.read().await.as_ref().map(|(info, _)| info.clone()).unwrap_or(Value::Null))
+}
+
+pub(crate) async fn get_minimum_delegation() -> Result<Value, ApiError> {
+    let epoch = get_epoch_info().await?["epoch"].as_u64();
+    let cached = *MINIMUM_DELEGATION.read().await;
+
+    let lamports = match cached {
+        Some((cached_epoch, lamports)) if Some(cached_epoch) == epoch => lamports,
+        _ => {
+            let resp = rpc_call("getStakeMinimumDelegation", serde_json::json!([])).await?;
+            let lamports = extract_value(&resp)?.as_u64().unwrap_or(0);
+            if let Some(epoch) = epoch {
+                *MINIMUM_DELEGATION.write().await = Some((epoch, lamports));
+            }
+            lamports
+        }
+    };
+
+    Ok(sol_amount(lamports))
+}
+
+// Stake accounts `wallet` can withdraw from, with where each delegation stands this epoch and
+// the reward it earned in the last completed one.
+pub(crate) async fn get_stake_accounts(wallet: &str) -> Result<Value, ApiError> {
+    let params = serde_json::json!([STAKE_PROGRAM_ID, {
+        "encoding": "jsonParsed",
+        "filters": [{ "memcmp": { "offset": STAKE_WITHDRAWER_OFFSET, "bytes": wallet } }]
+    }]);
+    let (resp, epoch_info) = tokio::join!(rpc_call("getProgramAccounts", params), get_epoch_info());
+    let accounts = extract_result(&resp?)?.as_array().cloned().unwrap_or_default();
+    let epoch = epoch_info?["epoch"].as_u64();
+
+    let pubkeys: Vec<&Value> = accounts.iter().map(|account| &account["pubkey"]).collect();
+    let rewards = match epoch {
+        Some(epoch) if epoch > 0 && !pubkeys.is_empty() => {
+            match rpc_call("getInflationReward", serde_json::json!([pubkeys, { "epoch": epoch - 1 }])).await {
+                Ok(resp) => extract_result(&resp).cloned().unwrap_or(Value::Null),
+                Err(e) => {
+                    tracing::warn!("Failed to fetch stake rewards: {}", e);
+                    Value::Null
+                }
+            }
+        }
+        _ => Value::Null,
+    };
+
+    let (mut total, mut total_active) = (0u64, 0u64);
+    let stakes: Vec<Value> = accounts
+        .iter()
+        .enumerate()
+        .map(|(i, account)| {
+            let mut stake = stake_activation(account, epoch);
+            total += account["account"]["lamports"].as_u64().unwrap_or(0);
+            total_active += stake["active_lamports"].as_u64().unwrap_or(0);
+            stake["reward"] = match &rewards[i] {
+                Value::Null => Value::Null,
+                reward => serde_json::json!({
+                    "epoch": reward["epoch"],
+                    "lamports": reward["amount"],
+                    "post_balance": reward["postBalance"],
+                    "commission": reward["commission"]
+                }),
+            };
+            stake
+        })
+        .collect();
+
+    Ok(serde_json::json!({
+        "wallet": wallet,
+        "epoch": epoch,
+        "total": sol_amount(total),
+        "active": sol_amount(total_active),
+        "stake_accounts": stakes
+    }))
+}
+
+// Activation is worked out from the delegation's epochs alone: a delegation activates over the
+// epoch it was made in and deactivates over the one it was undone in. Stake held back by the
+// cluster-wide warmup and cooldown limit is not modelled, so in a rare epoch where more than 9%
+// of all stake moves, some stake shown as active is still warming up.
+pub(crate) fn stake_activation(account: &Value, epoch: Option<u64>) -> Value {
+    let epoch_field = |value: &Value| value.as_str().and_then(|e| e.parse::<u64>().ok()).filter(|&e| e != u64::MAX);
+    let lamports = account["account"]["lamports"].as_u64().unwrap_or(0);
+    let parsed = &account["account"]["data"]["parsed"];
+    let info = &parsed["info"];
+    let reserve = info["meta"]["rentExemptReserve"].as_str().and_then(|r| r.parse::<u64>().ok()).unwrap_or(0);
+    let delegation = &info["stake"]["delegation"];
+    let delegated = delegation["stake"].as_str().and_then(|s| s.parse::<u64>().ok()).unwrap_or(0);
+    let activation_epoch = epoch_field(&delegation["activationEpoch"]);
+    let deactivation_epoch = epoch_field(&delegation["deactivationEpoch"]);
+
+    // A u64::MAX activation epoch marks stake delegated at genesis, which was never warming up.
+    let (state, active, activating) = match (parsed["type"].as_str(), epoch) {
+        (Some("delegated"), Some(epoch)) => match (activation_epoch, deactivation_epoch) {
+            (_, Some(deactivated)) if deactivated < epoch => ("inactive", 0, 0),
+            (Some(activated), Some(deactivated)) if activated == deactivated => ("inactive", 0, 0),
+            (_, Some(_)) => ("deactivating", delegated, 0),
+            (Some(activated), None) if activated >= epoch => ("activating", 0, delegated),
+            _ => ("active", delegated, 0),
+        },
+        (Some("delegated"), None) => ("delegated", 0, 0),
+        _ => ("inactive", 0, 0),
+    };
+    let mut stake = serde_json::json!({
+        "pubkey": account["pubkey"],
+        "state": state,
+        "voter": delegation["voter"],
+        "activation_epoch": activation_epoch,
+        "deactivation_epoch": deactivation_epoch,
+        "staker": info["meta"]["authorized"]["staker"],
+        "withdrawer": info["meta"]["authorized"]["withdrawer"],
+        "rent_exempt_reserve": reserve,
+        "delegated_lamports": delegated,
+        "active_lamports": active,
+        "activating_lamports": activating,
+        "inactive_lamports": lamports.saturating_sub(reserve).saturating_sub(active + activating)
+    });
+    if let (Some(object), Value::Object(amount)) = (stake.as_object_mut(), sol_amount(lamports)) {
+        object.extend(amount);
+    }
+    stake
+}
+
+// Accounts of `program` that store `wallet` at byte `offset`, which is where most programs keep
+// an account's owner or authority. Programs the RPC knows how to parse come back as parsed JSON,
+// anything else as base64.
+pub(crate) async fn get_positions(wallet: &str, program: &str, offset: usize) -> Result<Value, ApiError> {
+    let params = serde_json::json!([program, {
+        "encoding": "jsonParsed",
+        "filters": [{ "memcmp": { "offset": offset, "bytes": wallet } }]
+    }]);
+    let resp = rpc_call("getProgramAccounts", params).await?;
+    let accounts = extract_result(&resp)?.as_array().cloned().unwrap_or_default();
+
+    let positions: Vec<Value> = accounts
+        .iter()
+        .map(|account| {
+            let data = &account["account"]["data"];
+            serde_json::json!({
+                "pubkey": account["pubkey"],
+                "lamports": account["account"]["lamports"],
+                "data": if data.is_array() { &data[0] } else { data }
+            })
+        })
+        .collect();
+
+    Ok(serde_json::json!({
+        "wallet": wallet,
+        "program": program,
+        "positions": positions
+    }))
+}
+
+// The RPC prices the message against its recent blockhash and answers null once that
+// blockhash has expired, so a 404 here means the message needs a fresh blockhash.
+pub(crate) async fn get_fee_for_message(message: &str) -> Result<Value, ApiError> {
+    let resp = rpc_call("getFeeForMessage", serde_json::json!([message])).await?;
+    let lamports = extract_value(&resp)
+        .map_err(|e| match e {
+            ApiError::NotFound(_) => ApiError::NotFound("Blockhash not found or expired".to_string()),
+            e => e,
+        })?
+        .as_u64()
+        .unwrap_or(0);
+
+    Ok(sol_amount(lamports))
+}
+
+// Percentiles (nearest rank) of the per-slot priority fees the node has for its recent slots,
+// in micro-lamports per compute unit. With `accounts`, each slot's fee is the lowest that landed
+// a transaction write-locking any of them, so hot accounts show their own congestion.
+pub(crate) async fn get_priority_fees(accounts: &[String]) -> Result<Value, ApiError> {
+    let params = if accounts.is_empty() { serde_json::json!([]) } else { serde_json::json!([accounts]) };
+    let resp = rpc_call("getRecentPrioritizationFees", params).await?;
+    let mut fees: Vec<u64> = extract_result(&resp)?
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|slot| slot["prioritizationFee"].as_u64())
+        .collect();
+    fees.sort_unstable();
+
+    let mut percentiles = serde_json::Map::new();
+    for p in FEE_PERCENTILES {
+        let rank = (p as usize * fees.len()).div_ceil(100).max(1);
+        percentiles.insert(format!("p{}", p), fees.get(rank - 1).copied().into());
+    }
+    Ok(serde_json::json!({
+        "accounts": accounts,
+        "slots": fees.len(),
+        "min": fees.first(),
+        "max": fees.last(),
+        "percentiles": percentiles
+    }))
+}
+
+// Transactions per second over the latest performance samples, in total and without votes.
+pub(crate) fn transactions_per_second(samples: &[Value]) -> Value {
+    let sum = |field: &str| samples.iter().filter_map(|sample| sample[field].as_u64()).sum::<u64>();
+    let (secs, slots) = (sum("samplePeriodSecs"), sum("numSlots"));
+    if secs == 0 {
+        return Value::Null;
+    }
+    let rate = |count: u64| (count as f64 / secs as f64 * 10.0).round() / 10.0;
+    serde_json::json!({
+        "total": rate(sum("numTransactions")),
+        "non_vote": rate(sum("numNonVoteTransactions")),
+        "average_slot_ms": (slots > 0).then(|| secs * 1000 / slots),
+        "samples": samples.len(),
+        "period_secs": secs
+    })
+}
+
+// Fee guidance and the state of the network in one call. The fees are required; the slot,
+// epoch and TPS are extras that come back null, with a warning, when their call fails.
+pub(crate) async fn get_network_fees(accounts: &[String]) -> Result<Value, ApiError> {
+    let (fees, slot, epoch_info, samples) = tokio::join!(
+        get_priority_fees(accounts),
+        rpc_call("getSlot", serde_json::json!([])),
+        get_epoch_info(),
+        rpc_call("getRecentPerformanceSamples", serde_json::json!([NETWORK_PERFORMANCE_SAMPLES]))
+    );
+    let mut fees = fees?;
+
+    let mut warnings = Vec::new();
+    let mut field = |name: &str, value: Result<Value, ApiError>| {
+        value.unwrap_or_else(|e| {
+            warnings.push(format!("{}: {}", name, e));
+            Value::Null
+        })
+    };
+    let slot = field("slot", slot.and_then(|resp| extract_result(&resp).cloned()));
+    let epoch = field(
+        "epoch",
+        epoch_info.map(|info| {
+            serde_json::json!({
+                "epoch": info["epoch"],
+                "slot_index": info["slotIndex"],
+                "slots_in_epoch": info["slotsInEpoch"],
+                "block_height": info["blockHeight"]
+            })
+        }),
+    );
+    let tps = field("tps", samples.and_then(|resp| Ok(transactions_per_second(extract_result(&resp)?.as_array().map(Vec::as_slice).unwrap_or_default()))));
+
+    let tiers: serde_json::Map<String, Value> =
+        FEE_TIERS.iter().map(|&(tier, p)| (tier.to_string(), fees["percentiles"][format!("p{}", p)].clone())).collect();
+    Ok(serde_json::json!({
+        "accounts": fees["accounts"].take(),
+        "priority_fee_tiers": tiers,
+        "priority_fees": {
+            "slots": fees["slots"],
+            "min": fees["min"],
+            "max": fees["max"],
+            "percentiles": fees["percentiles"]
+        },
+        "slot": slot,
+        "epoch": epoch,
+        "tps": tps,
+        "warnings": warnings
+    }))
+}
+
+// Without a commitment the request's or DEFAULT_COMMITMENT applies. The context slot is the slot the
+// node answered at, which SDKs use together with last_valid_block_height to judge expiry.
+pub(crate) async fn get_latest_blockhash(commitment: Option<Commitment>, with_context: bool) -> Result<Value, ApiError> {
+    let params = match commitment {
+        Some(commitment) => serde_json::json!([{ "commitment": commitment.as_str() }]),
+        None => serde_json::json!([]),
+    };
+    let resp = rpc_call("getLatestBlockhash", params).await?;
+    let context_slot = extract_result(&resp)?["context"]["slot"].as_u64();
+    let value = extract_value(&resp)?;
+
+    let mut blockhash = serde_json::json!({
+        "blockhash": value["blockhash"],
+        "last_valid_block_height": value["lastValidBlockHeight"]
+    });
+    if with_context {
+        blockhash["context_slot"] = context_slot.into();
+    }
+    Ok(blockhash)
+}
+
+pub(crate) async fn epoch_schedule() -> Result<EpochSchedule, ApiError> {
+    if let Some(schedule) = *EPOCH_SCHEDULE.read().await {
+        return Ok(schedule);
+    }
+    let resp = rpc_call("getEpochSchedule", serde_json::json!([])).await?;
+    let result = extract_result(&resp)?;
+    let (Some(slots_per_epoch), Some(leader_schedule_slot_offset), Some(warmup), Some(first_normal_epoch), Some(first_normal_slot)) = (
+        result["slotsPerEpoch"].as_u64().filter(|&n| n > 0),
+        result["leaderScheduleSlotOffset"].as_u64(),
+        result["warmup"].as_bool(),
+        result["firstNormalEpoch"].as_u64(),
+        result["firstNormalSlot"].as_u64(),
+    ) else {
+        return Err(ApiError::NotFound("Epoch schedule not available".to_string()));
+    };
+    let schedule = EpochSchedule { slots_per_epoch, leader_schedule_slot_offset, warmup, first_normal_epoch, first_normal_slot };
+    *EPOCH_SCHEDULE.write().await = Some(schedule);
+    Ok(schedule)
+}
+
+pub(crate) async fn get_epoch_schedule() -> Result<Value, ApiError> {
+    let schedule = epoch_schedule().await?;
+    Ok(serde_json::json!({
+        "slots_per_epoch": schedule.slots_per_epoch,
+        "leader_schedule_slot_offset": schedule.leader_schedule_slot_offset,
+        "warmup": schedule.warmup,
+        "first_normal_epoch": schedule.first_normal_epoch,
+        "first_normal_slot": schedule.first_normal_slot
+    }))
+}
+
+// The epoch containing `slot` and that epoch's first slot.
+pub(crate) fn epoch_of_slot(schedule: EpochSchedule, slot: u64) -> (u64, u64) {
+    let EpochSchedule { slots_per_epoch, first_normal_epoch, first_normal_slot, .. } = schedule;
+    if slot >= first_normal_slot {
+        let epochs = (slot - first_normal_slot) / slots_per_epoch;
+        return (first_normal_epoch + epochs, first_normal_slot + epochs * slots_per_epoch);
+    }
+    let (mut epoch, mut start, mut len) = (0, 0, MINIMUM_SLOTS_PER_EPOCH);
+    while start + len <= slot {
+        start += len;
+        len *= 2;
+        epoch += 1;
+    }
+    (epoch, start)
+}
+
+// Slot indices in the schedule are relative to epoch_start_slot.
+pub(crate) async fn get_leader_schedule(slot: Option<u64>, identity: Option<&str>) -> Result<Value, ApiError> {
+    let slot = match slot {
+        Some(slot) => slot,
+        None => get_epoch_info().await?["absoluteSlot"]
+            .as_u64()
+            .ok_or_else(|| ApiError::NotFound("Current slot not available".to_string()))?,
+    };
+    let (epoch, epoch_start_slot) = epoch_of_slot(epoch_schedule().await?, slot);
+
+    let cached = LEADER_SCHEDULES.read().await.get(&epoch).cloned();
+    let schedule = match cached {
+        Some(schedule) => schedule,
+        None => {
+            let resp = rpc_call("getLeaderSchedule", serde_json::json!([slot])).await?;
+            let schedule = match extract_result(&resp) {
+                Ok(Value::Object(schedule)) => std::sync::Arc::new(schedule.clone()),
+                Ok(_) | Err(ApiError::NotFound(_)) => {
+                    return Err(ApiError::NotFound("No leader schedule for that epoch yet".to_string()));
+                }
+                Err(e) => return Err(e),
+            };
+            let mut schedules = LEADER_SCHEDULES.write().await;
+            schedules.insert(epoch, schedule.clone());
+            while schedules.len() > LEADER_SCHEDULE_CACHE_EPOCHS {
+                schedules.pop_first();
+            }
+            schedule
+        }
+    };
+
+    let schedule = match identity {
+        Some(identity) => {
+            let mut filtered = serde_json::Map::new();
+            if let Some(slots) = schedule.get(identity) {
+                filtered.insert(identity.to_string(), slots.clone());
+            }
+            Value::Object(filtered)
+        }
+        None => Value::Object((*schedule).clone()),
+    };
+    Ok(serde_json::json!({
+        "epoch": epoch,
+        "epoch_start_slot": epoch_start_slot,
+        "schedule": schedule
+    }))
+}
+
+pub(crate) async fn genesis_hash() -> Result<String, ApiError> {
+    let cached = GENESIS_HASH.read().await.clone();
+    let genesis_hash = match cached {
+        Some(genesis_hash) => genesis_hash,
+        None => {
+            let resp = rpc_call("getGenesisHash", serde_json::json!([])).await?;
+            let genesis_hash = extract_result(&resp)?
+                .as_str()
+                .ok_or_else(|| ApiError::NotFound("Genesis hash not available".to_string()))?
+                .to_string();
+            *GENESIS_HASH.write().await = Some(genesis_hash.clone());
+            genesis_hash
+        }
+    };
+    Ok(genesis_hash)
+}
+
+pub(crate) fn cluster_name(genesis_hash: &str) -> &'static str {
+    KNOWN_CLUSTERS.iter().find(|(hash, _)| *hash == genesis_hash).map_or("custom", |(_, name)| name)
+}
+
+pub(crate) async fn rent_exempt_minimum() -> Result<u64, ApiError> {
+    if let Some(lamports) = *RENT_EXEMPT_MINIMUM.read().await {
+        return Ok(lamports);
+    }
+    let resp = rpc_call("getMinimumBalanceForRentExemption", serde_json::json!([0])).await?;
+    let lamports = extract_result(&resp)?
+        .as_u64()
+        .ok_or_else(|| ApiError::NotFound("Rent-exempt minimum not available".to_string()))?;
+    *RENT_EXEMPT_MINIMUM.write().await = Some(lamports);
+    Ok(lamports)
+}
+
+// Everything here is cached by the function that fetches it, so after the first call only the
+// minimum delegation's epoch check can reach the RPC.
+pub(crate) async fn get_constants() -> Result<Value, ApiError> {
+    let (cluster, epoch_schedule, minimum_delegation, rent_exempt_minimum) =
+        tokio::try_join!(get_cluster_info(), get_epoch_schedule(), get_minimum_delegation(), rent_exempt_minimum())?;
+    Ok(serde_json::json!({
+        "cluster": cluster,
+        "epoch_schedule": epoch_schedule,
+        "stake_minimum_delegation": minimum_delegation,
+        "rent_exempt_minimum": sol_amount(rent_exempt_minimum)
+    }))
+}
+
+// What the server sees of the request. client_ip is the TCP peer, so behind a proxy it is the
+// proxy and the client is in x_forwarded_for. Only the presence of an API key header is
+// reported, never its value.
+pub(crate) async fn whoami(remote: Option<std::net::SocketAddr>, headers: &warp::http::HeaderMap) -> Value {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok()).map(str::to_string);
+    let detected_cluster = match genesis_hash().await {
+        Ok(genesis_hash) => Value::String(cluster_name(&genesis_hash).to_string()),
+        Err(_) => Value::Null,
+    };
+    serde_json::json!({
+        "client_ip": remote.map(|remote| remote.ip().to_string()),
+        "x_forwarded_for": header("x-forwarded-for"),
+        "user_agent": header("user-agent"),
+        "api_key_present": headers.contains_key("x-api-key") || headers.contains_key("authorization"),
+        "detected_cluster": detected_cluster
+    })
+}
+
+pub(crate) async fn get_cluster_info() -> Result<Value, ApiError> {
+    let genesis_hash = genesis_hash().await?;
+    let cluster_name = cluster_name(&genesis_hash);
+    Ok(serde_json::json!({
+        "genesis_hash": genesis_hash,
+        "cluster_name": cluster_name
+    }))
+}
+
+// Balances for up to MAX_MULTIPLE_ACCOUNTS wallets from one getMultipleAccounts call, largest
+// first. Accounts that do not exist count as zero lamports.
+pub(crate) async fn get_rich_list(wallets: &[String]) -> Result<Value, ApiError> {
+    let config = serde_json::json!({ "encoding": "base64", "dataSlice": { "offset": 0, "length": 0 } });
+    let resp = rpc_call("getMultipleAccounts", serde_json::json!([wallets, config])).await?;
+    let accounts = extract_value(&resp)?.as_array().cloned().unwrap_or_default();
+
+    let mut balances: Vec<(&String, u64)> = wallets
+        .iter()
+        .zip(accounts.iter().map(|account| account["lamports"].as_u64().unwrap_or(0)).chain(std::iter::repeat(0)))
+        .collect();
+    balances.sort_by_key(|&(_, lamports)| std::cmp::Reverse(lamports));
+
+    let ranked: Vec<Value> = balances
+        .into_iter()
+        .enumerate()
+        .map(|(index, (wallet, lamports))| {
+            serde_json::json!({
+                "rank": index + 1,
+                "wallet": wallet,
+                "lamports": lamports,
+                "sol": lamports_to_sol(lamports)
+            })
+        })
+        .collect();
+    Ok(Value::Array(ranked))
+}
+
+// Faucets only exist on the public test clusters, and the cluster is checked by genesis hash
+// so a misconfigured RPC can't turn this into a mainnet call.
+pub(crate) async fn request_airdrop(wallet: &str, lamports: u64) -> Result<Value, ApiError> {
+    match cluster_name(&genesis_hash().await?) {
+        "devnet" | "testnet" => {}
+        "mainnet-beta" => return Err(ApiError::Forbidden("airdrop not available on mainnet".to_string())),
+        _ => return Err(ApiError::Forbidden("airdrop is only available on devnet and testnet".to_string())),
+    }
+
+    let resp = rpc_call("requestAirdrop", serde_json::json!([wallet, lamports])).await?;
+    let signature = extract_result(&resp)?.clone();
+    let mut airdrop = sol_amount(lamports);
+    airdrop["wallet"] = Value::String(wallet.to_string());
+    airdrop["signature"] = signature;
+    Ok(airdrop)
+}
+
+// Statuses come back in request order; signatures the node has never seen are null.
+pub(crate) async fn get_signature_statuses(signatures: &[String]) -> Result<Value, ApiError> {
+    let params = serde_json::json!([signatures, { "searchTransactionHistory": true }]);
+    let resp = rpc_call("getSignatureStatuses", params).await?;
+    let values = extract_value(&resp)?.as_array().cloned().unwrap_or_default();
+
+    let statuses: Vec<Value> = signatures
+        .iter()
+        .enumerate()
+        .map(|(i, signature)| match values.get(i) {
+            Some(status) if status.is_object() => serde_json::json!({
+                "signature": signature,
+                "slot": status["slot"],
+                "confirmations": status["confirmations"],
+                "confirmation_status": status["confirmationStatus"],
+                "err": status["err"]
+            }),
+            _ => Value::Null,
+        })
+        .collect();
+
+    Ok(serde_json::json!(statuses))
+}
+
+// The error for a JSON-RPC response refusing the transaction itself, as opposed to a node failure.
+pub(crate) fn transaction_rejection(resp: &Value) -> Option<ApiError> {
+    let error = resp.get("error")?;
+    let rpc_code = error["code"].as_i64().filter(|code| TRANSACTION_REJECTION_CODES.contains(code))?;
+    Some(ApiError::TransactionRejected {
+        rpc_code,
+        message: error["message"].as_str().unwrap_or("rejected by the node").to_string(),
+        err: error["data"]["err"].clone(),
+        logs: error["data"]["logs"].clone(),
+    })
+}
+
+// Runs a wire-format transaction against the node's current state without submitting it. The
+// signature check is skipped unless `sig_verify`, so unsigned transactions can be simulated.
+pub(crate) async fn simulate_transaction(bytes: &[u8], sig_verify: bool, replace_recent_blockhash: bool) -> Result<Value, ApiError> {
+    let transaction = base64::engine::general_purpose::STANDARD.encode(bytes);
+    let config = serde_json::json!({
+        "encoding": "base64",
+        "sigVerify": sig_verify,
+        "replaceRecentBlockhash": replace_recent_blockhash
+    });
+    let resp = rpc_call("simulateTransaction", serde_json::json!([transaction, config])).await?;
+    if let Some(rejection) = transaction_rejection(&resp) {
+        return Err(rejection);
+    }
+    let result = extract_result(&resp)?;
+    let value = &result["value"];
+    Ok(serde_json::json!({
+        "slot": result["context"]["slot"],
+        "success": value["err"].is_null(),
+        "err": value["err"],
+        "logs": value["logs"],
+        "units_consumed": value["unitsConsumed"],
+        "return_data": value["returnData"],
+        "replacement_blockhash": value["replacementBlockhash"]["blockhash"]
+    }))
+}
+
+// Submits a signed wire-format transaction. With `confirm`, waits up to TX_CONFIRM_TIMEOUT for it
+// to reach that commitment or fail; a status lookup that errors is retried until the deadline, as the
+// transaction is already out and the client still needs its signature.
+pub(crate) async fn send_transaction(
+    bytes: &[u8],
+    skip_preflight: bool,
+    max_retries: Option<u64>,
+    confirm: Option<Commitment>,
+) -> Result<Value, ApiError> {
+    let transaction = base64::engine::general_purpose::STANDARD.encode(bytes);
+    let mut config = serde_json::json!({
+        "encoding": "base64",
+        "skipPreflight": skip_preflight,
+        "preflightCommitment": request_commitment().as_str()
+    });
+    if let Some(max_retries) = max_retries {
+        config["maxRetries"] = serde_json::json!(max_retries);
+    }
+    let resp = rpc_call("sendTransaction", serde_json::json!([transaction, config])).await?;
+    if let Some(rejection) = transaction_rejection(&resp) {
+        return Err(rejection);
+    }
+    let signature = extract_result(&resp)?.as_str().unwrap_or_default().to_string();
+
+    let mut status = Value::Null;
+    let mut timed_out = false;
+    if let Some(commitment) = confirm {
+        let deadline = Instant::now() + *TX_CONFIRM_TIMEOUT;
+        loop {
+            if let Ok(statuses) = get_signature_statuses(std::slice::from_ref(&signature)).await {
+                status = statuses[0].clone();
+            }
+            let landed = status["confirmation_status"].as_str().is_some_and(|seen| commitment.reached_by(seen));
+            if landed || !status["err"].is_null() {
+                break;
+            }
+            if Instant::now() + TX_CONFIRM_POLL_INTERVAL > deadline {
+                timed_out = true;
+                break;
+            }
+            tokio::time::sleep(TX_CONFIRM_POLL_INTERVAL).await;
+        }
+    }
+
+    Ok(serde_json::json!({
+        "signature": signature,
+        "commitment": confirm.map(Commitment::as_str),
+        "confirmation_status": status["confirmation_status"],
+        "slot": status["slot"],
+        "err": status["err"],
+        "timed_out": timed_out
+    }))
+}
+
+// Ready once the token map has loaded and the RPC reports healthy within READINESS_TIMEOUT_MS.
+// Without BACKGROUND_REFRESH nothing else loads the map until the first /tokens request, so the
+// probe triggers the load.
+pub(crate) async fn get_readiness() -> (bool, Value) {
+    let (token_map, health) = tokio::join!(
+        async {
+            if !TOKEN_MAP_LOADED.load(Ordering::Relaxed) && !*BACKGROUND_REFRESH {
+                let _ = get_token_map().await;
+            }
+            TOKEN_MAP_LOADED.load(Ordering::Relaxed)
+        },
+        tokio::time::timeout(Duration::from_millis(*READINESS_TIMEOUT_MS), rpc_call("getHealth", serde_json::json!([])))
+    );
+    let rpc_error = match health {
+        Err(_) => Some(format!("no answer to getHealth within {}ms", *READINESS_TIMEOUT_MS)),
+        Ok(Err(e)) => Some(e.to_string()),
+        Ok(Ok(resp)) => match extract_result(&resp) {
+            Ok(result) if result == "ok" => None,
+            Ok(result) => Some(format!("getHealth answered {}", result)),
+            Err(e) => Some(e.to_string()),
+        },
+    };
+    let rpc_healthy = rpc_error.is_none();
+    let ready = token_map && rpc_healthy;
+    let mut body = serde_json::json!({ "ready": ready, "token_map_loaded": token_map, "rpc_healthy": rpc_healthy });
+    if let Some(error) = rpc_error {
+        body["rpc_error"] = error.into();
+    }
+    (ready, body)
+}
+
+// Each probe is independent: a failed call nulls its field and adds a warning instead of
+// failing the whole status.
+pub(crate) async fn get_node_status() -> Value {
+    let (identity, health, slot, version) = tokio::join!(
+        rpc_call("getIdentity", serde_json::json!([])),
+        rpc_call("getHealth", serde_json::json!([])),
+        rpc_call("getSlot", serde_json::json!([])),
+        rpc_call("getVersion", serde_json::json!([]))
+    );
+
+    let mut warnings = Vec::new();
+    let mut field = |name: &str, resp: Result<Value, ApiError>, extract: fn(&Value) -> Value| {
+        match resp.and_then(|resp| extract_result(&resp).map(extract)) {
+            Ok(value) => value,
+            Err(e) => {
+                warnings.push(format!("{}: {}", name, e));
+                Value::Null
+            }
+        }
+    };
+
+    let identity = field("identity", identity, |r| r["identity"].clone());
+    let slot = field("slot", slot, |r| r.clone());
+    let version = field("version", version, |r| r["solana-core"].clone());
+    // An unhealthy node answers getHealth with an RPC error, which is a result rather than a failure.
+    let healthy = match health {
+        Ok(resp) => Value::Bool(resp["result"] == "ok"),
+        Err(e) => {
+            warnings.push(format!("healthy: {}", e));
+            Value::Null
+        }
+    };
+
+    serde_json::json!({
+        "identity": identity,
+        "healthy": healthy,
+        "slot": slot,
+        "version": version,
+        "rpc_endpoints": rpc_endpoint_status(),
+        "warnings": warnings
+    })
+}
+
+pub(crate) async fn get_account(pubkey: &str, slice: Option<(usize, usize)>) -> Result<Value, ApiError> {
+    let mut config = serde_json::json!({ "encoding": "base64" });
+    if let Some((offset, length)) = slice {
+        config["dataSlice"] = serde_json::json!({ "offset": offset, "length": length });
+    }
+
+    let resp = rpc_call("getAccountInfo", serde_json::json!([pubkey, config])).await?;
+    let account = extract_value(&resp).map_err(|e| match e {
+        ApiError::NotFound(_) => ApiError::NotFound("Account not found".to_string()),
+        e => e,
+    })?;
+
+    let mut details = account_details(pubkey, account);
+    if let Some((offset, length)) = slice {
+        details["data_slice"] = serde_json::json!({ "offset": offset, "length": length });
+    }
+    Ok(details)
+}
+
+// Accounts in request order; ones that don't exist are null. Without `include_data` a zero-length
+// data slice is requested so the node doesn't send the account bodies at all.
+pub(crate) async fn get_multiple_accounts(pubkeys: &[String], include_data: bool) -> Result<Value, ApiError> {
+    let mut config = serde_json::json!({ "encoding": "base64" });
+    if !include_data {
+        config["dataSlice"] = serde_json::json!({ "offset": 0, "length": 0 });
+    }
+    let resp = rpc_call("getMultipleAccounts", serde_json::json!([pubkeys, config])).await?;
+    let accounts = extract_value(&resp)?.as_array().cloned().unwrap_or_default();
+    let details: Vec<Value> = pubkeys
+        .iter()
+        .zip(accounts.iter().chain(std::iter::repeat(&Value::Null)))
+        .map(|(pubkey, account)| {
+            if account.is_null() {
+                return Value::Null;
+            }
+            let mut details = account_details(pubkey, account);
+            if !include_data && let Some(fields) = details.as_object_mut() {
+                fields.remove("data");
+            }
+            details
+        })
+        .collect();
+    Ok(Value::Array(details))
+}
+
+pub(crate) fn account_details(pubkey: &str, account: &Value) -> Value {
+    let mut details = serde_json::json!({
+        "pubkey": pubkey,
+        "lamports": account["lamports"],
+        "owner": account["owner"],
+        "executable": account["executable"],
+        "rent_epoch": account["rentEpoch"],
+        "data": account["data"][0]
+    });
+    if let Some(space) = account["space"].as_u64() {
+        details["space"] = space.into();
+    }
+    // Executable accounts are programs; otherwise an on-curve key has a private key behind it
+    // and an off-curve one can only be signed for by a program (a PDA).
+    let pubkey_type = if account["executable"].as_bool().unwrap_or(false) {
+        "program"
+    } else if parse_pubkey(pubkey).is_some_and(|key| is_on_curve(&key)) {
+        "wallet"
+    } else {
+        "pda"
+    };
+    details["pubkey_type"] = pubkey_type.into();
+    details
+}
+
+// Classifies an explorer search string: digits are a slot, 64 base58 bytes a transaction
+// signature, 32 bytes (or a .sol domain) an address.
+// Lookups that come back not-found are reported as a null result of the detected type.
+pub(crate) async fn search(input: &str) -> Result<Value, ApiError> {
+    let input = input.trim();
+
+    if input.chars().all(|c| c.is_ascii_digit())
+        && let Ok(slot) = input.parse::<u64>()
+    {
+        let block = found_or_null(get_block(slot, false).await)?;
+        return Ok(serde_json::json!({ "type": "slot", "result": block }));
+    }
+
+    if parse_signature(input).is_some() {
+        let statuses = get_signature_statuses(&[input.to_string()]).await?;
+        return Ok(serde_json::json!({ "type": "signature", "result": statuses[0] }));
+    }
+
+    let address = if input.ends_with(".sol") {
+        match resolve_sns(input).await {
+            Ok(owner) => Some(owner),
+            Err(ApiError::NotFound(_)) => None,
+            Err(e) => return Err(e),
+        }
+    } else {
+        parse_pubkey(input).map(|_| input.to_string())
+    };
+    let Some(address) = address else {
+        return Ok(serde_json::json!({ "type": "unknown", "result": null }));
+    };
+
+    let mut result = serde_json::json!({ "address": address });
+    if input != address {
+        result["domain"] = Value::String(input.to_string());
+    }
+    result["account_type"] = match found_or_null(get_account(&address, Some((0, 0))).await)? {
+        Value::Null => Value::Null,
+        account => {
+            let owner = account["owner"].as_str().unwrap_or_default();
+            if account["executable"] == true {
+                "program".into()
+            } else if owner == SYSTEM_PROGRAM_ID {
+                "system_account".into()
+            } else if owner == TOKEN_PROGRAM_ID || owner == TOKEN_2022_PROGRAM_ID {
+                let parsed = rpc_call("getAccountInfo", serde_json::json!([address, { "encoding": "jsonParsed" }])).await?;
+                match extract_value(&parsed)?["data"]["parsed"]["type"].as_str() {
+                    Some("mint") => {
+                        if let Some(metadata) = token_metadata(std::slice::from_ref(&address)).await?.get(&address) {
+                            result["token"] = serde_json::json!({
+                                "symbol": metadata["symbol"],
+                                "name": metadata["name"],
+                                "logoURI": metadata["logoURI"]
+                            });
+                        }
+                        "token_mint".into()
+                    }
+                    Some("account") => "token_account".into(),
+                    _ => "account".into(),
+                }
+            } else {
+                "account".into()
+            }
+        }
+    };
+    Ok(serde_json::json!({ "type": "address", "result": result }))
+}
+
+pub(crate) fn found_or_null(result: Result<Value, ApiError>) -> Result<Value, ApiError> {
+    match result {
+        Err(ApiError::NotFound(_)) => Ok(Value::Null),
+        other => other,
+    }
+}
+
+pub(crate) async fn get_transaction(signature: &str) -> Result<Value, ApiError> {
+    let params = serde_json::json!([signature, { "encoding": "jsonParsed", "maxSupportedTransactionVersion": 0 }]);
+    let resp = rpc_call("getTransaction", params).await?;
+    Ok(extract_result(&resp)?.clone())
+}
+
+// Signatures newer than `from_slot`, newest first, starting below the `before` cursor if given.
+// At most `max` are returned; the flag is true when more were left unscanned.
+pub(crate) async fn signatures_since(
+    address: &str,
+    from_slot: u64,
+    max: usize,
+    before: Option<String>,
+) -> Result<(Vec<Value>, bool), ApiError> {
+    signatures_until(address, |signature| signature["slot"].as_u64().unwrap_or(0) < from_slot, max, before).await
+}
+
+// Like signatures_since, but the scan stops at the first signature `past_window` says is too old.
+pub(crate) async fn signatures_until(
+    address: &str,
+    past_window: impl Fn(&Value) -> bool,
+    max: usize,
+    mut before: Option<String>,
+) -> Result<(Vec<Value>, bool), ApiError> {
+    let mut signatures = Vec::new();
+    loop {
+        // One past the cap is enough to tell whether the scan was cut short.
+        let limit = SIGNATURES_PAGE_LIMIT.min(max + 1 - signatures.len());
+        let mut config = serde_json::json!({ "limit": limit });
+        if let Some(before) = &before {
+            config["before"] = Value::String(before.clone());
+        }
+        let resp = rpc_call("getSignaturesForAddress", serde_json::json!([address, config])).await?;
+        let page = extract_result(&resp)?.as_array().cloned().unwrap_or_default();
+        let page_len = page.len();
+        for signature in page {
+            if past_window(&signature) {
+                return Ok((signatures, false));
+            }
+            if signatures.len() == max {
+                return Ok((signatures, true));
+            }
+            before = signature["signature"].as_str().map(str::to_string);
+            signatures.push(signature);
+        }
+        if page_len < limit {
+            return Ok((signatures, false));
+        }
+    }
+}
+
+// System and SPL token transfers in a jsonParsed transaction, inner instructions included. Token
+// transfers name token accounts; their mints and owners come from the transaction's token
+// balances, which also give the decimals a plain `transfer` instruction leaves out.
+pub(crate) fn parsed_transfers(transaction: &Value) -> Vec<Value> {
+    let keys = transaction["transaction"]["message"]["accountKeys"].as_array().cloned().unwrap_or_default();
+    let mut token_accounts: HashMap<String, (String, Option<String>, u32)> = HashMap::new();
+    for key in ["preTokenBalances", "postTokenBalances"] {
+        for balance in transaction["meta"][key].as_array().into_iter().flatten() {
+            let account = balance["accountIndex"].as_u64().and_then(|index| keys.get(index as usize)).and_then(|key| key["pubkey"].as_str());
+            let (Some(account), Some(mint)) = (account, balance["mint"].as_str()) else {
+                continue;
+            };
+            let owner = balance["owner"].as_str().map(str::to_string);
+            let decimals = balance["uiTokenAmount"]["decimals"].as_u64().and_then(valid_decimals).map_or(0, u32::from);
+            token_accounts.insert(account.to_string(), (mint.to_string(), owner, decimals));
+        }
+    }
+
+    let inner = transaction["meta"]["innerInstructions"].as_array().into_iter().flatten().flat_map(|set| set["instructions"].as_array().into_iter().flatten());
+    let instructions = transaction["transaction"]["message"]["instructions"].as_array().into_iter().flatten().chain(inner);
+    let mut transfers = Vec::new();
+    for instruction in instructions {
+        let (kind, info) = (instruction["parsed"]["type"].as_str(), &instruction["parsed"]["info"]);
+        match (instruction["program"].as_str(), kind) {
+            (Some("system"), Some("transfer")) => {
+                let Some(lamports) = info["lamports"].as_u64() else {
+                    continue;
+                };
+                transfers.push(serde_json::json!({
+                    "source": info["source"],
+                    "destination": info["destination"],
+                    "mint": null,
+                    "amount": lamports_to_sol(lamports),
+                    "ui_amount_string": lamports_to_sol_string(lamports)
+                }));
+            }
+            (Some("spl-token"), Some("transfer" | "transferChecked")) => {
+                let (Some(source), Some(destination)) = (info["source"].as_str(), info["destination"].as_str()) else {
+                    continue;
+                };
+                let known = token_accounts.get(source).or_else(|| token_accounts.get(destination));
+                let mint = info["mint"].as_str().map(str::to_string).or_else(|| known.map(|(mint, ..)| mint.clone()));
+                let ui_amount = match info["tokenAmount"]["uiAmountString"].as_str() {
+                    Some(ui) => Some(ui.to_string()),
+                    None => info["amount"]
+                        .as_str()
+                        .and_then(|raw| raw.parse::<u128>().ok())
+                        .zip(known)
+                        .map(|(raw, (_, _, decimals))| raw_to_ui_amount(raw, *decimals)),
+                };
+                let owner = |account: &str| token_accounts.get(account).and_then(|(_, owner, _)| owner.clone());
+                transfers.push(serde_json::json!({
+                    "source": source,
+                    "destination": destination,
+                    "source_owner": owner(source),
+                    "destination_owner": owner(destination),
+                    "mint": mint,
+                    "amount": ui_amount.as_deref().and_then(|ui| ui.parse::<f64>().ok()),
+                    "ui_amount_string": ui_amount
+                }));
+            }
+            _ => {}
+        }
+    }
+    transfers
+}
+
+// One page of the wallet's history, newest first. Transactions the node no longer has keep the
+// signature's own slot, time and status but have null fee and transfers.
+pub(crate) async fn get_transactions(wallet: &str, limit: usize, before: Option<String>) -> Result<Value, ApiError> {
+    let (signatures, more) = signatures_since(wallet, 0, limit, before).await?;
+    let signature_ids: Vec<String> = signatures
+        .iter()
+        .map(|s| s["signature"].as_str().unwrap_or_default().to_string())
+        .collect();
+    let transactions: Vec<Result<Value, ApiError>> = futures::stream::iter(signature_ids)
+        .map(|signature| async move { get_transaction(&signature).await })
+        .buffered(TRANSACTION_FETCH_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut page = Vec::new();
+    for (signature, transaction) in signatures.iter().zip(transactions) {
+        let transaction = match transaction {
+            Ok(transaction) => transaction,
+            Err(ApiError::NotFound(_)) => Value::Null,
+            Err(e) => return Err(e),
+        };
+        let transfers = if transaction.is_null() { Value::Null } else { Value::Array(parsed_transfers(&transaction)) };
+        page.push(serde_json::json!({
+            "signature": signature["signature"],
+            "slot": signature["slot"],
+            "block_time": signature["blockTime"],
+            "success": signature["err"].is_null(),
+            "fee": transaction["meta"]["fee"],
+            "transfers": transfers
+        }));
+    }
+    let next_cursor = signatures.last().filter(|_| more).map_or(Value::Null, |oldest| oldest["signature"].clone());
+    Ok(serde_json::json!({ "wallet": wallet, "transactions": page, "next_cursor": next_cursor }))
+}
+
+// The wallet's lamports before and after a jsonParsed transaction, read from the meta balances
+// at the wallet's position among the account keys. None when the wallet isn't one of them.
+pub(crate) fn lamport_balances(transaction: &Value, wallet: &str) -> Option<(u64, u64)> {
+    let keys = transaction["transaction"]["message"]["accountKeys"].as_array()?;
+    let index = keys.iter().position(|key| key["pubkey"] == wallet)?;
+    let pre = transaction["meta"]["preBalances"][index].as_u64()?;
+    let post = transaction["meta"]["postBalances"][index].as_u64()?;
+    Some((pre, post))
+}
+
+// The wallet's SOL balance over the last `days` days as a series for charting, oldest first:
+// where it stood when the window opened, after each transaction that changed it, and now.
+// Every point comes from a transaction's own pre/post balances, so a transaction the node no
+// longer has costs only its point and makes the opening balance approximate if it was the oldest.
+pub(crate) async fn get_balance_history(wallet: &str, days: u64) -> Result<Value, ApiError> {
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let from_time = now.saturating_sub(days * 86_400);
+    let too_old = |signature: &Value| signature["blockTime"].as_u64().is_some_and(|time| time < from_time);
+    let (current, history) = tokio::join!(
+        balance_at_slot(wallet),
+        signatures_until(wallet, too_old, *MAX_HISTORY_SIGNATURES, None)
+    );
+    let (lamports_now, slot_now) = current?;
+    let (signatures, truncated) = history?;
+
+    let signature_ids: Vec<String> = signatures
+        .iter()
+        .filter_map(|s| s["signature"].as_str().map(str::to_string))
+        .collect();
+    let transactions: Vec<Result<Value, ApiError>> = futures::stream::iter(signature_ids)
+        .map(|signature| async move { get_transaction(&signature).await })
+        .buffered(TRANSACTION_FETCH_CONCURRENCY)
+        .collect()
+        .await;
+
+    // Walked newest first, so the opening balance ends up as the oldest transaction's pre-balance.
+    let mut points = Vec::new();
+    let mut opening_lamports = lamports_now;
+    let mut oldest_missing = false;
+    for (signature, transaction) in signatures.iter().zip(transactions) {
+        let transaction = match transaction {
+            Ok(transaction) => transaction,
+            Err(ApiError::NotFound(_)) => {
+                oldest_missing = true;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        oldest_missing = false;
+        let Some((pre, post)) = lamport_balances(&transaction, wallet) else {
+            continue;
+        };
+        opening_lamports = pre;
+        if pre != post {
+            let mut point = sol_amount(post);
+            point["time"] = signature["blockTime"].clone();
+            point["slot"] = signature["slot"].clone();
+            point["signature"] = signature["signature"].clone();
+            points.push(point);
+        }
+    }
+    points.reverse();
+
+    // A truncated scan can only vouch for the balance from its oldest transaction onward.
+    let opening_time = match signatures.last() {
+        Some(oldest) if truncated => oldest["blockTime"].as_u64().unwrap_or(from_time),
+        _ => from_time,
+    };
+    let mut opening = sol_amount(opening_lamports);
+    opening["time"] = serde_json::json!(opening_time);
+    opening["slot"] = Value::Null;
+    opening["signature"] = Value::Null;
+    let mut closing = sol_amount(lamports_now);
+    closing["time"] = serde_json::json!(now);
+    closing["slot"] = serde_json::json!(slot_now);
+    closing["signature"] = Value::Null;
+    points.insert(0, opening);
+    points.push(closing);
+
+    Ok(serde_json::json!({
+        "wallet": wallet,
+        "days": days,
+        "from_time": from_time,
+        "earliest_time": opening_time,
+        "approximate": truncated || oldest_missing,
+        "transactions_scanned": signatures.len(),
+        "truncated": truncated,
+        "max_signatures": *MAX_HISTORY_SIGNATURES,
+        "points": points
+    }))
+}
+
+// Net change a transaction made to each mint held in token accounts owned by `wallet`.
+// Accounts missing from one side (opened or closed in the transaction) count as zero there.
+pub(crate) fn token_balance_deltas(transaction: &Value, wallet: &str) -> HashMap<String, f64> {
+    let mut balances: HashMap<(u64, String), (f64, f64)> = HashMap::new();
+    for (key, is_post) in [("preTokenBalances", false), ("postTokenBalances", true)] {
+        for balance in transaction["meta"][key].as_array().into_iter().flatten() {
+            if balance["owner"] != wallet {
+                continue;
+            }
+            let (Some(index), Some(mint)) = (balance["accountIndex"].as_u64(), balance["mint"].as_str()) else {
+                continue;
+            };
+            let amount = balance["uiTokenAmount"]["uiAmountString"]
+                .as_str()
+                .and_then(|a| a.parse::<f64>().ok())
+                .unwrap_or(0.0);
+            let entry = balances.entry((index, mint.to_string())).or_default();
+            if is_post {
+                entry.1 = amount;
+            } else {
+                entry.0 = amount;
+            }
+        }
+    }
+
+    let mut deltas = HashMap::new();
+    for ((_, mint), (pre, post)) in balances {
+        *deltas.entry(mint).or_insert(0.0) += post - pre;
+    }
+    deltas
+}
+
+// Replays the wallet's transactions back to `from_slot` to estimate what it held then. Only
+// transactions that reference the wallet itself are seen, so transfers into existing token
+// accounts that don't mention the owner are missed; the result is an approximation.
+// With a `before` cursor only the transactions older than it are replayed, so each change
+// carries just the delta for that stretch.
+pub(crate) async fn get_holdings_change(wallet: &str, from_slot: u64, before: Option<String>) -> Result<Value, ApiError> {
+    let continuation = before.is_some();
+    let (current, history) = tokio::join!(
+        get_spl_tokens(wallet),
+        signatures_since(wallet, from_slot, *MAX_HISTORY_SIGNATURES, before)
+    );
+    let (signatures, truncated) = history?;
+
+    let mut amounts_now: HashMap<String, f64> = HashMap::new();
+    for token in current?.0.as_array().into_iter().flatten() {
+        if let (Some(mint), Some(amount)) = (token["mint"].as_str(), token["amount"].as_f64()) {
+            *amounts_now.entry(mint.to_string()).or_insert(0.0) += amount;
+        }
+    }
+
+    let signature_ids: Vec<String> = signatures
+        .iter()
+        .filter_map(|s| s["signature"].as_str().map(str::to_string))
+        .collect();
+    let transactions: Vec<Result<Value, ApiError>> = futures::stream::iter(signature_ids)
+        .map(|signature| async move { get_transaction(&signature).await })
+        .buffered(TRANSACTION_FETCH_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut deltas: HashMap<String, f64> = HashMap::new();
+    let mut missing_transactions = 0;
+    for transaction in transactions {
+        match transaction {
+            Ok(transaction) => {
+                for (mint, delta) in token_balance_deltas(&transaction, wallet) {
+                    *deltas.entry(mint).or_insert(0.0) += delta;
+                }
+            }
+            Err(ApiError::NotFound(_)) => missing_transactions += 1,
+            Err(e) => return Err(e),
+        }
+    }
+
+    let mut mints: Vec<&String> = amounts_now.keys().chain(deltas.keys()).collect();
+    mints.sort();
+    mints.dedup();
+    let changes: Vec<Value> = mints
+        .into_iter()
+        .map(|mint| {
+            let amount_now = amounts_now.get(mint).copied().unwrap_or(0.0);
+            let delta = deltas.get(mint).copied().unwrap_or(0.0);
+            if continuation {
+                return serde_json::json!({ "mint": mint, "delta": delta });
+            }
+            serde_json::json!({
+                "mint": mint,
+                "amount_now": amount_now,
+                "amount_then": amount_now - delta,
+                "delta": delta
+            })
+        })
+        .collect();
+
+    let next_cursor = signatures.last().filter(|_| truncated).map(|oldest| oldest["signature"].clone());
+    let earliest_slot = match signatures.last() {
+        Some(oldest) if truncated => oldest["slot"].as_u64().unwrap_or(from_slot),
+        _ => from_slot,
+    };
+
+    Ok(serde_json::json!({
+        "wallet": wallet,
+        "from_slot": from_slot,
+        "earliest_slot": earliest_slot,
+        "approximate": truncated || missing_transactions > 0,
+        "transactions_scanned": signatures.len() - missing_transactions,
+        "truncated": truncated,
+        "next_cursor": next_cursor,
+        "max_signatures": *MAX_HISTORY_SIGNATURES,
+        "changes": changes
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lamports_to_sol_string_is_exact() {
+        assert_eq!(lamports_to_sol_string(0), "0");
+        assert_eq!(lamports_to_sol_string(1), "0.000000001");
+        assert_eq!(lamports_to_sol_string(2_500_000_000), "2.5");
+        assert_eq!(lamports_to_sol_string(LAMPORTS_PER_SOL), "1");
+        assert_eq!(lamports_to_sol_string(u64::MAX), "18446744073.709551615");
+    }
+}
@@ -0,0 +1,236 @@
+//! Clusters served side by side: each has its own RPC endpoints, token list, metadata sources
+//! and caches, and a request picks one by path prefix or X-Cluster header.
+
+use crate::*;
+
+// CLUSTERS_FILE names a TOML file listing the clusters to serve; the first one is the default.
+// Unset, there is a single cluster configured from the environment (RPC_URLS and friends).
+pub(crate) static CLUSTERS_FILE: Lazy<Option<String>> = Lazy::new(|| std::env::var("CLUSTERS_FILE").ok());
+pub(crate) static CLUSTERS: once_cell::sync::OnceCell<Vec<Cluster>> = once_cell::sync::OnceCell::new();
+
+tokio::task_local! {
+    // The cluster a request (or a background job run for one cluster) talks to.
+    pub(crate) static REQUEST_CLUSTER: &'static Cluster;
+}
+
+pub(crate) struct Cluster {
+    pub(crate) name: String,
+    // Position in CLUSTERS, which picks the cluster's value out of every PerCluster.
+    pub(crate) index: usize,
+    pub(crate) endpoints: Vec<RpcEndpoint>,
+    pub(crate) next_endpoint: AtomicUsize,
+    // WebSocket endpoint for subscriptions. Unset, it is derived from an RPC endpoint.
+    pub(crate) ws_url: Option<String>,
+    pub(crate) token_list_url: String,
+    pub(crate) metadata_sources: MetadataSources,
+    // Prefix of the cluster's shared cache keys. Empty for the environment-configured cluster,
+    // so a single-cluster deployment keeps the keys it always had.
+    pub(crate) cache_namespace: String,
+    pub(crate) circuit: CircuitBreaker,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ClustersConfig {
+    pub(crate) clusters: Vec<ClusterConfig>,
+}
+
+// One [[clusters]] table. Only name and rpc_urls are required; the token list defaults to
+// TOKEN_LIST_URL, the metadata sources to TOKEN_METADATA_SOURCES and the namespace to the name.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ClusterConfig {
+    pub(crate) name: String,
+    pub(crate) rpc_urls: Vec<String>,
+    pub(crate) ws_url: Option<String>,
+    pub(crate) token_list_url: Option<String>,
+    pub(crate) metadata_sources: Option<Vec<String>>,
+    pub(crate) cache_namespace: Option<String>,
+}
+
+// Reads CLUSTERS_FILE once. run() calls this before serving so a bad file stops startup with
+// its error; anything reaching clusters() first panics with the same message instead.
+pub(crate) fn init_clusters() -> Result<&'static [Cluster], String> {
+    CLUSTERS.get_or_try_init(load_clusters).map(Vec::as_slice)
+}
+
+pub(crate) fn clusters() -> &'static [Cluster] {
+    init_clusters().unwrap_or_else(|e| panic!("{}", e))
+}
+
+pub(crate) fn load_clusters() -> Result<Vec<Cluster>, String> {
+    let Some(path) = CLUSTERS_FILE.as_ref() else {
+        return Ok(vec![env_cluster()]);
+    };
+    let raw = std::fs::read_to_string(path).map_err(|e| format!("cannot read CLUSTERS_FILE {}: {}", path, e))?;
+    let config: ClustersConfig = toml::from_str(&raw).map_err(|e| format!("invalid CLUSTERS_FILE {}: {}", path, e))?;
+    if config.clusters.is_empty() {
+        return Err(format!("CLUSTERS_FILE {} lists no clusters", path));
+    }
+    let mut clusters: Vec<Cluster> = Vec::new();
+    for (index, settings) in config.clusters.into_iter().enumerate() {
+        let cluster = file_cluster(index, settings).map_err(|e| format!("CLUSTERS_FILE {}: {}", path, e))?;
+        if let Some(other) = clusters.iter().find(|other| other.name == cluster.name || other.cache_namespace == cluster.cache_namespace) {
+            return Err(format!("CLUSTERS_FILE {}: clusters {} and {} share a name or cache namespace", path, other.name, cluster.name));
+        }
+        clusters.push(cluster);
+    }
+    Ok(clusters)
+}
+
+// The deployment as configured before clusters existed. RPC_URLS is a comma-separated list of
+// endpoints to spread requests over; unset means RPC_URL alone. Only hosts are logged or reported,
+// since provider URLs often embed an API key.
+pub(crate) fn env_cluster() -> Cluster {
+    let setting = std::env::var("RPC_URLS").unwrap_or_default();
+    let mut endpoints: Vec<RpcEndpoint> = setting
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .filter_map(|url| match reqwest::Url::parse(url) {
+            Ok(_) => Some(RpcEndpoint::new(url)),
+            Err(e) => {
+                tracing::warn!("Ignoring invalid RPC_URLS entry: {}", e);
+                None
+            }
+        })
+        .collect();
+    if endpoints.is_empty() {
+        endpoints.push(RpcEndpoint::new(RPC_URL));
+    }
+    Cluster {
+        name: "default".to_string(),
+        index: 0,
+        endpoints,
+        next_endpoint: AtomicUsize::new(0),
+        ws_url: std::env::var("RPC_WS_URL").ok(),
+        token_list_url: TOKEN_LIST_URL.to_string(),
+        metadata_sources: MetadataSources::from_env(),
+        cache_namespace: String::new(),
+        circuit: CircuitBreaker::default(),
+    }
+}
+
+// Unlike the environment settings, which skip what they can't use, a file entry that can't be
+// used is an error: serving devnet requests from mainnet would be worse than not starting.
+pub(crate) fn file_cluster(index: usize, settings: ClusterConfig) -> Result<Cluster, String> {
+    let name = settings.name;
+    if name.is_empty() || !name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-') {
+        return Err(format!("cluster name {:?} must be lowercase letters, digits and dashes", name));
+    }
+    if route_segments().contains(&name.as_str()) {
+        return Err(format!("cluster name {:?} is also a route", name));
+    }
+    if settings.rpc_urls.is_empty() {
+        return Err(format!("cluster {} has no rpc_urls", name));
+    }
+    let mut endpoints = Vec::new();
+    for url in &settings.rpc_urls {
+        reqwest::Url::parse(url).map_err(|e| format!("cluster {} has an invalid RPC URL: {}", name, e))?;
+        endpoints.push(RpcEndpoint::new(url));
+    }
+    let metadata_sources = match settings.metadata_sources {
+        Some(sources) => {
+            let mut providers = Vec::new();
+            for source in &sources {
+                providers.push(MetadataProvider::named(source).ok_or_else(|| format!("cluster {} has an unknown metadata source {:?}", name, source))?);
+            }
+            MetadataSources::new(providers)
+        }
+        None => MetadataSources::from_env(),
+    };
+    Ok(Cluster {
+        cache_namespace: settings.cache_namespace.unwrap_or_else(|| name.clone()),
+        name,
+        index,
+        endpoints,
+        next_endpoint: AtomicUsize::new(0),
+        ws_url: settings.ws_url,
+        token_list_url: settings.token_list_url.unwrap_or_else(|| TOKEN_LIST_URL.to_string()),
+        metadata_sources,
+        circuit: CircuitBreaker::default(),
+    })
+}
+
+// First path segments of the documented routes, which a cluster prefix must not shadow.
+pub(crate) fn route_segments() -> Vec<&'static str> {
+    let mut segments: Vec<&str> = routes::OPENAPI["paths"]
+        .as_object()
+        .into_iter()
+        .flat_map(|paths| paths.keys())
+        .filter_map(|path| path.trim_start_matches('/').split('/').next())
+        .collect();
+    segments.dedup();
+    segments
+}
+
+// The current request's cluster; the default one outside a request.
+pub(crate) fn cluster() -> &'static Cluster {
+    REQUEST_CLUSTER.try_with(|cluster| *cluster).unwrap_or_else(|_| &clusters()[0])
+}
+
+pub(crate) fn find_cluster(name: &str) -> Option<&'static Cluster> {
+    clusters().iter().find(|cluster| cluster.name == name)
+}
+
+pub(crate) async fn in_cluster<F: std::future::Future>(cluster: &'static Cluster, future: F) -> F::Output {
+    REQUEST_CLUSTER.scope(cluster, future).await
+}
+
+// The cluster a request is for: a leading /{cluster} path segment, which is stripped so the
+// routes see their usual paths, or else the X-Cluster header. Neither means the default.
+pub(crate) fn request_cluster(request: &mut hyper::Request<hyper::Body>) -> Result<&'static Cluster, ApiError> {
+    let path = request.uri().path();
+    let (first, rest) = path.trim_start_matches('/').split_once('/').map_or((path.trim_start_matches('/'), ""), |(first, rest)| (first, rest));
+    if let Some(cluster) = find_cluster(first) {
+        let rest = format!("/{}", rest);
+        let path_and_query = match request.uri().query() {
+            Some(query) => format!("{}?{}", rest, query),
+            None => rest,
+        };
+        let mut parts = request.uri().clone().into_parts();
+        parts.path_and_query = path_and_query.parse().ok();
+        if let Ok(uri) = warp::http::Uri::from_parts(parts) {
+            *request.uri_mut() = uri;
+        }
+        return Ok(cluster);
+    }
+    match request.headers().get("x-cluster") {
+        None => Ok(&clusters()[0]),
+        Some(value) => value.to_str().ok().and_then(find_cluster).ok_or_else(|| {
+            let names: Vec<&str> = clusters().iter().map(|cluster| cluster.name.as_str()).collect();
+            ApiError::InvalidParam { field: "X-Cluster".to_string(), message: format!("expected one of {}", names.join(", ")) }
+        }),
+    }
+}
+
+// The key a shared cache entry is stored under for the current cluster.
+#[cfg(feature = "redis")]
+pub(crate) fn cluster_cache_key(key: &str) -> String {
+    match cluster().cache_namespace.as_str() {
+        "" => key.to_string(),
+        namespace => format!("{}:{}", namespace, key),
+    }
+}
+
+// A value kept per cluster, such as a cache. It derefs to the current cluster's, so a static
+// that used to hold one value for the process is used the same way.
+pub(crate) struct PerCluster<T> {
+    init: fn() -> T,
+    values: once_cell::sync::OnceCell<Vec<T>>,
+}
+
+impl<T> PerCluster<T> {
+    pub(crate) const fn new(init: fn() -> T) -> Self {
+        PerCluster { init, values: once_cell::sync::OnceCell::new() }
+    }
+}
+
+impl<T> std::ops::Deref for PerCluster<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        let values = self.values.get_or_init(|| clusters().iter().map(|_| (self.init)()).collect());
+        &values[cluster().index]
+    }
+}
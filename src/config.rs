@@ -0,0 +1,392 @@
+//! Constants, and settings read from the environment.
+
+use crate::*;
+
+pub(crate) const RPC_URL: &str = "https://api.mainnet-beta.solana.com";
+pub(crate) const TOKEN_LIST_URL: &str = "https://raw.githubusercontent.com/solana-labs/token-list/main/src/tokens/solana.tokenlist.json";
+pub(crate) const TOKEN_PROGRAM_ID: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+pub(crate) const TOKEN_2022_PROGRAM_ID: &str = "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb";
+pub(crate) const SYSTEM_PROGRAM_ID: &str = "11111111111111111111111111111111";
+pub(crate) const NAME_PROGRAM_ID: &str = "namesLPneVptA9Z5rqUDD9tMTWEJwofgaYwp8cawRkX";
+pub(crate) const SOL_TLD_AUTHORITY: &str = "58PwtjSDuFHuUkYjH9BYnnQKHfwo9reZhC2zMJv9JPkx";
+pub(crate) const NAME_HASH_PREFIX: &str = "SPL Name Service";
+// Reverse lookup accounts map a domain's name account back to its text.
+pub(crate) const REVERSE_LOOKUP_CLASS: &str = "33m47vH6Eav6jr5Ry86XjhRft2jRBLDnDgPSHoquXi2Z";
+// Holds each wallet's "favourite" (primary) domain.
+pub(crate) const NAME_OFFERS_PROGRAM_ID: &str = "85iDfUvr3HJyLM2zcq5BXSiDvUWfw6cSE1FfNBo8Ap29";
+pub(crate) const METADATA_PROGRAM_ID: &str = "metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s";
+pub(crate) const STAKE_PROGRAM_ID: &str = "Stake11111111111111111111111111111111111111";
+// Wrapped SOL, which is the mint price sources quote SOL under.
+pub(crate) const WRAPPED_SOL_MINT: &str = "So11111111111111111111111111111111111111112";
+// Stake accounts keep the withdraw authority after the 4-byte state tag, the 8-byte rent reserve
+// and the 32-byte staker.
+pub(crate) const STAKE_WITHDRAWER_OFFSET: usize = 44;
+// First byte of a Metaplex metadata account (Key::MetadataV1).
+pub(crate) const METADATA_V1_KEY: u8 = 4;
+// Bumped only for breaking changes to response shapes; clients pin theirs with ?v=.
+pub(crate) const API_VERSION: u32 = 1;
+pub(crate) const LAMPORTS_PER_SOL: u64 = 1_000_000_000;
+pub(crate) const MAX_BODY_BYTES: u64 = 64 * 1024;
+// Smaller response bodies are sent uncompressed; the encoding overhead isn't worth it.
+pub(crate) const COMPRESSION_MIN_BYTES: usize = 1024;
+// Brotli's default of 11 is tuned for static assets and too slow per request; 5 still beats gzip.
+pub(crate) const BROTLI_QUALITY: u32 = 5;
+pub(crate) const MAX_SIGNATURE_STATUSES: usize = 256;
+pub(crate) const MAX_TOKEN_METADATA_MINTS: usize = 256;
+pub(crate) const MAX_AIRDROP_SOL: f64 = 2.0;
+pub(crate) const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+pub(crate) const MAX_WATCHES: usize = 1000;
+// Longest X-Request-Id taken from a caller; longer ones are replaced with a generated id.
+pub(crate) const MAX_REQUEST_ID_LENGTH: usize = 128;
+// getRecentPrioritizationFees accepts at most 128 account addresses.
+pub(crate) const MAX_FEE_ACCOUNTS: usize = 128;
+pub(crate) const FEE_PERCENTILES: [u64; 5] = [25, 50, 75, 90, 99];
+// The fee percentile /network/fees suggests for each priority tier; each is one of FEE_PERCENTILES.
+pub(crate) const FEE_TIERS: [(&str, u64); 3] = [("low", 25), ("medium", 50), ("high", 75)];
+// Performance samples /network/fees averages TPS over; the node takes one about every 60 seconds.
+pub(crate) const NETWORK_PERFORMANCE_SAMPLES: usize = 5;
+// Epochs during warmup start at this length and double until they reach slots_per_epoch.
+pub(crate) const MINIMUM_SLOTS_PER_EPOCH: u64 = 32;
+pub(crate) const LEADER_SCHEDULE_CACHE_EPOCHS: usize = 3;
+pub(crate) const WATCH_POLL_INTERVAL: Duration = Duration::from_secs(10);
+// How often a /ws/balance socket may re-read token balances while transactions keep arriving.
+pub(crate) const WS_TOKEN_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+pub(crate) const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+// How many token-tracking watches have their token accounts read at once per poll.
+pub(crate) const WATCH_TOKEN_CONCURRENCY: usize = 8;
+// Off-chain NFT JSON is a few KB; anything far bigger isn't metadata and isn't worth buffering.
+pub(crate) const MAX_OFFCHAIN_METADATA_BYTES: usize = 256 * 1024;
+pub(crate) const OFFCHAIN_METADATA_CONCURRENCY: usize = 8;
+// How many getAsset calls the DAS token metadata source makes at once.
+pub(crate) const DAS_METADATA_CONCURRENCY: usize = 8;
+// getAssetsByOwner page size, and how many pages /nfts will walk through at most.
+pub(crate) const DAS_PAGE_LIMIT: usize = 1000;
+pub(crate) const DAS_MAX_PAGES: usize = 10;
+pub(crate) const SIGNATURES_PAGE_LIMIT: usize = 1000;
+pub(crate) const TRANSACTION_FETCH_CONCURRENCY: usize = 8;
+// How many wallets of a /tokens/batch or /balance/batch request are fetched at once.
+pub(crate) const BATCH_CONCURRENCY: usize = 8;
+// Page size of /transactions: the default and the most a client may ask for.
+pub(crate) const DEFAULT_TRANSACTIONS_LIMIT: usize = 20;
+pub(crate) const MAX_TRANSACTIONS_LIMIT: usize = 100;
+// Most tokens one /tokens page may hold; without ?limit= every token is returned.
+pub(crate) const MAX_TOKENS_LIMIT: usize = 1000;
+// How often /tx/send checks on a transaction it is waiting for.
+pub(crate) const TX_CONFIRM_POLL_INTERVAL: Duration = Duration::from_millis(500);
+// JSON-RPC errors sendTransaction and simulateTransaction give for a bad transaction rather than
+// a failing node: preflight failure, signature verification failure, signature count mismatch
+// and undecodable params.
+pub(crate) const TRANSACTION_REJECTION_CODES: [i64; 4] = [-32002, -32003, -32013, -32602];
+// Window of /balance/{wallet}/history in days: the default and the most a client may ask for.
+pub(crate) const DEFAULT_BALANCE_HISTORY_DAYS: u64 = 30;
+pub(crate) const MAX_BALANCE_HISTORY_DAYS: u64 = 365;
+pub(crate) const JUPITER_PRICE_URL: &str = "https://lite-api.jup.ag/price/v3";
+pub(crate) const JUPITER_MAX_IDS: usize = 50;
+pub(crate) const COINGECKO_PRICE_URL: &str = "https://api.coingecko.com/api/v3/simple/token_price/solana";
+pub(crate) const COINGECKO_MAX_ADDRESSES: usize = 30;
+pub(crate) const PRICE_TTL: Duration = Duration::from_secs(60);
+// How long a domain's owner, or a wallet's primary domain, is reused before it is looked up again.
+pub(crate) const SNS_CACHE_TTL: Duration = Duration::from_secs(60);
+pub(crate) const RPC_RETRY_BASE_DELAY: Duration = Duration::from_millis(100);
+pub(crate) const RPC_RETRY_MAX_DELAY: Duration = Duration::from_secs(2);
+// A Retry-After longer than this is not waited out; the request fails instead of hanging.
+pub(crate) const RPC_RETRY_AFTER_MAX: Duration = Duration::from_secs(10);
+// Consecutive transient failures that take an endpoint out of rotation until it answers again.
+pub(crate) const RPC_UNHEALTHY_AFTER: u32 = 3;
+pub(crate) const RPC_HEALTH_INTERVAL: Duration = Duration::from_secs(15);
+// Methods that change state, so a retry could apply them twice. They are only retried when the
+// upstream can't have acted on the request: the connection failed, or it answered 429.
+pub(crate) const NON_IDEMPOTENT_RPC_METHODS: [&str; 2] = ["sendTransaction", "requestAirdrop"];
+pub(crate) const TOKEN_MAP_TTL: Duration = Duration::from_secs(3600);
+pub(crate) const VOTE_ACCOUNTS_TTL: Duration = Duration::from_secs(30);
+pub(crate) const EPOCH_INFO_TTL: Duration = Duration::from_secs(60);
+pub(crate) const ADDRESS_LABELS_TTL: Duration = Duration::from_secs(3600);
+pub(crate) const HOLDER_COUNT_TTL: Duration = Duration::from_secs(300);
+// Metadata read from chain or DAS, and mints no source knew, are looked up again after this.
+pub(crate) const TOKEN_METADATA_TTL: Duration = Duration::from_secs(3600);
+// SPL token accounts are 165 bytes: mint (32), owner (32), amount (u64 LE), ...
+pub(crate) const TOKEN_ACCOUNT_SIZE: u64 = 165;
+pub(crate) const TOKEN_ACCOUNT_AMOUNT_OFFSET: u64 = 64;
+pub(crate) const TOKEN_ACCOUNT_OWNER_OFFSET: u64 = 32;
+// getTokenLargestAccounts answers with at most this many accounts, so it is also /token/{mint}/holders'
+// default limit.
+pub(crate) const LARGEST_TOKEN_ACCOUNTS: usize = 20;
+// Most holders /token/{mint}/holders?scan=true lists.
+pub(crate) const MAX_TOKEN_HOLDERS_LIMIT: usize = 1000;
+// A u64 amount has at most 20 digits, so a mint claiming more decimals than this is malformed.
+pub(crate) const MAX_TOKEN_DECIMALS: u64 = 19;
+
+// In-flight request cap (default 1024). Requests beyond it get an immediate 503, except liveness
+// probes: a busy server is still alive.
+pub(crate) static MAX_CONCURRENT_REQUESTS: Lazy<usize> =
+    Lazy::new(|| std::env::var("MAX_CONCURRENT_REQUESTS").ok().and_then(|v| v.parse().ok()).unwrap_or(1024));
+pub(crate) const LIVENESS_PATH: &str = "/health";
+// How long /ready waits for the RPC's getHealth, retries included, before calling it unhealthy
+// (default 2000).
+pub(crate) static READINESS_TIMEOUT_MS: Lazy<u64> =
+    Lazy::new(|| std::env::var("READINESS_TIMEOUT_MS").ok().and_then(|v| v.parse().ok()).unwrap_or(2000));
+
+// Upper bound on signatures one history scan fetches across all getSignaturesForAddress pages.
+pub(crate) static MAX_HISTORY_SIGNATURES: Lazy<usize> =
+    Lazy::new(|| std::env::var("MAX_HISTORY_SIGNATURES").ok().and_then(|v| v.parse().ok()).unwrap_or(1000));
+// How often /sse/balance streams re-check the balance (default 5 seconds).
+pub(crate) static SSE_POLL_INTERVAL: Lazy<Duration> = Lazy::new(|| {
+    Duration::from_secs(std::env::var("SSE_POLL_SECS").ok().and_then(|v| v.parse().ok()).filter(|&secs| secs > 0).unwrap_or(5))
+});
+// How long a cached /tokens answer for a wallet with no token accounts is reused, when that is
+// longer than CACHE_TTL. Like CACHE_TTL it only applies while CACHE_TTL is set.
+pub(crate) static EMPTY_RESULT_TTL: Lazy<Duration> = Lazy::new(|| {
+    Duration::from_secs(std::env::var("EMPTY_RESULT_TTL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(60))
+});
+// How long /tokens and /balance answers are reused per wallet (default 0, off).
+pub(crate) static CACHE_TTL: Lazy<Duration> = Lazy::new(|| {
+    Duration::from_secs(std::env::var("CACHE_TTL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(0))
+});
+// How long past its TTL a holder count, or a /balance or /tokens answer of an endpoint listed in
+// STALE_WHILE_REVALIDATE_ENDPOINTS, may still be served while it is refreshed in the background
+// (default 0, off).
+pub(crate) static STALE_WHILE_REVALIDATE: Lazy<Duration> = Lazy::new(|| {
+    Duration::from_secs(std::env::var("STALE_WHILE_REVALIDATE_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(0))
+});
+// The response-cache endpoints ("balance", "tokens") that serve stale answers; none by default.
+pub(crate) const STALE_WHILE_REVALIDATE_ENDPOINT_NAMES: [&str; 2] = ["balance", "tokens"];
+pub(crate) static STALE_WHILE_REVALIDATE_ENDPOINTS: Lazy<HashSet<String>> = Lazy::new(|| {
+    let setting = std::env::var("STALE_WHILE_REVALIDATE_ENDPOINTS").unwrap_or_default();
+    setting
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .filter(|name| {
+            let known = STALE_WHILE_REVALIDATE_ENDPOINT_NAMES.contains(&name.as_str());
+            if !known {
+                tracing::warn!("Ignoring unknown STALE_WHILE_REVALIDATE_ENDPOINTS entry {:?}", name);
+            }
+            known
+        })
+        .collect()
+});
+// How long /tx/send waits for a transaction to reach the requested commitment (default 30 seconds).
+pub(crate) static TX_CONFIRM_TIMEOUT: Lazy<Duration> = Lazy::new(|| {
+    Duration::from_secs(std::env::var("TX_CONFIRM_TIMEOUT_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(30))
+});
+// How long a shutdown waits for open requests to finish before exiting anyway (default 30 seconds).
+pub(crate) static SHUTDOWN_TIMEOUT: Lazy<Duration> = Lazy::new(|| {
+    Duration::from_secs(std::env::var("SHUTDOWN_TIMEOUT_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(30))
+});
+// LOG_FORMAT=json writes each log line as a JSON object instead of text.
+pub(crate) static LOG_JSON: Lazy<bool> = Lazy::new(|| std::env::var("LOG_FORMAT").map(|v| v == "json").unwrap_or(false));
+// LOG_LEVEL: error, warn, info (the default), debug, trace or off. Read before logging is set
+// up, so a bad value is reported on stderr directly.
+pub(crate) static LOG_LEVEL: Lazy<tracing_subscriber::filter::LevelFilter> = Lazy::new(|| match std::env::var("LOG_LEVEL") {
+    Err(_) => tracing_subscriber::filter::LevelFilter::INFO,
+    Ok(value) => value.parse().unwrap_or_else(|_| {
+        eprintln!("Unknown LOG_LEVEL {:?}, using info", value);
+        tracing_subscriber::filter::LevelFilter::INFO
+    }),
+});
+// Requests slower than this are logged with the RPC calls they made (0 disables).
+pub(crate) static SLOW_REQUEST_MS: Lazy<u64> =
+    Lazy::new(|| std::env::var("SLOW_REQUEST_MS").ok().and_then(|v| v.parse().ok()).unwrap_or(2000));
+
+// Optional JSON object of per-mint metadata that is layered over the token list on every refresh,
+// e.g. { "<mint>": { "logoURI": "https://..." } }. Unlisted mints are added.
+pub(crate) static TOKEN_OVERRIDES_FILE: Lazy<Option<String>> = Lazy::new(|| std::env::var("TOKEN_OVERRIDES_FILE").ok());
+
+// Known-address labels from ADDRESS_LABELS_URL (a URL or a local file path): {"address": "label"}.
+pub(crate) static ADDRESS_LABELS_URL: Lazy<Option<String>> = Lazy::new(|| std::env::var("ADDRESS_LABELS_URL").ok());
+
+pub(crate) static MAX_DATA_SLICE_LENGTH: Lazy<usize> = Lazy::new(|| {
+    std::env::var("MAX_DATA_SLICE_LENGTH").ok().and_then(|v| v.parse().ok()).unwrap_or(10 * 1024)
+});
+// BLOCKED_ADDRESSES is either a comma-separated list of addresses or the path of a file
+// listing them (comma- or newline-separated). Unset means nothing is blocked.
+pub(crate) static BLOCKED_ADDRESSES: Lazy<HashSet<String>> = Lazy::new(|| {
+    let Ok(setting) = std::env::var("BLOCKED_ADDRESSES") else {
+        return HashSet::new();
+    };
+    let list = std::fs::read_to_string(&setting).unwrap_or(setting);
+    list.split([',', '\n']).map(str::trim).filter(|a| !a.is_empty()).map(str::to_string).collect()
+});
+// Default per-key limits for API_KEYS entries that don't set their own: sustained requests per
+// second, and how many may arrive at once.
+pub(crate) static API_RATE_LIMIT_RPS: Lazy<f64> =
+    Lazy::new(|| std::env::var("API_RATE_LIMIT_RPS").ok().and_then(|v| v.parse().ok()).filter(|&rps: &f64| rps > 0.0).unwrap_or(10.0));
+pub(crate) static API_RATE_LIMIT_BURST: Lazy<f64> =
+    Lazy::new(|| std::env::var("API_RATE_LIMIT_BURST").ok().and_then(|v| v.parse().ok()).filter(|&burst: &f64| burst >= 1.0).unwrap_or(20.0));
+// ENABLED_ENDPOINTS (an allowlist) and DISABLED_ENDPOINTS (a denylist) take comma-separated
+// names from ENDPOINT_NAMES. Disabled endpoints answer 404 as if they did not exist.
+pub(crate) static ENABLED_ENDPOINTS: Lazy<Option<HashSet<String>>> = Lazy::new(|| endpoint_list("ENABLED_ENDPOINTS"));
+pub(crate) static DISABLED_ENDPOINTS: Lazy<HashSet<String>> = Lazy::new(|| endpoint_list("DISABLED_ENDPOINTS").unwrap_or_default());
+
+// MOCK_MODE=1 serves the embedded fixtures below instead of talking to the RPC and token list.
+pub(crate) static MOCK_MODE: Lazy<bool> = Lazy::new(|| std::env::var("MOCK_MODE").map(|v| v == "1").unwrap_or(false));
+// BACKGROUND_REFRESH=1 keeps caches warm from a background task instead of refreshing them on request.
+pub(crate) static BACKGROUND_REFRESH: Lazy<bool> =
+    Lazy::new(|| std::env::var("BACKGROUND_REFRESH").map(|v| v == "1").unwrap_or(false));
+// DEFAULT_COMMITMENT (processed, confirmed or finalized) goes with every RPC call that takes a
+// commitment, unless the request picks another with ?commitment=.
+pub(crate) static DEFAULT_COMMITMENT: Lazy<Commitment> = Lazy::new(|| match std::env::var("DEFAULT_COMMITMENT") {
+    Err(_) => Commitment::Confirmed,
+    Ok(value) => Commitment::parse(&value).unwrap_or_else(|| {
+        tracing::warn!("Unknown DEFAULT_COMMITMENT {:?}, using confirmed", value);
+        Commitment::Confirmed
+    }),
+});
+// Webhook hosts /watch may deliver to, e.g. WEBHOOK_ALLOWLIST=hooks.example.com,api.partner.io.
+// Unset means /watch refuses registrations, so the server never POSTs to arbitrary URLs.
+pub(crate) static WEBHOOK_ALLOWLIST: Lazy<HashSet<String>> = Lazy::new(|| {
+    std::env::var("WEBHOOK_ALLOWLIST")
+        .unwrap_or_default()
+        .split(',')
+        .map(|host| host.trim().to_lowercase())
+        .filter(|host| !host.is_empty())
+        .collect()
+});
+// Key webhook deliveries are signed with (HMAC-SHA256). Unset sends them unsigned.
+pub(crate) static WEBHOOK_SECRET: Lazy<Option<String>> = Lazy::new(|| std::env::var("WEBHOOK_SECRET").ok().filter(|secret| !secret.is_empty()));
+// File watches are saved to, e.g. WATCH_STORE=/var/lib/solana-api/watches.json, and reloaded
+// from at startup. Unset keeps them in memory only.
+pub(crate) static WATCH_STORE: Lazy<Option<String>> = Lazy::new(|| std::env::var("WATCH_STORE").ok().filter(|path| !path.is_empty()));
+
+// Methods /rpc forwards, e.g. RPC_PROXY_ALLOWED_METHODS=getBalance,getSlot. Unset means the
+// read-only DEFAULT_PROXY_METHODS; ALWAYS_BLOCKED_PROXY_METHODS are refused either way.
+pub(crate) static RPC_PROXY_ALLOWED_METHODS: Lazy<HashSet<String>> = Lazy::new(|| match std::env::var("RPC_PROXY_ALLOWED_METHODS") {
+    Ok(list) => list.split(',').map(str::trim).filter(|m| !m.is_empty()).map(str::to_string).collect(),
+    Err(_) => DEFAULT_PROXY_METHODS.iter().map(|m| m.to_string()).collect(),
+});
+
+pub(crate) const DEFAULT_PROXY_METHODS: [&str; 22] = [
+    "getAccountInfo",
+    "getBalance",
+    "getBlock",
+    "getBlockHeight",
+    "getBlockTime",
+    "getEpochInfo",
+    "getEpochSchedule",
+    "getFeeForMessage",
+    "getGenesisHash",
+    "getHealth",
+    "getLatestBlockhash",
+    "getMinimumBalanceForRentExemption",
+    "getMultipleAccounts",
+    "getSignatureStatuses",
+    "getSignaturesForAddress",
+    "getSlot",
+    "getTokenAccountBalance",
+    "getTokenAccountsByOwner",
+    "getTokenSupply",
+    "getTransaction",
+    "getVersion",
+    "isBlockhashValid",
+];
+// Submitting transactions and faucet requests go through their own endpoints (or none), and
+// subscriptions need a websocket, so these are refused whatever the allowlist says.
+pub(crate) const ALWAYS_BLOCKED_PROXY_METHODS: [&str; 2] = ["sendTransaction", "requestAirdrop"];
+
+pub(crate) const ENDPOINT_NAMES: [&str; 53] = [
+    "balance",
+    "balance-batch",
+    "balance-history",
+    "sse-balance",
+    "ws-balance",
+    "tokens",
+    "tokens-meta",
+    "tokens-count",
+    "tokens-batch",
+    "resolve",
+    "resolve-batch",
+    "reverse",
+    "block",
+    "vote-accounts",
+    "stake-minimum-delegation",
+    "stake",
+    "leader-schedule",
+    "epoch-schedule",
+    "label",
+    "signature-statuses",
+    "node-status",
+    "health",
+    "ready",
+    "account",
+    "accounts",
+    "search",
+    "holdings-change",
+    "transactions",
+    "token-holders-count",
+    "token-authorities",
+    "token",
+    "token-holders",
+    "fee-for-message",
+    "fees",
+    "network-fees",
+    "blockhash",
+    "token-metadata",
+    "nfts",
+    "portfolio",
+    "cluster-info",
+    "constants",
+    "whoami",
+    "positions",
+    "metrics",
+    "airdrop",
+    "richlist",
+    "watch",
+    "decode-transaction",
+    "tx-simulate",
+    "tx-send",
+    "rpc",
+    "openapi",
+    "docs",
+];
+
+pub(crate) fn endpoint_list(key: &str) -> Option<HashSet<String>> {
+    let setting = std::env::var(key).ok()?;
+    Some(setting.split(',').map(str::trim).filter(|n| !n.is_empty()).map(str::to_string).collect())
+}
+
+pub(crate) fn endpoint_enabled(name: &str) -> bool {
+    ENABLED_ENDPOINTS.as_ref().is_none_or(|enabled| enabled.contains(name)) && !DISABLED_ENDPOINTS.contains(name)
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Commitment {
+    Processed,
+    Confirmed,
+    Finalized,
+}
+
+impl Commitment {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "processed" => Some(Commitment::Processed),
+            "confirmed" => Some(Commitment::Confirmed),
+            "finalized" => Some(Commitment::Finalized),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Commitment::Processed => "processed",
+            Commitment::Confirmed => "confirmed",
+            Commitment::Finalized => "finalized",
+        }
+    }
+
+    // Whether a signature's confirmationStatus is at least this level. Variants are declared
+    // weakest first.
+    pub(crate) fn reached_by(self, status: &str) -> bool {
+        Commitment::parse(status).is_some_and(|status| status as u8 >= self as u8)
+    }
+}
+
+// HOST and PORT set the listening address, 127.0.0.1:3030 by default. HOST=0.0.0.0 (or ::)
+// listens on every interface, as containers need.
+pub(crate) fn bind_address() -> Result<std::net::SocketAddr, String> {
+    let host = std::env::var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+    let port = std::env::var("PORT").unwrap_or_else(|_| "3030".to_string());
+    let ip: std::net::IpAddr = host.parse().map_err(|_| format!("HOST must be an IP address, got {:?}", host))?;
+    let port: u16 = port.parse().map_err(|_| format!("PORT must be a port number, got {:?}", port))?;
+    Ok(std::net::SocketAddr::new(ip, port))
+}
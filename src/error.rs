@@ -0,0 +1,150 @@
+//! The error type handlers return, and how it maps to HTTP statuses and error codes.
+
+use crate::*;
+
+// Numeric error codes are part of the API contract and append-only: once published a code
+// keeps its meaning and is never reused. Codes 1xxx are request problems, 2xxx upstream RPC
+// failures, 3xxx refusals, 4004 missing data and 5xxx server-side conditions.
+pub(crate) const ERROR_INTERNAL: u32 = 5000;
+pub(crate) const ERROR_SERVER_BUSY: u32 = 5003;
+
+#[derive(Debug)]
+pub(crate) enum ApiError {
+    // The upstream request itself failed (connection, TLS, timeout, undecodable body).
+    Http(reqwest::Error),
+    // The RPC endpoint answered with something other than a JSON-RPC response, typically a
+    // provider's HTML 502/504 page. `retry_after` is the provider's Retry-After hint, if any.
+    RpcUnavailable { status: StatusCode, retry_after: Option<Duration> },
+    // The cluster's RPC circuit breaker is open, so the call wasn't sent; it is half-open after
+    // `retry_after`.
+    CircuitOpen { retry_after: Duration },
+    // The RPC answered with a JSON-RPC error object.
+    Rpc { code: i64, message: String },
+    // The RPC answered with a null result, e.g. an unknown account or a skipped slot.
+    NotFound(String),
+    BadRequest(String),
+    // A wallet, mint or other address in the request doesn't decode to a public key.
+    InvalidAddress(String),
+    // The request is understood but not allowed here, e.g. an airdrop on mainnet.
+    Forbidden(String),
+    // A query parameter failed to parse; `field` names it so clients can point at the input.
+    InvalidParam { field: String, message: String },
+    // The address is on the operator's BLOCKED_ADDRESSES list.
+    Blocked,
+    // The request body's Content-Type or Content-Encoding is not one we accept.
+    UnsupportedMediaType(String),
+    // The request body decompressed to more than MAX_BODY_BYTES.
+    PayloadTooLarge,
+    // /rpc was asked for a method outside its allowlist.
+    MethodNotAllowed(String),
+    // API_KEYS is set and the request has no X-API-Key, or one that isn't listed.
+    Unauthorized,
+    // The request's API key has used up its rate limit; a token is back after `retry_after`.
+    RateLimited { retry_after: Duration },
+    // The node refused a transaction sent to /tx: it failed preflight simulation, a signature
+    // didn't verify or it couldn't be deserialized. `err` and `logs` are the node's, if it gave them.
+    TransactionRejected { rpc_code: i64, message: String, err: Value, logs: Value },
+}
+
+impl ApiError {
+    pub(crate) fn status(&self) -> StatusCode {
+        match self {
+            // A provider still rate limiting us after the retries is passed on as such, so
+            // clients back off instead of treating it as an outage.
+            ApiError::RpcUnavailable { status, .. } if *status == StatusCode::TOO_MANY_REQUESTS => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Http(_) | ApiError::RpcUnavailable { .. } | ApiError::Rpc { .. } => StatusCode::BAD_GATEWAY,
+            ApiError::CircuitOpen { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::BadRequest(_)
+            | ApiError::InvalidAddress(_)
+            | ApiError::InvalidParam { .. }
+            | ApiError::TransactionRejected { .. } => StatusCode::BAD_REQUEST,
+            ApiError::Forbidden(_) | ApiError::MethodNotAllowed(_) => StatusCode::FORBIDDEN,
+            ApiError::Blocked => StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    // Stable code for clients to switch on; see ERROR_* for codes that aren't ApiErrors.
+    pub(crate) fn code(&self) -> u32 {
+        match self {
+            ApiError::BadRequest(_) => 1000,
+            ApiError::InvalidAddress(_) => 1001,
+            ApiError::InvalidParam { .. } => 1002,
+            ApiError::UnsupportedMediaType(_) => 1003,
+            ApiError::PayloadTooLarge => 1004,
+            ApiError::TransactionRejected { .. } => 1005,
+            ApiError::Http(_) => 2000,
+            ApiError::RpcUnavailable { .. } => 2001,
+            ApiError::Rpc { .. } => 2002,
+            ApiError::CircuitOpen { .. } => 2003,
+            ApiError::Forbidden(_) => 3001,
+            ApiError::Blocked => 3002,
+            ApiError::MethodNotAllowed(_) => 3003,
+            ApiError::Unauthorized => 3004,
+            ApiError::RateLimited { .. } => 3005,
+            ApiError::NotFound(_) => 4004,
+        }
+    }
+
+    // Machine-readable specifics for the "details" object of the error body, if there are any.
+    pub(crate) fn details(&self) -> Option<Value> {
+        match self {
+            ApiError::RpcUnavailable { status, retry_after } => Some(serde_json::json!({
+                "upstream_status": status.as_u16(),
+                "retry_after_secs": retry_after.map(|wait| wait.as_secs()),
+            })),
+            ApiError::CircuitOpen { retry_after } => Some(serde_json::json!({ "retry_after_secs": retry_after.as_secs_f64().ceil() as u64 })),
+            ApiError::Rpc { code, .. } => Some(serde_json::json!({ "rpc_code": code })),
+            ApiError::InvalidParam { field, .. } => Some(serde_json::json!({ "field": field })),
+            ApiError::MethodNotAllowed(method) => Some(serde_json::json!({ "method": method })),
+            ApiError::TransactionRejected { rpc_code, err, logs, .. } => {
+                Some(serde_json::json!({ "rpc_code": rpc_code, "err": err, "logs": logs }))
+            }
+            _ => None,
+        }
+    }
+
+    // How long the client should wait before retrying, when we know.
+    pub(crate) fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ApiError::RpcUnavailable { retry_after, .. } => *retry_after,
+            ApiError::RateLimited { retry_after } | ApiError::CircuitOpen { retry_after } => Some(*retry_after),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::Http(e) => write!(f, "upstream request failed: {}", e),
+            ApiError::RpcUnavailable { status, .. } => write!(f, "RPC unavailable (HTTP {})", status),
+            ApiError::CircuitOpen { .. } => f.write_str("RPC circuit breaker open: the RPC keeps failing"),
+            ApiError::Rpc { code, message } => write!(f, "RPC error {}: {}", code, message),
+            ApiError::NotFound(message)
+            | ApiError::BadRequest(message)
+            | ApiError::InvalidAddress(message)
+            | ApiError::Forbidden(message)
+            | ApiError::UnsupportedMediaType(message) => f.write_str(message),
+            ApiError::InvalidParam { field, message } => write!(f, "Invalid value for {}: {}", field, message),
+            ApiError::Blocked => f.write_str("address blocked"),
+            ApiError::MethodNotAllowed(_) => f.write_str("method not allowed"),
+            ApiError::Unauthorized => f.write_str("missing or unknown API key"),
+            ApiError::RateLimited { .. } => f.write_str("rate limit exceeded for this API key"),
+            ApiError::PayloadTooLarge => write!(f, "Request body exceeds {} bytes", MAX_BODY_BYTES),
+            ApiError::TransactionRejected { message, .. } => write!(f, "transaction rejected: {}", message),
+        }
+    }
+}
+
+impl warp::reject::Reject for ApiError {}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(e: reqwest::Error) -> Self {
+        ApiError::Http(e)
+    }
+}
@@ -0,0 +1,79 @@
+//! A JSON API over a Solana RPC node: SOL and SPL token balances, .sol name resolution, NFTs,
+//! transaction history and assorted chain data.
+//!
+//! [`run`] is the whole RustBackend binary. Binaries embedding the API on a runtime of their own
+//! call [`serve`] instead; [`models`] has the typed shapes of its responses.
+
+mod auth;
+mod cache;
+mod chain;
+mod cluster;
+mod config;
+mod error;
+mod logging;
+mod metrics;
+pub mod models;
+mod routes;
+mod rpc;
+mod sns;
+mod tokens;
+mod watch;
+mod wire;
+
+use warp::Filter;
+use serde_json::Value;
+use reqwest::Client;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use once_cell::sync::Lazy;
+use tokio::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime};
+use base64::Engine;
+use futures::{FutureExt, SinkExt, StreamExt};
+use hyper::service::Service;
+use sha2::{Digest, Sha256};
+use warp::http::StatusCode;
+use warp::Reply;
+
+// Every module starts with `use crate::*`, so items are shared across modules through these.
+use auth::*;
+use cache::*;
+use chain::*;
+use cluster::*;
+use config::*;
+use error::*;
+use logging::*;
+use metrics::*;
+use models::*;
+use rpc::*;
+use sns::*;
+use tokens::*;
+use watch::*;
+use wire::*;
+
+pub use routes::{serve, serve_with_shutdown};
+
+/// Builds the runtime and serves the API on HOST:PORT (default 127.0.0.1:3030) until SIGINT or
+/// SIGTERM.
+pub fn run() {
+    let _logging = init_logging();
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.worker_threads(8).enable_all();
+    // BLOCKING_THREADS caps the pool used by run_blocking (tokio's default is 512).
+    if let Some(threads) = std::env::var("BLOCKING_THREADS").ok().and_then(|v| v.parse().ok()) {
+        runtime.max_blocking_threads(threads);
+    }
+    let addr = match bind_address() {
+        Ok(addr) => addr,
+        Err(e) => {
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = init_clusters() {
+        tracing::error!("{}", e);
+        std::process::exit(1);
+    }
+    runtime.build().expect("failed to build tokio runtime").block_on(serve_with_shutdown(addr, routes::shutdown_signal()));
+}
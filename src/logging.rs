@@ -0,0 +1,83 @@
+//! Log output, request ids and the access log line written for every request.
+
+use crate::*;
+
+// Kept by run() for as long as the server runs: the otlp feature's tracer provider flushes its
+// last spans when dropped.
+pub(crate) struct LoggingGuard {
+    #[cfg(feature = "otlp")]
+    pub(crate) _tracer_provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+// Logs go to stdout, as text or (LOG_FORMAT=json) one JSON object per line. This crate logs at
+// LOG_LEVEL; dependencies only get to report warnings and errors.
+pub(crate) fn init_logging() -> LoggingGuard {
+    use tracing_subscriber::layer::{Layer, SubscriberExt};
+
+    let level = *LOG_LEVEL;
+    let filter = tracing_subscriber::filter::Targets::new()
+        .with_target(env!("CARGO_CRATE_NAME"), level)
+        .with_default(level.min(tracing_subscriber::filter::LevelFilter::WARN));
+    let output = tracing_subscriber::fmt::layer();
+    let output = if *LOG_JSON { output.json().with_span_list(false).boxed() } else { output.boxed() };
+    #[cfg_attr(not(feature = "otlp"), allow(unused_mut))]
+    let mut layers = vec![output.with_filter(filter).boxed()];
+    #[cfg(feature = "otlp")]
+    let tracer_provider = otlp_layer().map(|(layer, provider)| {
+        layers.push(layer);
+        provider
+    });
+    if tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layers)).is_err() {
+        eprintln!("A global tracing subscriber is already set; logging through it");
+    }
+    #[cfg(feature = "otlp")]
+    if tracer_provider.is_some() {
+        tracing::info!("Exporting traces over OTLP");
+    }
+    LoggingGuard {
+        #[cfg(feature = "otlp")]
+        _tracer_provider: tracer_provider,
+    }
+}
+
+// A caller's X-Request-Id is kept when it is a plausible id, so one id can follow a request
+// through a proxy chain; otherwise a new one is made up.
+pub(crate) fn request_id(headers: &warp::http::HeaderMap) -> String {
+    headers
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            (1..=MAX_REQUEST_ID_LENGTH).contains(&id.len()) && id.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+        })
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()))
+}
+
+// The id of the request being handled, for error bodies; None outside a request.
+pub(crate) fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+// The first address (or .sol name) in the path: the wallet for per-wallet endpoints.
+pub(crate) fn path_wallet(path: &str) -> Option<&str> {
+    path.split('/').find(|segment| segment.ends_with(".sol") || parse_pubkey(segment).is_some())
+}
+
+// One line per request. Written inside the request's span, which carries the request id.
+pub(crate) fn log_request(method: &str, path: &str, endpoint: &str, status: StatusCode, elapsed: Duration) {
+    let (rpc_calls, rpc_errors) = REQUEST_TRACE.with(|trace| {
+        let trace = trace.borrow();
+        (trace.rpc_calls.len(), trace.rpc_calls.iter().filter(|(_, _, ok)| !ok).count())
+    });
+    tracing::info!(
+        method,
+        path,
+        wallet = path_wallet(path),
+        endpoint,
+        status = status.as_u16(),
+        latency_ms = elapsed.as_millis() as u64,
+        rpc_calls,
+        rpc_errors,
+        "request"
+    );
+}
@@ -0,0 +1,123 @@
+//! Request, RPC and cache counters with latency histograms, rendered for GET /metrics.
+
+use crate::*;
+
+// Upper bounds, in seconds, of the latency histogram buckets.
+pub(crate) const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+pub(crate) static METRICS: Lazy<std::sync::Mutex<Metrics>> = Lazy::new(Default::default);
+pub(crate) static TOKEN_MAP_REFRESH_FAILURES: AtomicU64 = AtomicU64::new(0);
+
+// Cumulative counts per bucket, as Prometheus expects: an observation lands in every bucket
+// whose bound it is under.
+#[derive(Default)]
+pub(crate) struct Histogram {
+    pub(crate) buckets: [u64; LATENCY_BUCKETS.len()],
+    pub(crate) count: u64,
+    pub(crate) sum: f64,
+}
+
+impl Histogram {
+    pub(crate) fn observe(&mut self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if secs <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += secs;
+    }
+
+    pub(crate) fn render(&self, out: &mut String, name: &str, labels: &str) {
+        for (bucket, bound) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+            out.push_str(&format!("{}_bucket{{{},le=\"{}\"}} {}\n", name, labels, bound, bucket));
+        }
+        out.push_str(&format!("{}_bucket{{{},le=\"+Inf\"}} {}\n", name, labels, self.count));
+        out.push_str(&format!("{}_sum{{{}}} {}\n", name, labels, self.sum));
+        out.push_str(&format!("{}_count{{{}}} {}\n", name, labels, self.count));
+    }
+}
+
+// Label values are endpoint names, RPC method names and cache names, none of which need escaping.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    // By endpoint and HTTP status.
+    pub(crate) requests: BTreeMap<(&'static str, u16), u64>,
+    pub(crate) request_latency: BTreeMap<&'static str, Histogram>,
+    // By RPC method and whether the call (after retries) succeeded.
+    pub(crate) rpc_calls: BTreeMap<(String, bool), u64>,
+    pub(crate) rpc_latency: BTreeMap<String, Histogram>,
+    // By cache and whether the lookup was a hit.
+    pub(crate) cache_lookups: BTreeMap<(&'static str, bool), u64>,
+}
+
+pub(crate) fn metrics() -> std::sync::MutexGuard<'static, Metrics> {
+    METRICS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub(crate) fn record_request(endpoint: &'static str, status: StatusCode, elapsed: Duration) {
+    let mut metrics = metrics();
+    *metrics.requests.entry((endpoint, status.as_u16())).or_default() += 1;
+    metrics.request_latency.entry(endpoint).or_default().observe(elapsed);
+}
+
+pub(crate) fn record_rpc_call(method: &str, ok: bool, elapsed: Duration) {
+    let mut metrics = metrics();
+    *metrics.rpc_calls.entry((method.to_string(), ok)).or_default() += 1;
+    metrics.rpc_latency.entry(method.to_string()).or_default().observe(elapsed);
+}
+
+pub(crate) fn record_cache_lookup(cache: &'static str, hit: bool) {
+    *metrics().cache_lookups.entry((cache, hit)).or_default() += 1;
+}
+
+pub(crate) fn render_counters() -> String {
+    let metrics = metrics();
+    let mut out = String::new();
+    out.push_str("# HELP http_requests_total Requests answered, by endpoint and status code.\n");
+    out.push_str("# TYPE http_requests_total counter\n");
+    for ((endpoint, status), count) in &metrics.requests {
+        out.push_str(&format!("http_requests_total{{endpoint=\"{}\",status=\"{}\"}} {}\n", endpoint, status, count));
+    }
+    out.push_str("# HELP http_request_duration_seconds Time to produce a response, by endpoint.\n");
+    out.push_str("# TYPE http_request_duration_seconds histogram\n");
+    for (endpoint, histogram) in &metrics.request_latency {
+        histogram.render(&mut out, "http_request_duration_seconds", &format!("endpoint=\"{}\"", endpoint));
+    }
+    out.push_str("# HELP rpc_requests_total Solana RPC calls, by method and result, counting retries as one call.\n");
+    out.push_str("# TYPE rpc_requests_total counter\n");
+    for ((method, ok), count) in &metrics.rpc_calls {
+        let result = if *ok { "ok" } else { "error" };
+        out.push_str(&format!("rpc_requests_total{{method=\"{}\",result=\"{}\"}} {}\n", method, result, count));
+    }
+    out.push_str("# HELP rpc_request_duration_seconds Solana RPC call time including retries, by method.\n");
+    out.push_str("# TYPE rpc_request_duration_seconds histogram\n");
+    for (method, histogram) in &metrics.rpc_latency {
+        histogram.render(&mut out, "rpc_request_duration_seconds", &format!("method=\"{}\"", method));
+    }
+    out.push_str("# HELP cache_requests_total Cache lookups, by cache and result.\n");
+    out.push_str("# TYPE cache_requests_total counter\n");
+    for ((cache, hit), count) in &metrics.cache_lookups {
+        let result = if *hit { "hit" } else { "miss" };
+        out.push_str(&format!("cache_requests_total{{cache=\"{}\",result=\"{}\"}} {}\n", cache, result, count));
+    }
+    out.push_str("# HELP rpc_circuit_state Each cluster's RPC circuit breaker state: 1 for the current one.\n");
+    out.push_str("# TYPE rpc_circuit_state gauge\n");
+    for cluster in clusters() {
+        let current = cluster.circuit.state().name();
+        for state in ["closed", "open", "half_open"] {
+            let value = u8::from(state == current);
+            out.push_str(&format!("rpc_circuit_state{{cluster=\"{}\",state=\"{}\"}} {}\n", cluster.name, state, value));
+        }
+    }
+    out.push_str("# HELP rpc_circuit_trips_total Times each cluster's RPC circuit breaker opened.\n");
+    out.push_str("# TYPE rpc_circuit_trips_total counter\n");
+    for cluster in clusters() {
+        out.push_str(&format!("rpc_circuit_trips_total{{cluster=\"{}\"}} {}\n", cluster.name, cluster.circuit.trips.load(Ordering::Relaxed)));
+    }
+    out.push_str("# HELP token_map_refresh_failures_total Token list refreshes that failed and kept the previous list.\n");
+    out.push_str("# TYPE token_map_refresh_failures_total counter\n");
+    out.push_str(&format!("token_map_refresh_failures_total {}\n", TOKEN_MAP_REFRESH_FAILURES.load(Ordering::Relaxed)));
+    out
+}
@@ -0,0 +1,273 @@
+//! Typed shapes of the API's responses, for clients and for binaries embedding the API.
+//!
+//! Handlers build these and serialize them, so the JSON they describe is exactly what the API
+//! sends before presentation options (`?case=`, `?bigint=`, `?envelope=`) are applied.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::chain::{lamports_to_sol, lamports_to_sol_string};
+
+/// A SOL amount, as in `/balance`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SolBalance {
+    pub lamports: u64,
+    /// Lossy past 2^53 lamports (about 9 million SOL); `sol_string` is exact.
+    pub sol: f64,
+    /// Exact decimal amount without trailing zeros, e.g. "2.5".
+    pub sol_string: String,
+}
+
+impl SolBalance {
+    pub fn from_lamports(lamports: u64) -> Self {
+        SolBalance {
+            lamports,
+            sol: lamports_to_sol(lamports),
+            sol_string: lamports_to_sol_string(lamports),
+        }
+    }
+}
+
+/// One token account's balance, as listed by `/tokens`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenBalance {
+    pub mint: String,
+    /// In whole tokens, or in raw base units when `warning` says the decimals are unusable.
+    pub amount: f64,
+    pub decimals: u64,
+    /// "token" or "token-2022".
+    pub program: String,
+    /// The node's exact decimal amount.
+    #[serde(rename = "uiAmountString", default, skip_serializing_if = "Option::is_none")]
+    pub ui_amount_string: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(rename = "logoURI", default, skip_serializing_if = "Option::is_none")]
+    pub logo_uri: Option<String>,
+    /// Token-2022 extension names.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Vec<Value>>,
+    /// The mint's Token-2022 transfer fee configuration.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transfer_fee: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+/// The body of every error response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    /// Stable code to switch on: 1xxx request problems, 2xxx upstream RPC failures, 3xxx
+    /// refusals, 4004 missing data, 5xxx server-side conditions.
+    pub code: u32,
+    pub message: String,
+    /// Same text as `message`; predates it and stays for existing clients.
+    pub error: String,
+    /// The query parameter that failed to parse, for code 1002.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// The refused method, for code 3003.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    /// Machine-readable specifics, e.g. the upstream HTTP status for code 2001.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+    /// The request's X-Request-Id, to quote when reporting a problem.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl ErrorResponse {
+    pub fn new(code: u32, message: &str) -> Self {
+        ErrorResponse { code, message: message.to_string(), error: message.to_string(), field: None, method: None, details: None, request_id: None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ENDPOINT_NAMES;
+    use crate::routes::OPENAPI;
+    use std::collections::BTreeMap;
+
+    // The object schema serde gives a model: `full` has every optional field set and `minimal`
+    // none, so the fields `minimal` still emits are the required ones.
+    fn serde_schema(full: impl Serialize, minimal: impl Serialize) -> (BTreeMap<String, &'static str>, Vec<String>) {
+        let json_type = |value: &Value| match value {
+            Value::Null => "null",
+            Value::Bool(_) => "boolean",
+            Value::Number(n) if n.is_f64() => "number",
+            Value::Number(_) => "integer",
+            Value::String(_) => "string",
+            Value::Array(_) => "array",
+            Value::Object(_) => "object",
+        };
+        let full = serde_json::to_value(full).unwrap();
+        let properties = full.as_object().unwrap().iter().map(|(key, value)| (key.clone(), json_type(value))).collect();
+        let mut required: Vec<String> = serde_json::to_value(minimal).unwrap().as_object().unwrap().keys().cloned().collect();
+        required.sort();
+        (properties, required)
+    }
+
+    fn check_against_spec(name: &str, (properties, required): (BTreeMap<String, &'static str>, Vec<String>)) {
+        let spec = &OPENAPI["components"]["schemas"][name];
+        let mut spec_required: Vec<String> = serde_json::from_value(spec["required"].clone()).unwrap();
+        spec_required.sort();
+        assert_eq!(required, spec_required, "required fields of {}", name);
+        for (property, json_type) in properties {
+            let documented = spec["properties"][&property]["type"].as_str();
+            // An integer is a valid JSON Schema "number".
+            let matches = documented == Some(json_type) || (json_type == "integer" && documented == Some("number"));
+            assert!(matches, "{}.{} is {} but documented as {:?}", name, property, json_type, documented);
+        }
+    }
+
+    #[test]
+    fn models_match_the_openapi_schemas() {
+        check_against_spec("Lamports", serde_schema(SolBalance::from_lamports(1), SolBalance::from_lamports(1)));
+
+        let minimal = TokenBalance {
+            mint: "Mint".to_string(),
+            amount: 1.5,
+            decimals: 6,
+            program: "token".to_string(),
+            ui_amount_string: None,
+            symbol: None,
+            name: None,
+            logo_uri: None,
+            extensions: None,
+            transfer_fee: None,
+            warning: None,
+        };
+        let full = TokenBalance {
+            ui_amount_string: Some("1.5".to_string()),
+            symbol: Some("TKN".to_string()),
+            name: Some("Token".to_string()),
+            logo_uri: Some("https://example.com/logo.png".to_string()),
+            extensions: Some(vec![Value::String("transferFeeAmount".to_string())]),
+            transfer_fee: Some(serde_json::json!({ "basis_points": 50, "maximum_fee": 1000 })),
+            warning: Some("decimals 255 is out of range".to_string()),
+            ..minimal.clone()
+        };
+        check_against_spec("TokenBalance", serde_schema(full, minimal));
+
+        let minimal = ErrorResponse::new(1002, "invalid parameter");
+        let full = ErrorResponse {
+            field: Some("limit".to_string()),
+            method: Some("sendTransaction".to_string()),
+            details: Some(serde_json::json!({ "field": "limit" })),
+            request_id: Some("req-1".to_string()),
+            ..minimal.clone()
+        };
+        check_against_spec("Error", serde_schema(full, minimal));
+    }
+
+    // Every schema reference in the paths resolves, and each model is used by at least one path.
+    #[test]
+    fn openapi_paths_reference_known_schemas() {
+        fn collect_refs(value: &Value, refs: &mut Vec<String>) {
+            match value {
+                Value::Object(fields) => {
+                    if let Some(Value::String(reference)) = fields.get("$ref") {
+                        refs.push(reference.clone());
+                    }
+                    fields.values().for_each(|value| collect_refs(value, refs));
+                }
+                Value::Array(items) => items.iter().for_each(|item| collect_refs(item, refs)),
+                _ => {}
+            }
+        }
+        let mut refs = Vec::new();
+        collect_refs(&OPENAPI["paths"], &mut refs);
+        collect_refs(&OPENAPI["components"]["responses"], &mut refs);
+        for reference in &refs {
+            let pointer = reference.strip_prefix('#').unwrap_or_else(|| panic!("external reference {}", reference));
+            assert!(OPENAPI.pointer(pointer).is_some(), "{} does not resolve", reference);
+        }
+        for schema in ["Lamports", "TokenBalance", "Error"] {
+            let reference = format!("#/components/schemas/{}", schema);
+            assert!(refs.contains(&reference), "no path uses {}", schema);
+        }
+    }
+
+    // Each name in ENDPOINT_NAMES with the routes it serves. The spec is hand-maintained, so a
+    // new endpoint fails here until both this table and openapi.json list it.
+    const ENDPOINT_PATHS: [(&str, &[(&str, &str)]); 53] = [
+        ("balance", &[("get", "/balance/{wallet}")]),
+        ("balance-batch", &[("post", "/balance/batch")]),
+        ("balance-history", &[("get", "/balance/{wallet}/history")]),
+        ("sse-balance", &[("get", "/sse/balance/{wallet}")]),
+        ("ws-balance", &[("get", "/ws/balance/{wallet}")]),
+        ("tokens", &[("get", "/tokens/{wallet}")]),
+        ("tokens-meta", &[("get", "/tokens/meta")]),
+        ("tokens-count", &[("get", "/tokens/{wallet}/count")]),
+        ("tokens-batch", &[("post", "/tokens/batch")]),
+        ("resolve", &[("get", "/resolve/{name}")]),
+        ("resolve-batch", &[("post", "/resolve-batch")]),
+        ("reverse", &[("get", "/reverse/{address}")]),
+        ("block", &[("get", "/block/{slot}")]),
+        ("vote-accounts", &[("get", "/vote-accounts")]),
+        ("stake-minimum-delegation", &[("get", "/stake/minimum-delegation")]),
+        ("stake", &[("get", "/stake/{wallet}")]),
+        ("leader-schedule", &[("get", "/leader-schedule")]),
+        ("epoch-schedule", &[("get", "/epoch-schedule")]),
+        ("label", &[("get", "/label/{address}")]),
+        ("signature-statuses", &[("post", "/signature-statuses")]),
+        ("node-status", &[("get", "/node-status")]),
+        ("health", &[("get", "/health")]),
+        ("ready", &[("get", "/ready")]),
+        ("account", &[("get", "/account/{pubkey}")]),
+        ("accounts", &[("post", "/accounts")]),
+        ("search", &[("get", "/search/{query}")]),
+        ("holdings-change", &[("post", "/holdings-change")]),
+        ("transactions", &[("get", "/transactions/{wallet}")]),
+        ("token-holders-count", &[("get", "/token-holders-count/{mint}")]),
+        ("token-authorities", &[("get", "/token/{mint}/authorities")]),
+        ("token", &[("get", "/token/{mint}")]),
+        ("token-holders", &[("get", "/token/{mint}/holders")]),
+        ("fee-for-message", &[("post", "/fee-for-message")]),
+        ("fees", &[("get", "/fees")]),
+        ("network-fees", &[("get", "/network/fees")]),
+        ("blockhash", &[("get", "/blockhash/latest")]),
+        ("token-metadata", &[("post", "/token-metadata")]),
+        ("nfts", &[("get", "/nfts/{wallet}")]),
+        ("portfolio", &[("get", "/portfolio/{wallet}")]),
+        ("cluster-info", &[("get", "/cluster-info")]),
+        ("constants", &[("get", "/constants")]),
+        ("whoami", &[("get", "/whoami")]),
+        ("positions", &[("get", "/positions/{wallet}")]),
+        ("metrics", &[("get", "/metrics")]),
+        ("airdrop", &[("post", "/airdrop")]),
+        ("richlist", &[("post", "/richlist")]),
+        ("watch", &[("post", "/watch"), ("delete", "/watch/{id}")]),
+        ("decode-transaction", &[("post", "/decode-transaction")]),
+        ("tx-simulate", &[("post", "/tx/simulate")]),
+        ("tx-send", &[("post", "/tx/send")]),
+        ("rpc", &[("post", "/rpc")]),
+        ("openapi", &[("get", "/openapi.json")]),
+        ("docs", &[("get", "/docs")]),
+    ];
+
+    // Every endpoint is documented under the method and path it serves, and the spec lists
+    // nothing that no endpoint serves.
+    #[test]
+    fn openapi_documents_every_endpoint() {
+        let names: Vec<&str> = ENDPOINT_PATHS.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ENDPOINT_NAMES);
+        let mut served = Vec::new();
+        for (name, routes) in ENDPOINT_PATHS {
+            for &(method, path) in routes {
+                assert!(OPENAPI["paths"][path][method].is_object(), "{} is missing {} {} in openapi.json", name, method.to_uppercase(), path);
+                served.push((method.to_string(), path.to_string()));
+            }
+        }
+        let documented = OPENAPI["paths"].as_object().unwrap();
+        for (path, methods) in documented {
+            for method in methods.as_object().unwrap().keys() {
+                assert!(served.contains(&(method.clone(), path.clone())), "openapi.json documents {} {}, which no endpoint serves", method, path);
+            }
+        }
+    }
+}
@@ -0,0 +1,2015 @@
+//! The HTTP layer: routes, query parsing, response rendering and the server itself.
+
+use crate::*;
+
+pub(crate) static REQUEST_SLOTS: Lazy<std::sync::Arc<tokio::sync::Semaphore>> =
+    Lazy::new(|| std::sync::Arc::new(tokio::sync::Semaphore::new(*MAX_CONCURRENT_REQUESTS)));
+pub(crate) static REJECTED_REQUESTS: AtomicU64 = AtomicU64::new(0);
+
+// Hand-maintained: update src/openapi.json whenever a route or response shape changes. The
+// tests in models.rs check its SolBalance, TokenBalance and Error schemas against the models.
+pub(crate) const OPENAPI_SPEC: &str = include_str!("openapi.json");
+pub(crate) const DOCS_HTML: &str = include_str!("docs.html");
+pub(crate) static OPENAPI: Lazy<Value> = Lazy::new(|| serde_json::from_str(OPENAPI_SPEC).expect("src/openapi.json is valid JSON"));
+
+// Gates a route on the endpoint configuration; put it in front of the route's own filters.
+pub(crate) fn endpoint<F, R>(name: &'static str, route: F) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone
+where
+    F: Filter<Extract = (R,), Error = warp::Rejection> + Clone + Send + Sync + 'static,
+    R: Reply + Send,
+{
+    warp::any()
+        .and_then(move || async move {
+            if endpoint_enabled(name) {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+        .and(route)
+        .map(move |reply: R| {
+            let mut response = reply.into_response();
+            response.extensions_mut().insert(Endpoint(name));
+            response
+        })
+}
+
+// The endpoint that answered, for labelling its request metrics. Requests no endpoint took
+// (unknown paths, blocked addresses, bad query parameters) have none.
+pub(crate) struct Endpoint(pub(crate) &'static str);
+
+// Rejects any request whose path mentions a blocked address before it reaches a handler.
+pub(crate) fn blocklist_filter() -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::path::full()
+        .and_then(|path: warp::path::FullPath| async move {
+            if BLOCKED_ADDRESSES.is_empty() || !path.as_str().split('/').any(|segment| BLOCKED_ADDRESSES.contains(segment)) {
+                Ok(())
+            } else {
+                Err(warp::reject::custom(ApiError::Blocked))
+            }
+        })
+        .untuple_one()
+}
+
+pub(crate) async fn handle_rejection(rejection: warp::Rejection) -> Result<warp::reply::Response, warp::Rejection> {
+    match rejection.find::<ApiError>() {
+        Some(e) => Ok(api_error_reply(e, false)),
+        None => Err(rejection),
+    }
+}
+
+// JSON request bodies, optionally gzip-compressed. MAX_BODY_BYTES bounds both the bytes on the
+// wire and the decompressed size, so a small compressed body can't expand without limit.
+pub(crate) fn json_body() -> impl Filter<Extract = (Value,), Error = warp::Rejection> + Clone {
+    warp::body::content_length_limit(MAX_BODY_BYTES)
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::header::optional::<String>("content-encoding"))
+        .and(warp::body::bytes())
+        .and_then(|content_type: Option<String>, encoding: Option<String>, body: hyper::body::Bytes| async move {
+            parse_json_body(content_type.as_deref(), encoding.as_deref(), &body).map_err(warp::reject::custom)
+        })
+}
+
+// The body's "transaction" as wire bytes, decoded per its "encoding" (base64 unless base58).
+pub(crate) fn body_transaction(body: &Value) -> Result<Vec<u8>, ApiError> {
+    let transaction = body["transaction"]
+        .as_str()
+        .ok_or_else(|| ApiError::BadRequest("Expected {\"transaction\": \"...\", \"encoding\": \"base64\"}".to_string()))?;
+    let bytes = match body["encoding"].as_str().unwrap_or("base64") {
+        "base64" => base64::engine::general_purpose::STANDARD.decode(transaction).ok(),
+        "base58" => bs58::decode(transaction).into_vec().ok(),
+        _ => return Err(ApiError::BadRequest("encoding must be base64 or base58".to_string())),
+    };
+    bytes.ok_or_else(|| ApiError::BadRequest("transaction does not match its encoding".to_string()))
+}
+
+// An optional boolean field of a request body; absent means false.
+pub(crate) fn body_flag(body: &Value, field: &str) -> Result<bool, ApiError> {
+    match &body[field] {
+        Value::Null => Ok(false),
+        Value::Bool(flag) => Ok(*flag),
+        _ => Err(ApiError::BadRequest(format!("{} must be true or false", field))),
+    }
+}
+
+pub(crate) fn parse_json_body(content_type: Option<&str>, encoding: Option<&str>, body: &[u8]) -> Result<Value, ApiError> {
+    if let Some(content_type) = content_type
+        && !content_type.trim().to_lowercase().starts_with("application/json")
+    {
+        return Err(ApiError::UnsupportedMediaType("Content-Type must be application/json".to_string()));
+    }
+    let json = match encoding.map(|e| e.trim().to_lowercase()).as_deref() {
+        None | Some("identity") => std::borrow::Cow::Borrowed(body),
+        Some("gzip") => std::borrow::Cow::Owned(gunzip(body)?),
+        Some(_) => return Err(ApiError::UnsupportedMediaType("Content-Encoding must be gzip or identity".to_string())),
+    };
+    serde_json::from_slice(&json).map_err(|e| ApiError::BadRequest(format!("Invalid JSON body: {}", e)))
+}
+
+// Response encodings the server offers, preferred first.
+#[derive(Clone, Copy)]
+pub(crate) enum ContentEncoding {
+    Brotli,
+    Gzip,
+}
+
+impl ContentEncoding {
+    // The best encoding an Accept-Encoding header allows. `*` stands for any coding not listed,
+    // and q=0 rules one out.
+    pub(crate) fn negotiate(accept: &str) -> Option<Self> {
+        let mut qualities = HashMap::new();
+        for item in accept.split(',') {
+            let mut params = item.split(';');
+            let coding = params.next().unwrap_or_default().trim().to_ascii_lowercase();
+            let quality = match params.find_map(|param| param.trim().strip_prefix("q=")) {
+                Some(q) => q.trim().parse::<f64>().unwrap_or(0.0),
+                None => 1.0,
+            };
+            qualities.insert(coding, quality);
+        }
+        let wildcard = qualities.get("*").copied();
+        let accepts = |coding: &str| qualities.get(coding).copied().or(wildcard).is_some_and(|q| q > 0.0);
+        [ContentEncoding::Brotli, ContentEncoding::Gzip].into_iter().find(|encoding| accepts(encoding.as_str()))
+    }
+
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            ContentEncoding::Brotli => "br",
+            ContentEncoding::Gzip => "gzip",
+        }
+    }
+
+    pub(crate) fn encode(self, body: &[u8]) -> std::io::Result<Vec<u8>> {
+        use std::io::Write;
+        match self {
+            ContentEncoding::Brotli => {
+                let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, BROTLI_QUALITY, 22);
+                writer.write_all(body)?;
+                writer.flush()?;
+                Ok(writer.into_inner())
+            }
+            ContentEncoding::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+// What the request said about cached copies and encodings, taken before it is handed to the routes.
+pub(crate) struct Negotiation {
+    // Only GET and HEAD get ETags; a POST answer isn't a representation that can be revalidated.
+    pub(crate) revalidatable: bool,
+    pub(crate) if_none_match: Option<String>,
+    pub(crate) encoding: Option<ContentEncoding>,
+}
+
+impl Negotiation {
+    pub(crate) fn from_request(request: &hyper::Request<hyper::Body>) -> Self {
+        let header = |name: &str| request.headers().get(name).and_then(|value| value.to_str().ok());
+        Negotiation {
+            revalidatable: matches!(*request.method(), hyper::Method::GET | hyper::Method::HEAD),
+            if_none_match: header("if-none-match").map(str::to_string),
+            encoding: header("accept-encoding").and_then(ContentEncoding::negotiate),
+        }
+    }
+}
+
+// Weak, since the same ETag covers every encoding of the body.
+pub(crate) fn body_etag(body: &[u8]) -> String {
+    let digest = Sha256::digest(body);
+    let hex: String = digest[..16].iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("W/\"{}\"", hex)
+}
+
+// If-None-Match uses weak comparison, so W/ prefixes are ignored on both sides.
+pub(crate) fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    if_none_match.split(',').any(|candidate| candidate.trim() == "*" || opaque(candidate) == opaque(etag))
+}
+
+// Tags successful GET responses with an ETag, answering 304 when the client already has that
+// body, then compresses bodies of COMPRESSION_MIN_BYTES or more in the negotiated encoding.
+// Event streams and upgrades pass through untouched: their bodies never end, so can't be buffered.
+pub(crate) async fn encode_response(response: warp::reply::Response, negotiation: Negotiation) -> warp::reply::Response {
+    let content_type = response.headers().get("content-type").and_then(|value| value.to_str().ok()).unwrap_or_default();
+    if response.status() == StatusCode::SWITCHING_PROTOCOLS
+        || content_type.starts_with("text/event-stream")
+        || response.headers().contains_key("content-encoding")
+    {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::warn!("Failed to read response body: {}", e);
+            return error_reply(ERROR_INTERNAL, "internal error", StatusCode::INTERNAL_SERVER_ERROR, false);
+        }
+    };
+
+    if negotiation.revalidatable && parts.status == StatusCode::OK {
+        let etag = body_etag(&body);
+        let fresh = negotiation.if_none_match.as_deref().is_some_and(|if_none_match| etag_matches(if_none_match, &etag));
+        if let Ok(value) = warp::http::HeaderValue::from_str(&etag) {
+            parts.headers.insert("etag", value);
+        }
+        if fresh {
+            parts.status = StatusCode::NOT_MODIFIED;
+            parts.headers.remove("content-length");
+            return warp::reply::Response::from_parts(parts, hyper::Body::empty());
+        }
+    }
+
+    parts.headers.append("vary", warp::http::HeaderValue::from_static("accept-encoding"));
+    if let Some(encoding) = negotiation.encoding.filter(|_| body.len() >= COMPRESSION_MIN_BYTES) {
+        let uncompressed = body.clone();
+        match run_blocking(move || encoding.encode(&uncompressed)).await {
+            Ok(compressed) => {
+                parts.headers.insert("content-encoding", warp::http::HeaderValue::from_static(encoding.as_str()));
+                parts.headers.remove("content-length");
+                return warp::reply::Response::from_parts(parts, compressed.into());
+            }
+            Err(e) => tracing::warn!("Failed to {} response body, sending it uncompressed: {}", encoding.as_str(), e),
+        }
+    }
+    warp::reply::Response::from_parts(parts, body.into())
+}
+
+pub(crate) fn gunzip(body: &[u8]) -> Result<Vec<u8>, ApiError> {
+    use std::io::Read;
+    let mut decompressed = Vec::new();
+    flate2::read::GzDecoder::new(body)
+        .take(MAX_BODY_BYTES + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|_| ApiError::BadRequest("Request body is not valid gzip".to_string()))?;
+    if decompressed.len() as u64 > MAX_BODY_BYTES {
+        return Err(ApiError::PayloadTooLarge);
+    }
+    Ok(decompressed)
+}
+
+// Parses the query string into an endpoint's parameter struct. Unknown parameters are ignored;
+// a known one with a bad value rejects with ApiError::InvalidParam naming the field.
+pub(crate) fn typed_query<T>() -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone
+where
+    T: serde::de::DeserializeOwned + Send + 'static,
+{
+    warp::query::raw()
+        .or(warp::any().map(String::new))
+        .unify()
+        .and_then(|raw: String| async move {
+            let deserializer = serde_urlencoded::Deserializer::new(form_urlencoded::parse(raw.as_bytes()));
+            serde_path_to_error::deserialize(deserializer).map_err(|e| {
+                let field = e.path().to_string();
+                warp::reject::custom(ApiError::InvalidParam { field, message: e.into_inner().to_string() })
+            })
+        })
+}
+
+// Boolean query flags accept true/false and 1/0.
+pub(crate) fn flag<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    match String::deserialize(deserializer)?.as_str() {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        other => Err(serde::de::Error::custom(format!("expected true, false, 1 or 0, got {:?}", other))),
+    }
+}
+
+pub(crate) fn api_version<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let version: u32 = String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)?;
+    if (1..=API_VERSION).contains(&version) {
+        Ok(version)
+    } else {
+        Err(serde::de::Error::custom(format!("unsupported API version {}, latest is {}", version, API_VERSION)))
+    }
+}
+
+pub(crate) fn latest_api_version() -> u32 {
+    API_VERSION
+}
+
+#[derive(Deserialize)]
+pub(crate) struct TokensQuery {
+    #[serde(default, alias = "prices", deserialize_with = "flag")]
+    pub(crate) with_prices: bool,
+    #[serde(default, deserialize_with = "flag")]
+    pub(crate) debug: bool,
+    #[serde(default, deserialize_with = "flag")]
+    pub(crate) aggregate_by_mint: bool,
+    // Fractional digits to round each amount to for display.
+    pub(crate) round: Option<u32>,
+    #[serde(default, deserialize_with = "flag")]
+    pub(crate) fresh: bool,
+    pub(crate) mint: Option<String>,
+    pub(crate) min_amount: Option<f64>,
+    pub(crate) sort: Option<TokenSort>,
+    pub(crate) limit: Option<usize>,
+    #[serde(default)]
+    pub(crate) offset: usize,
+}
+
+impl TokensQuery {
+    // Checked once per request, before any wallet is fetched, so a batch fails as a whole.
+    pub(crate) fn validate(&self) -> Result<(), ApiError> {
+        if let Some(mint) = &self.mint
+            && parse_pubkey(mint).is_none()
+        {
+            return Err(ApiError::InvalidParam { field: "mint".to_string(), message: "not a mint address".to_string() });
+        }
+        if let Some(min_amount) = self.min_amount
+            && !(min_amount.is_finite() && min_amount >= 0.0)
+        {
+            let message = "must be a non-negative number".to_string();
+            return Err(ApiError::InvalidParam { field: "min_amount".to_string(), message });
+        }
+        if let Some(limit) = self.limit
+            && !(1..=MAX_TOKENS_LIMIT).contains(&limit)
+        {
+            let message = format!("must be between 1 and {}", MAX_TOKENS_LIMIT);
+            return Err(ApiError::InvalidParam { field: "limit".to_string(), message });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct HoldersQuery {
+    pub(crate) limit: Option<usize>,
+    #[serde(default, deserialize_with = "flag")]
+    pub(crate) scan: bool,
+}
+
+impl HoldersQuery {
+    pub(crate) fn limit(&self) -> Result<usize, ApiError> {
+        let max = if self.scan { MAX_TOKEN_HOLDERS_LIMIT } else { LARGEST_TOKEN_ACCOUNTS };
+        match self.limit {
+            Some(limit) if !(1..=max).contains(&limit) => {
+                let message = format!("must be between 1 and {}{}", max, if self.scan { "" } else { " (more needs scan=true)" });
+                Err(ApiError::InvalidParam { field: "limit".to_string(), message })
+            }
+            limit => Ok(limit.unwrap_or(LARGEST_TOKEN_ACCOUNTS)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct BalanceQuery {
+    #[serde(default, deserialize_with = "flag")]
+    pub(crate) multi_commitment: bool,
+    #[serde(default, deserialize_with = "flag")]
+    pub(crate) breakdown: bool,
+    #[serde(default, deserialize_with = "flag")]
+    pub(crate) fresh: bool,
+    #[serde(default, deserialize_with = "flag")]
+    pub(crate) with_domain: bool,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PortfolioQuery {
+    #[serde(default, alias = "prices", deserialize_with = "flag")]
+    pub(crate) with_prices: bool,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct NftsQuery {
+    #[serde(default, deserialize_with = "flag")]
+    pub(crate) with_images: bool,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct TransactionsQuery {
+    pub(crate) limit: Option<usize>,
+    pub(crate) before: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct BalanceHistoryQuery {
+    pub(crate) days: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct FeesQuery {
+    // Comma-separated addresses the transaction will write to.
+    pub(crate) accounts: Option<String>,
+}
+
+impl FeesQuery {
+    pub(crate) fn accounts(&self) -> Result<Vec<String>, ApiError> {
+        let accounts: Vec<String> = self
+            .accounts
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|account| !account.is_empty())
+            .map(str::to_string)
+            .collect();
+        let invalid = |message: String| ApiError::InvalidParam { field: "accounts".to_string(), message };
+        if accounts.len() > MAX_FEE_ACCOUNTS {
+            return Err(invalid(format!("at most {} accounts are allowed", MAX_FEE_ACCOUNTS)));
+        }
+        if let Some(account) = accounts.iter().find(|account| parse_pubkey(account).is_none()) {
+            return Err(invalid(format!("{} is not a valid address", account)));
+        }
+        Ok(accounts)
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct LeaderScheduleQuery {
+    pub(crate) slot: Option<u64>,
+    pub(crate) identity: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PositionsQuery {
+    pub(crate) program: Option<String>,
+    pub(crate) offset: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct BlockhashQuery {
+    pub(crate) commitment: Option<Commitment>,
+    #[serde(default, deserialize_with = "flag")]
+    pub(crate) with_context: bool,
+}
+
+// How a route renders its response, parsed from the query alongside the endpoint's own
+// parameters. `v` is the response contract the client was written against.
+#[derive(Deserialize)]
+pub(crate) struct ReplyOptions {
+    #[serde(default, deserialize_with = "flag")]
+    pub(crate) pretty: bool,
+    #[serde(default, deserialize_with = "flag")]
+    pub(crate) envelope: bool,
+    #[serde(default = "latest_api_version", deserialize_with = "api_version")]
+    pub(crate) v: u32,
+    #[serde(default)]
+    pub(crate) bigint: Bigint,
+    pub(crate) case: Option<Case>,
+}
+
+// ?bigint=string returns u64 amounts as strings for clients whose JSON numbers are doubles.
+#[derive(Deserialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Bigint {
+    #[default]
+    Number,
+    String,
+}
+
+// ?case= rewrites every response key to one convention; without it keys are sent as written.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Case {
+    Camel,
+    Snake,
+}
+
+// Fields holding raw u64 amounts; `sol` is a float already and stays a number.
+pub(crate) const BIGINT_FIELDS: [&str; 5] = ["lamports", "maximum_fee", "total_lamports", "rent_exempt_reserve", "spendable_lamports"];
+
+pub(crate) fn stringify_bigints(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, field) in map.iter_mut() {
+                match field {
+                    Value::Number(n) if BIGINT_FIELDS.contains(&key.as_str()) => {
+                        if let Some(n) = n.as_u64() {
+                            *field = Value::String(n.to_string());
+                        }
+                    }
+                    _ => stringify_bigints(field),
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(stringify_bigints),
+        _ => {}
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct BlockQuery {
+    #[serde(default, deserialize_with = "flag")]
+    pub(crate) full: bool,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct VoteAccountsQuery {
+    #[serde(default, deserialize_with = "flag")]
+    pub(crate) delinquent_only: bool,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct AccountQuery {
+    pub(crate) offset: Option<usize>,
+    pub(crate) length: Option<usize>,
+}
+
+// Keys that are addresses or signatures (e.g. /token-metadata's mints, /leader-schedule's
+// identities) are data, not field names, and are left alone.
+pub(crate) fn rewrite_keys(value: Value, case: Case) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, field)| {
+                    let is_address = key.len() >= 32 && bs58::decode(&key).into_vec().is_ok();
+                    let key = match case {
+                        _ if is_address => key,
+                        Case::Snake => to_snake_case(&key),
+                        Case::Camel => to_camel_case(&key),
+                    };
+                    (key, rewrite_keys(field, case))
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(|item| rewrite_keys(item, case)).collect()),
+        other => other,
+    }
+}
+
+// "logoURI" -> "logo_uri", "uiAmountString" -> "ui_amount_string". A run of capitals is one word.
+pub(crate) fn to_snake_case(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    let mut snake = String::with_capacity(key.len() + 4);
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_ascii_uppercase() {
+            snake.push(c);
+            continue;
+        }
+        let boundary = i > 0 && {
+            let prev = chars[i - 1];
+            prev.is_ascii_lowercase()
+                || prev.is_ascii_digit()
+                || (prev.is_ascii_uppercase() && chars.get(i + 1).is_some_and(|next| next.is_ascii_lowercase()))
+        };
+        if boundary && !snake.ends_with('_') {
+            snake.push('_');
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+    snake
+}
+
+// Goes through snake case first so "logoURI" comes out as "logoUri". A leading underscore, as in
+// "_api_version", is kept.
+pub(crate) fn to_camel_case(key: &str) -> String {
+    let mut camel = String::with_capacity(key.len());
+    let mut upper = false;
+    for (i, c) in to_snake_case(key).chars().enumerate() {
+        if c == '_' && i > 0 {
+            upper = true;
+        } else if upper {
+            camel.push(c.to_ascii_uppercase());
+            upper = false;
+        } else {
+            camel.push(c);
+        }
+    }
+    camel
+}
+
+pub(crate) fn reply_json(value: &Value, pretty: bool) -> warp::reply::Response {
+    let body = if pretty {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    }
+    .unwrap_or_default();
+    warp::reply::with_header(body, "content-type", "application/json").into_response()
+}
+
+// Successful responses go through here so ?envelope= and ?v= apply. Only version 1 exists;
+// a breaking change would branch on `options.v` here and keep rendering v1 for clients that ask.
+pub(crate) fn render(value: &Value, options: &ReplyOptions) -> warp::reply::Response {
+    let mut rewritten;
+    let value = if options.bigint == Bigint::String || options.case.is_some() {
+        rewritten = value.clone();
+        if options.bigint == Bigint::String {
+            stringify_bigints(&mut rewritten);
+        }
+        if let Some(case) = options.case {
+            rewritten = rewrite_keys(rewritten, case);
+        }
+        &rewritten
+    } else {
+        value
+    };
+    if options.envelope {
+        reply_json(&serde_json::json!({"_api_version": options.v, "data": value}), options.pretty)
+    } else {
+        reply_json(value, options.pretty)
+    }
+}
+
+// "error" predates "code"/"message" and carries the same text; it stays for existing clients.
+pub(crate) fn error_body(code: u32, message: &str) -> Value {
+    let mut body = ErrorResponse::new(code, message);
+    body.request_id = current_request_id();
+    serde_json::json!(body)
+}
+
+pub(crate) fn error_reply(code: u32, message: &str, status: StatusCode, pretty: bool) -> warp::reply::Response {
+    warp::reply::with_status(reply_json(&error_body(code, message), pretty), status).into_response()
+}
+
+// Replies with the error's own message; parameter errors also name the offending field.
+pub(crate) fn api_error_reply(e: &ApiError, pretty: bool) -> warp::reply::Response {
+    api_error_reply_with_message(e, &e.to_string(), pretty)
+}
+
+pub(crate) fn api_error_body(e: &ApiError, message: &str) -> ErrorResponse {
+    let mut body = ErrorResponse::new(e.code(), message);
+    match e {
+        ApiError::InvalidParam { field, .. } => body.field = Some(field.clone()),
+        ApiError::MethodNotAllowed(method) => body.method = Some(method.clone()),
+        _ => {}
+    }
+    body.details = e.details();
+    body.request_id = current_request_id();
+    body
+}
+
+pub(crate) fn api_error_reply_with_message(e: &ApiError, message: &str, pretty: bool) -> warp::reply::Response {
+    let body = serde_json::json!(api_error_body(e, message));
+    let mut response = warp::reply::with_status(reply_json(&body, pretty), e.status()).into_response();
+    if let Some(wait) = e.retry_after() {
+        response.headers_mut().insert("retry-after", (wait.as_secs_f64().ceil() as u64).max(1).to_string().parse().unwrap());
+    }
+    response
+}
+
+// Upstream failures are reported with the route's own message; not-found and bad-request
+// errors carry a message meant for the client.
+pub(crate) fn respond(result: Result<Value, ApiError>, failure: &str, options: &ReplyOptions) -> warp::reply::Response {
+    let pretty = options.pretty;
+    match result {
+        Ok(value) => render(&value, options),
+        Err(e @ (ApiError::Http(_) | ApiError::RpcUnavailable { .. } | ApiError::Rpc { .. })) => {
+            tracing::warn!("{}: {}", failure, e);
+            api_error_reply_with_message(&e, failure, pretty)
+        }
+        Err(e) => api_error_reply(&e, pretty),
+    }
+}
+
+// Tells clients how old a cached wallet answer is, how long it stays good for, and whether it
+// was fetched for them (X-Cache: MISS), cached (HIT) or served past its TTL while it is
+// refreshed (STALE). Nothing is added when CACHE_TTL is off or the request failed.
+pub(crate) fn with_cache_headers(response: warp::reply::Response, cached: Option<CachedAge>) -> warp::reply::Response {
+    let mut response = response;
+    if let Some(CachedAge { age, ttl }) = cached
+        && response.status().is_success()
+    {
+        let max_age = ttl.as_secs().saturating_sub(age.as_secs());
+        let status = match age {
+            _ if age.is_zero() => "MISS",
+            _ if age >= ttl => "STALE",
+            _ => "HIT",
+        };
+        let headers = response.headers_mut();
+        headers.insert("cache-control", format!("max-age={}", max_age).parse().unwrap());
+        headers.insert("age", age.as_secs().to_string().parse().unwrap());
+        headers.insert("x-cache", warp::http::HeaderValue::from_static(status));
+    }
+    response
+}
+
+// GET /tokens/{wallet} for one wallet, shared with POST /tokens/batch. The age of a cached
+// answer comes back alongside it for with_cache_headers.
+pub(crate) async fn wallet_tokens(wallet: String, query: &TokensQuery) -> Result<(Value, Option<CachedAge>), ApiError> {
+    wallet_token_list(wallet, query).await.map(|(tokens, _, age)| (tokens, age))
+}
+
+// Filters, sorts and pages before prices are looked up, so only the tokens returned are priced.
+// The count is how many tokens matched the filters, across all pages.
+pub(crate) async fn wallet_token_list(wallet: String, query: &TokensQuery) -> Result<(Value, usize, Option<CachedAge>), ApiError> {
+    let wallet = resolve_wallet(&wallet).await?;
+    if parse_pubkey(&wallet).is_none() {
+        return Err(ApiError::InvalidAddress("Invalid address".to_string()));
+    }
+    let owner = wallet.clone();
+    let fetch = async move {
+        let (tokens, skipped) = get_spl_tokens(&owner).await?;
+        Ok(serde_json::json!({ "tokens": tokens, "skipped_count": skipped }))
+    };
+    let (cached, age) = cached_wallet_response(format!("tokens:{}", wallet), query.fresh, "tokens", fetch).await?;
+    let mut tokens = cached["tokens"].clone();
+    let skipped = cached["skipped_count"].as_u64().unwrap_or(0);
+    if query.aggregate_by_mint {
+        tokens = aggregate_by_mint(tokens);
+    }
+    filter_tokens(&mut tokens, query.mint.as_deref(), query.min_amount);
+    if query.sort.is_some() || query.limit.is_some() || query.offset > 0 {
+        sort_tokens(&mut tokens, query.sort);
+    }
+    let total = page_tokens(&mut tokens, query.offset, query.limit);
+    if query.with_prices {
+        add_prices(&mut tokens).await;
+    }
+    if let Some(places) = query.round {
+        round_amounts(&mut tokens, places);
+    }
+    if query.debug {
+        return Ok((serde_json::json!({ "tokens": tokens, "skipped_count": skipped, "total_count": total }), total, age));
+    }
+    Ok((tokens, total, age))
+}
+
+// GET /balance/{wallet} for one wallet, shared with POST /balance/batch. Any 32-byte address
+// has a balance, PDAs included, so on-curve-ness isn't checked.
+pub(crate) async fn wallet_balance(wallet: String, query: &BalanceQuery) -> Result<(Value, Option<CachedAge>), ApiError> {
+    let wallet = resolve_wallet(&wallet).await?;
+    if parse_pubkey(&wallet).is_none() {
+        return Err(ApiError::InvalidAddress("Invalid address".to_string()));
+    }
+    if query.multi_commitment && query.breakdown {
+        let message = "can't be combined with multi_commitment".to_string();
+        return Err(ApiError::InvalidParam { field: "breakdown".to_string(), message });
+    }
+    let owner = wallet.clone();
+    let (key, fetch) = if query.multi_commitment {
+        ("balance-by-commitment", async move { get_sol_balance_by_commitment(&owner).await }.boxed())
+    } else if query.breakdown {
+        ("balance-breakdown", async move { get_sol_balance_breakdown(&owner).await }.boxed())
+    } else {
+        ("balance", async move { get_sol_balance(&owner).await }.boxed())
+    };
+    let (mut balance, age) = cached_wallet_response(format!("{}:{}", key, wallet), query.fresh, "balance", fetch).await?;
+    // Best effort like prices: a failed lookup leaves the domain null rather than failing the balance.
+    if query.with_domain {
+        balance["domain"] = reverse_sns(&wallet).await.unwrap_or_else(|e| {
+            tracing::warn!("Failed to look up primary domain of {}: {}", wallet, e);
+            None
+        }).into();
+    }
+    Ok((balance, age))
+}
+
+pub(crate) fn split_age(result: Result<(Value, Option<CachedAge>), ApiError>) -> (Result<Value, ApiError>, Option<CachedAge>) {
+    match result {
+        Ok((value, age)) => (Ok(value), age),
+        Err(e) => (Err(e), None),
+    }
+}
+
+// Runs `fetch` for each wallet of a {"wallets": [...]} body, BATCH_CONCURRENCY at a time, and
+// maps every wallet to its answer. A wallet that fails maps to the error body its own request
+// would have got, so one bad address doesn't fail the batch.
+pub(crate) async fn batch_wallets<F, Fut>(body: &Value, failure: &str, fetch: F) -> Result<Value, ApiError>
+where
+    F: Fn(String) -> Fut,
+    Fut: std::future::Future<Output = Result<(Value, Option<CachedAge>), ApiError>>,
+{
+    let wallets = body["wallets"]
+        .as_array()
+        .ok_or_else(|| ApiError::BadRequest("Expected {\"wallets\": [...]}".to_string()))?;
+    if wallets.is_empty() || wallets.len() > MAX_MULTIPLE_ACCOUNTS {
+        let message = format!("Between 1 and {} wallets are allowed", MAX_MULTIPLE_ACCOUNTS);
+        return Err(ApiError::BadRequest(message));
+    }
+    let wallets: Vec<String> = wallets
+        .iter()
+        .map(|wallet| wallet.as_str().map(str::to_string))
+        .collect::<Option<_>>()
+        .ok_or_else(|| ApiError::BadRequest("Wallets must be strings".to_string()))?;
+    let results: Vec<(String, Value)> = futures::stream::iter(wallets)
+        .map(|wallet| {
+            let answer = if BLOCKED_ADDRESSES.contains(&wallet) { None } else { Some(fetch(wallet.clone())) };
+            async move {
+                let result = match answer {
+                    Some(answer) => answer.await,
+                    None => Err(ApiError::Blocked),
+                };
+                let value = match result {
+                    Ok((value, _)) => value,
+                    Err(e @ (ApiError::Http(_) | ApiError::RpcUnavailable { .. } | ApiError::Rpc { .. })) => {
+                        tracing::warn!("{} for {}: {}", failure, wallet, e);
+                        serde_json::json!(api_error_body(&e, failure))
+                    }
+                    Err(e) => serde_json::json!(api_error_body(&e, &e.to_string())),
+                };
+                (wallet, value)
+            }
+        })
+        .buffer_unordered(BATCH_CONCURRENCY)
+        .collect()
+        .await;
+    Ok(Value::Object(results.into_iter().collect()))
+}
+
+/// Serves the API on `addr` until the server fails. Must be called from within a tokio runtime.
+pub async fn serve(addr: std::net::SocketAddr) {
+    serve_with_shutdown(addr, std::future::pending()).await
+}
+
+/// Like [`serve`], but stops once `shutdown` completes: new connections are refused and open
+/// requests get up to SHUTDOWN_TIMEOUT_SECS (default 30) to finish before this returns.
+pub async fn serve_with_shutdown(addr: std::net::SocketAddr, shutdown: impl std::future::Future<Output = ()> + Send + 'static) {
+    let tokens_route = warp::path!("tokens" / String)
+        .and(typed_query())
+        .and(typed_query())
+        .then(|wallet: String, query: TokensQuery, options: ReplyOptions| async move {
+            let listed = match query.validate() {
+                Ok(()) => wallet_token_list(wallet, &query).await,
+                Err(e) => Err(e),
+            };
+            let (listed, count) = match listed {
+                Ok((tokens, count, age)) => (Ok((tokens, age)), Some(count)),
+                Err(e) => (Err(e), None),
+            };
+            let (result, age) = split_age(listed);
+            let total = result.as_ref().ok().filter(|_| query.with_prices).map(total_value_usd);
+            let generation = token_map_generation().await;
+            let mut response = with_cache_headers(respond(result, "Failed to fetch tokens", &options), age);
+            if let Some(total) = total {
+                response.headers_mut().insert("x-total-value-usd", total.to_string().parse().unwrap());
+            }
+            if let Some(count) = count {
+                response.headers_mut().insert("x-total-count", count.into());
+            }
+            warp::reply::with_header(response, "x-token-map-generation", generation.to_string())
+        });
+
+    let tokens_batch_route = warp::path!("tokens" / "batch")
+        .and(warp::post())
+        .and(json_body())
+        .and(typed_query())
+        .and(typed_query())
+        .then(|body: Value, query: TokensQuery, options: ReplyOptions| async move {
+            let result = match query.validate() {
+                Ok(()) => batch_wallets(&body, "Failed to fetch tokens", |wallet| wallet_tokens(wallet, &query)).await,
+                Err(e) => Err(e),
+            };
+            respond(result, "Failed to fetch tokens", &options)
+        });
+
+    let tokens_count_route = warp::path!("tokens" / String / "count")
+        .and(typed_query())
+        .then(|wallet: String, options: ReplyOptions| async move {
+            let result = async {
+                let wallet = resolve_wallet(&wallet).await?;
+                if parse_pubkey(&wallet).is_none() {
+                    return Err(ApiError::InvalidAddress("Invalid address".to_string()));
+                }
+                count_token_accounts(&wallet).await
+            }
+            .await;
+            respond(result, "Failed to count token accounts", &options)
+        });
+
+    let tokens_meta_route = warp::path!("tokens" / "meta")
+        .and(typed_query())
+        .then(|options: ReplyOptions| async move {
+            render(&get_token_map_meta().await, &options)
+        });
+
+    let balance_route = warp::path!("balance" / String)
+        .and(typed_query())
+        .and(typed_query())
+        .then(|wallet: String, query: BalanceQuery, options: ReplyOptions| async move {
+            let (result, age) = split_age(wallet_balance(wallet, &query).await);
+            with_cache_headers(respond(result, "Failed to fetch balance", &options), age)
+        });
+
+    let balance_batch_route = warp::path!("balance" / "batch")
+        .and(warp::post())
+        .and(json_body())
+        .and(typed_query())
+        .and(typed_query())
+        .then(|body: Value, query: BalanceQuery, options: ReplyOptions| async move {
+            let result = batch_wallets(&body, "Failed to fetch balance", |wallet| wallet_balance(wallet, &query)).await;
+            respond(result, "Failed to fetch balances", &options)
+        });
+
+    let balance_stream_route = warp::path!("sse" / "balance" / String)
+        .and(typed_query())
+        .then(|wallet: String, options: ReplyOptions| async move {
+            let wallet = match resolve_wallet(&wallet).await {
+                Ok(wallet) if parse_pubkey(&wallet).is_some() => wallet,
+                Ok(_) => return api_error_reply(&ApiError::InvalidAddress("Invalid address".to_string()), options.pretty),
+                Err(e) => return respond(Err(e), "Failed to resolve wallet", &options),
+            };
+            warp::sse::reply(warp::sse::keep_alive().stream(balance_events(wallet))).into_response()
+        });
+
+    let balance_socket_route = warp::path!("ws" / "balance" / String)
+        .and(warp::ws())
+        .and(typed_query())
+        .and(warp::ext::optional::<RequestSlot>())
+        .then(|wallet: String, ws: warp::ws::Ws, options: ReplyOptions, slot: Option<RequestSlot>| async move {
+            let wallet = match resolve_wallet(&wallet).await {
+                Ok(wallet) if parse_pubkey(&wallet).is_some() => wallet,
+                Ok(_) => return api_error_reply(&ApiError::InvalidAddress("Invalid address".to_string()), options.pretty),
+                Err(e) => return respond(Err(e), "Failed to resolve wallet", &options),
+            };
+            // The upgraded socket is served outside the request, so it is told the cluster, and
+            // holds the request's slot for as long as it is open.
+            let cluster = cluster();
+            let permit = slot.and_then(|slot| slot.take());
+            ws.on_upgrade(move |socket| async move {
+                let _permit = permit;
+                in_cluster(cluster, balance_socket(socket, wallet)).await
+            })
+            .into_response()
+        });
+
+    let resolve_route = warp::path!("resolve" / String)
+        .and(typed_query())
+        .then(|name: String, options: ReplyOptions| async move {
+            let result = if name.ends_with(".sol") {
+                resolve_sns(&name).await.map(|owner| serde_json::json!({"name": name, "owner": owner}))
+            } else if parse_pubkey(&name).is_some() {
+                Ok(serde_json::json!({"name": name, "owner": name}))
+            } else {
+                Err(ApiError::InvalidAddress("Invalid name or address".to_string()))
+            };
+            respond(result, "Failed to resolve name", &options)
+        });
+
+    let resolve_batch_route = warp::path!("resolve-batch")
+        .and(warp::post())
+        .and(json_body())
+        .and(typed_query())
+        .then(|body: Value, options: ReplyOptions| async move {
+            let result = async {
+                let names = body["names"]
+                    .as_array()
+                    .ok_or_else(|| ApiError::BadRequest("Expected {\"names\": [...]}".to_string()))?;
+                if names.is_empty() || names.len() > MAX_MULTIPLE_ACCOUNTS {
+                    let message = format!("Between 1 and {} names are allowed", MAX_MULTIPLE_ACCOUNTS);
+                    return Err(ApiError::BadRequest(message));
+                }
+                let names: Vec<String> = names
+                    .iter()
+                    .map(|name| name.as_str().filter(|name| name.ends_with(".sol") || parse_pubkey(name).is_some()))
+                    .map(|name| name.map(str::to_string))
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| ApiError::InvalidAddress("Invalid name or address".to_string()))?;
+                if names.iter().any(|name| BLOCKED_ADDRESSES.contains(name)) {
+                    return Err(ApiError::Blocked);
+                }
+                resolve_sns_batch(names).await
+            }
+            .await;
+            respond(result, "Failed to resolve names", &options)
+        });
+
+    let reverse_route = warp::path!("reverse" / String)
+        .and(typed_query())
+        .then(|address: String, options: ReplyOptions| async move {
+            let result = async {
+                if parse_pubkey(&address).is_none() {
+                    return Err(ApiError::InvalidAddress("Invalid address".to_string()));
+                }
+                let primary_domain = reverse_sns(&address).await?;
+                Ok(serde_json::json!({ "address": address, "primary_domain": primary_domain }))
+            }
+            .await;
+            respond(result, "Failed to look up primary domain", &options)
+        });
+
+    let block_route = warp::path!("block" / String)
+        .and(typed_query())
+        .and(typed_query())
+        .then(|slot: String, query: BlockQuery, options: ReplyOptions| async move {
+            let result = match slot.parse::<u64>() {
+                Ok(slot) => get_block(slot, query.full).await,
+                Err(_) => Err(ApiError::BadRequest("Invalid slot".to_string())),
+            };
+            respond(result, "Failed to fetch block", &options)
+        });
+
+    let vote_accounts_route = warp::path!("vote-accounts")
+        .and(typed_query())
+        .and(typed_query())
+        .then(|query: VoteAccountsQuery, options: ReplyOptions| async move {
+            let result = get_vote_accounts(query.delinquent_only).await;
+            respond(result, "Failed to fetch vote accounts", &options)
+        });
+
+    let minimum_delegation_route = warp::path!("stake" / "minimum-delegation")
+        .and(typed_query())
+        .then(|options: ReplyOptions| async move {
+            respond(get_minimum_delegation().await, "Failed to fetch minimum delegation", &options)
+        });
+
+    let stake_accounts_route = warp::path!("stake" / String)
+        .and(typed_query())
+        .then(|wallet: String, options: ReplyOptions| async move {
+            let result = async {
+                let wallet = resolve_wallet(&wallet).await?;
+                if parse_pubkey(&wallet).is_none() {
+                    return Err(ApiError::InvalidAddress("Invalid address".to_string()));
+                }
+                get_stake_accounts(&wallet).await
+            }
+            .await;
+            respond(result, "Failed to fetch stake accounts", &options)
+        });
+
+    let leader_schedule_route = warp::path!("leader-schedule")
+        .and(typed_query())
+        .and(typed_query())
+        .then(|query: LeaderScheduleQuery, options: ReplyOptions| async move {
+            let result = async {
+                if let Some(identity) = &query.identity
+                    && parse_pubkey(identity).is_none()
+                {
+                    return Err(ApiError::InvalidParam { field: "identity".to_string(), message: "not a valid address".to_string() });
+                }
+                get_leader_schedule(query.slot, query.identity.as_deref()).await
+            }
+            .await;
+            respond(result, "Failed to fetch leader schedule", &options)
+        });
+
+    let fees_route = warp::path!("fees")
+        .and(typed_query())
+        .and(typed_query())
+        .then(|query: FeesQuery, options: ReplyOptions| async move {
+            let result = match query.accounts() {
+                Ok(accounts) => get_priority_fees(&accounts).await,
+                Err(e) => Err(e),
+            };
+            respond(result, "Failed to fetch priority fees", &options)
+        });
+
+    let network_fees_route = warp::path!("network" / "fees")
+        .and(typed_query())
+        .and(typed_query())
+        .then(|query: FeesQuery, options: ReplyOptions| async move {
+            let result = match query.accounts() {
+                Ok(accounts) => get_network_fees(&accounts).await,
+                Err(e) => Err(e),
+            };
+            respond(result, "Failed to fetch network fees", &options)
+        });
+
+    let epoch_schedule_route = warp::path!("epoch-schedule")
+        .and(typed_query())
+        .then(|options: ReplyOptions| async move {
+            respond(get_epoch_schedule().await, "Failed to fetch epoch schedule", &options)
+        });
+
+    let label_route = warp::path!("label" / String)
+        .and(typed_query())
+        .then(|address: String, options: ReplyOptions| async move {
+            let result = match label_for(&address).await {
+                Some(label) => Ok(serde_json::json!({"address": address, "label": label})),
+                None => Err(ApiError::NotFound("Label not found".to_string())),
+            };
+            respond(result, "Failed to fetch label", &options)
+        });
+
+    let openapi_route = warp::path!("openapi.json")
+        .and(typed_query())
+        .map(|options: ReplyOptions| reply_json(&OPENAPI, options.pretty));
+
+    let docs_route = warp::path!("docs").map(|| warp::reply::html(DOCS_HTML));
+
+    let signature_statuses_route = warp::path!("signature-statuses")
+        .and(warp::post())
+        .and(json_body())
+        .and(typed_query())
+        .then(|body: Value, options: ReplyOptions| async move {
+            let result = async {
+                let signatures = body["signatures"]
+                    .as_array()
+                    .ok_or_else(|| ApiError::BadRequest("Expected {\"signatures\": [...]}".to_string()))?;
+                if signatures.is_empty() || signatures.len() > MAX_SIGNATURE_STATUSES {
+                    let message = format!("Between 1 and {} signatures are allowed", MAX_SIGNATURE_STATUSES);
+                    return Err(ApiError::BadRequest(message));
+                }
+                let signatures: Vec<String> = signatures
+                    .iter()
+                    .map(|s| s.as_str().filter(|s| parse_signature(s).is_some()).map(str::to_string))
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| ApiError::BadRequest("Invalid signature".to_string()))?;
+                get_signature_statuses(&signatures).await
+            }
+            .await;
+            respond(result, "Failed to fetch signature statuses", &options)
+        });
+
+    // Liveness: answers as long as the server is up, without touching the RPC or the caches.
+    let health_route = warp::path!("health")
+        .and(typed_query())
+        .map(|options: ReplyOptions| render(&serde_json::json!({ "status": "ok" }), &options).into_response());
+
+    let ready_route = warp::path!("ready")
+        .and(typed_query())
+        .then(|options: ReplyOptions| async move {
+            let (ready, body) = get_readiness().await;
+            let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+            warp::reply::with_status(render(&body, &options), status).into_response()
+        });
+
+    let node_status_route = warp::path!("node-status")
+        .and(typed_query())
+        .then(|options: ReplyOptions| async move {
+            render(&get_node_status().await, &options)
+        });
+
+    let account_route = warp::path!("account" / String)
+        .and(typed_query())
+        .and(typed_query())
+        .then(|pubkey: String, query: AccountQuery, options: ReplyOptions| async move {
+            let result = async {
+                if parse_pubkey(&pubkey).is_none() {
+                    return Err(ApiError::InvalidAddress("Invalid address".to_string()));
+                }
+                let slice = match (query.offset, query.length) {
+                    (None, None) => None,
+                    (offset, length) => {
+                        let length = length.unwrap_or(*MAX_DATA_SLICE_LENGTH);
+                        if length > *MAX_DATA_SLICE_LENGTH {
+                            return Err(ApiError::InvalidParam {
+                                field: "length".to_string(),
+                                message: format!("must not exceed {}", *MAX_DATA_SLICE_LENGTH),
+                            });
+                        }
+                        Some((offset.unwrap_or(0), length))
+                    }
+                };
+                get_account(&pubkey, slice).await
+            }
+            .await;
+            respond(result, "Failed to fetch account", &options)
+        });
+
+    let accounts_route = warp::path!("accounts")
+        .and(warp::post())
+        .and(json_body())
+        .and(typed_query())
+        .then(|body: Value, options: ReplyOptions| async move {
+            let result = async {
+                let pubkeys = body["pubkeys"]
+                    .as_array()
+                    .ok_or_else(|| ApiError::BadRequest("Expected {\"pubkeys\": [...], \"include_data\": <bool>}".to_string()))?;
+                if pubkeys.is_empty() || pubkeys.len() > MAX_MULTIPLE_ACCOUNTS {
+                    let message = format!("Between 1 and {} pubkeys are allowed", MAX_MULTIPLE_ACCOUNTS);
+                    return Err(ApiError::BadRequest(message));
+                }
+                let pubkeys: Vec<String> = pubkeys
+                    .iter()
+                    .map(|p| p.as_str().filter(|p| parse_pubkey(p).is_some()).map(str::to_string))
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| ApiError::InvalidAddress("Invalid address".to_string()))?;
+                if pubkeys.iter().any(|pubkey| BLOCKED_ADDRESSES.contains(pubkey)) {
+                    return Err(ApiError::Blocked);
+                }
+                get_multiple_accounts(&pubkeys, body["include_data"].as_bool().unwrap_or(false)).await
+            }
+            .await;
+            respond(result, "Failed to fetch accounts", &options)
+        });
+
+    let search_route = warp::path!("search" / String)
+        .and(typed_query())
+        .then(|input: String, options: ReplyOptions| async move {
+            respond(search(&input).await, "Failed to search", &options)
+        });
+
+    let holder_count_route = warp::path!("token-holders-count" / String)
+        .and(typed_query())
+        .then(|mint: String, options: ReplyOptions| async move {
+            let result = async {
+                if parse_pubkey(&mint).is_none() {
+                    return Err(ApiError::InvalidAddress("Invalid mint address".to_string()));
+                }
+                get_holder_count(&mint).await
+            }
+            .await;
+            let (result, cache) = match result {
+                Ok((count, cache)) => (Ok(count), Some(cache)),
+                Err(e) => (Err(e), None),
+            };
+            let mut response = respond(result, "Failed to fetch holder count", &options);
+            if let Some(cache) = cache {
+                response.headers_mut().insert("x-cache", warp::http::HeaderValue::from_static(cache));
+            }
+            response
+        });
+
+    let token_authorities_route = warp::path!("token" / String / "authorities")
+        .and(typed_query())
+        .then(|mint: String, options: ReplyOptions| async move {
+            let result = async {
+                if parse_pubkey(&mint).is_none() {
+                    return Err(ApiError::InvalidAddress("Invalid mint address".to_string()));
+                }
+                get_token_authorities(&mint).await
+            }
+            .await;
+            respond(result, "Failed to fetch token authorities", &options)
+        });
+
+    let token_route = warp::path!("token" / String)
+        .and(typed_query())
+        .then(|mint: String, options: ReplyOptions| async move {
+            let result = async {
+                if parse_pubkey(&mint).is_none() {
+                    return Err(ApiError::InvalidAddress("Invalid mint address".to_string()));
+                }
+                get_token_info(&mint).await
+            }
+            .await;
+            respond(result, "Failed to fetch token", &options)
+        });
+
+    let token_holders_route = warp::path!("token" / String / "holders")
+        .and(typed_query())
+        .and(typed_query())
+        .then(|mint: String, query: HoldersQuery, options: ReplyOptions| async move {
+            let result = async {
+                if parse_pubkey(&mint).is_none() {
+                    return Err(ApiError::InvalidAddress("Invalid mint address".to_string()));
+                }
+                get_token_holders(&mint, query.limit()?, query.scan).await
+            }
+            .await;
+            respond(result, "Failed to fetch token holders", &options)
+        });
+
+    let holdings_change_route = warp::path!("holdings-change")
+        .and(warp::post())
+        .and(json_body())
+        .and(typed_query())
+        .then(|body: Value, options: ReplyOptions| async move {
+            let result = async {
+                let (Some(wallet), Some(from_slot)) = (body["wallet"].as_str(), body["from_slot"].as_u64()) else {
+                    return Err(ApiError::BadRequest("Expected {\"wallet\": \"...\", \"from_slot\": <slot>}".to_string()));
+                };
+                let wallet = resolve_wallet(wallet).await?;
+                if parse_pubkey(&wallet).is_none() {
+                    return Err(ApiError::InvalidAddress("Invalid address".to_string()));
+                }
+                let before = match &body["before"] {
+                    Value::Null => None,
+                    Value::String(cursor) if parse_signature(cursor).is_some() => Some(cursor.clone()),
+                    _ => return Err(ApiError::BadRequest("before must be a transaction signature".to_string())),
+                };
+                get_holdings_change(&wallet, from_slot, before).await
+            }
+            .await;
+            respond(result, "Failed to compute holdings change", &options)
+        });
+
+    let balance_history_route = warp::path!("balance" / String / "history")
+        .and(typed_query())
+        .and(typed_query())
+        .then(|wallet: String, query: BalanceHistoryQuery, options: ReplyOptions| async move {
+            let result = async {
+                let wallet = resolve_wallet(&wallet).await?;
+                if parse_pubkey(&wallet).is_none() {
+                    return Err(ApiError::InvalidAddress("Invalid address".to_string()));
+                }
+                let days = query.days.unwrap_or(DEFAULT_BALANCE_HISTORY_DAYS);
+                if !(1..=MAX_BALANCE_HISTORY_DAYS).contains(&days) {
+                    let message = format!("must be between 1 and {}", MAX_BALANCE_HISTORY_DAYS);
+                    return Err(ApiError::InvalidParam { field: "days".to_string(), message });
+                }
+                get_balance_history(&wallet, days).await
+            }
+            .await;
+            respond(result, "Failed to fetch balance history", &options)
+        });
+
+    let transactions_route = warp::path!("transactions" / String)
+        .and(typed_query())
+        .and(typed_query())
+        .then(|wallet: String, query: TransactionsQuery, options: ReplyOptions| async move {
+            let result = async {
+                let wallet = resolve_wallet(&wallet).await?;
+                if parse_pubkey(&wallet).is_none() {
+                    return Err(ApiError::InvalidAddress("Invalid address".to_string()));
+                }
+                let limit = query.limit.unwrap_or(DEFAULT_TRANSACTIONS_LIMIT);
+                if !(1..=MAX_TRANSACTIONS_LIMIT).contains(&limit) {
+                    let message = format!("must be between 1 and {}", MAX_TRANSACTIONS_LIMIT);
+                    return Err(ApiError::InvalidParam { field: "limit".to_string(), message });
+                }
+                if let Some(before) = &query.before
+                    && parse_signature(before).is_none()
+                {
+                    let message = "not a transaction signature".to_string();
+                    return Err(ApiError::InvalidParam { field: "before".to_string(), message });
+                }
+                get_transactions(&wallet, limit, query.before).await
+            }
+            .await;
+            respond(result, "Failed to fetch transactions", &options)
+        });
+
+    let fee_for_message_route = warp::path!("fee-for-message")
+        .and(warp::post())
+        .and(json_body())
+        .and(typed_query())
+        .then(|body: Value, options: ReplyOptions| async move {
+            let result = async {
+                let message = body["message"]
+                    .as_str()
+                    .ok_or_else(|| ApiError::BadRequest("Expected {\"message\": \"<base64>\"}".to_string()))?;
+                match base64::engine::general_purpose::STANDARD.decode(message) {
+                    Ok(bytes) if !bytes.is_empty() => get_fee_for_message(message).await,
+                    _ => Err(ApiError::BadRequest("message must be a base64-encoded transaction message".to_string())),
+                }
+            }
+            .await;
+            respond(result, "Failed to estimate fee", &options)
+        });
+
+    let blockhash_route = warp::path!("blockhash" / "latest")
+        .and(typed_query())
+        .and(typed_query())
+        .then(|query: BlockhashQuery, options: ReplyOptions| async move {
+            let result = get_latest_blockhash(query.commitment, query.with_context).await;
+            respond(result, "Failed to fetch blockhash", &options)
+        });
+
+    let token_metadata_route = warp::path!("token-metadata")
+        .and(warp::post())
+        .and(json_body())
+        .and(typed_query())
+        .then(|body: Value, options: ReplyOptions| async move {
+            let result = async {
+                let mints = body["mints"]
+                    .as_array()
+                    .ok_or_else(|| ApiError::BadRequest("Expected {\"mints\": [...]}".to_string()))?;
+                if mints.is_empty() || mints.len() > MAX_TOKEN_METADATA_MINTS {
+                    let message = format!("Between 1 and {} mints are allowed", MAX_TOKEN_METADATA_MINTS);
+                    return Err(ApiError::BadRequest(message));
+                }
+                let mints: Vec<String> = mints
+                    .iter()
+                    .map(|m| m.as_str().filter(|m| parse_pubkey(m).is_some()).map(str::to_string))
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| ApiError::InvalidAddress("Invalid mint address".to_string()))?;
+                get_token_metadata(&mints).await
+            }
+            .await;
+            respond(result, "Failed to fetch token metadata", &options)
+        });
+
+    let nfts_route = warp::path!("nfts" / String)
+        .and(typed_query())
+        .and(typed_query())
+        .then(|wallet: String, query: NftsQuery, options: ReplyOptions| async move {
+            let result = async {
+                let wallet = resolve_wallet(&wallet).await?;
+                if parse_pubkey(&wallet).is_none() {
+                    return Err(ApiError::InvalidAddress("Invalid address".to_string()));
+                }
+                get_nfts(&wallet, query.with_images).await
+            }
+            .await;
+            respond(result, "Failed to fetch NFTs", &options)
+        });
+
+    let portfolio_route = warp::path!("portfolio" / String)
+        .and(typed_query())
+        .and(typed_query())
+        .then(|wallet: String, query: PortfolioQuery, options: ReplyOptions| async move {
+            let result = async {
+                let wallet = resolve_wallet(&wallet).await?;
+                if parse_pubkey(&wallet).is_none() {
+                    return Err(ApiError::InvalidAddress("Invalid address".to_string()));
+                }
+                Box::pin(get_portfolio(&wallet, query.with_prices)).await
+            }
+            .await;
+            respond(result, "Failed to fetch portfolio", &options)
+        });
+
+    let cluster_info_route = warp::path!("cluster-info")
+        .and(typed_query())
+        .then(|options: ReplyOptions| async move {
+            respond(get_cluster_info().await, "Failed to fetch cluster info", &options)
+        });
+
+    let constants_route = warp::path!("constants")
+        .and(typed_query())
+        .then(|options: ReplyOptions| async move {
+            respond(get_constants().await, "Failed to fetch cluster constants", &options)
+        });
+
+    let whoami_route = warp::path!("whoami")
+        .and(warp::ext::optional::<std::net::SocketAddr>())
+        .and(warp::header::headers_cloned())
+        .and(typed_query())
+        .then(|remote: Option<std::net::SocketAddr>, headers: warp::http::HeaderMap, options: ReplyOptions| async move {
+            respond(Ok(whoami(remote, &headers).await), "Failed to describe request", &options)
+        });
+
+    let positions_route = warp::path!("positions" / String)
+        .and(typed_query())
+        .and(typed_query())
+        .then(|wallet: String, query: PositionsQuery, options: ReplyOptions| async move {
+            let result = async {
+                let wallet = resolve_wallet(&wallet).await?;
+                if parse_pubkey(&wallet).is_none() {
+                    return Err(ApiError::InvalidAddress("Invalid address".to_string()));
+                }
+                let missing = |field: &str| ApiError::InvalidParam {
+                    field: field.to_string(),
+                    message: "required".to_string(),
+                };
+                let program = query.program.ok_or_else(|| missing("program"))?;
+                if parse_pubkey(&program).is_none() {
+                    return Err(ApiError::InvalidParam { field: "program".to_string(), message: "not a valid address".to_string() });
+                }
+                let offset = query.offset.ok_or_else(|| missing("offset"))?;
+                get_positions(&wallet, &program, offset).await
+            }
+            .await;
+            respond(result, "Failed to fetch positions", &options)
+        });
+
+    let metrics_route = warp::path!("metrics").map(|| {
+        warp::reply::with_header(render_metrics(), "content-type", "text/plain; version=0.0.4").into_response()
+    });
+
+    let airdrop_route = warp::path!("airdrop")
+        .and(warp::post())
+        .and(json_body())
+        .and(typed_query())
+        .then(|body: Value, options: ReplyOptions| async move {
+            let result = async {
+                let (Some(wallet), Some(sol)) = (body["wallet"].as_str(), body["sol"].as_f64()) else {
+                    return Err(ApiError::BadRequest("Expected {\"wallet\": \"...\", \"sol\": <amount>}".to_string()));
+                };
+                if !(sol > 0.0 && sol <= MAX_AIRDROP_SOL) {
+                    return Err(ApiError::BadRequest(format!("sol must be greater than 0 and at most {}", MAX_AIRDROP_SOL)));
+                }
+                let wallet = resolve_wallet(wallet).await?;
+                if parse_pubkey(&wallet).is_none() {
+                    return Err(ApiError::InvalidAddress("Invalid address".to_string()));
+                }
+                // resolve_wallet already refuses blocked wallets; checked again right before the
+                // one call that makes the RPC send funds.
+                if BLOCKED_ADDRESSES.contains(&wallet) {
+                    return Err(ApiError::Blocked);
+                }
+                request_airdrop(&wallet, (sol * LAMPORTS_PER_SOL as f64).round() as u64).await
+            }
+            .await;
+            respond(result, "Failed to request airdrop", &options)
+        });
+
+    let richlist_route = warp::path!("richlist")
+        .and(warp::post())
+        .and(json_body())
+        .and(typed_query())
+        .then(|body: Value, options: ReplyOptions| async move {
+            let result = async {
+                let wallets = body["wallets"]
+                    .as_array()
+                    .ok_or_else(|| ApiError::BadRequest("Expected {\"wallets\": [...]}".to_string()))?;
+                if wallets.is_empty() || wallets.len() > MAX_MULTIPLE_ACCOUNTS {
+                    let message = format!("Between 1 and {} wallets are allowed", MAX_MULTIPLE_ACCOUNTS);
+                    return Err(ApiError::BadRequest(message));
+                }
+                let wallets: Vec<String> = wallets
+                    .iter()
+                    .map(|w| w.as_str().filter(|w| parse_pubkey(w).is_some()).map(str::to_string))
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| ApiError::InvalidAddress("Invalid address".to_string()))?;
+                if wallets.iter().any(|wallet| BLOCKED_ADDRESSES.contains(wallet)) {
+                    return Err(ApiError::Blocked);
+                }
+                get_rich_list(&wallets).await
+            }
+            .await;
+            respond(result, "Failed to fetch balances", &options)
+        });
+
+    let watch_route = warp::path!("watch")
+        .and(warp::post())
+        .and(json_body())
+        .and(typed_query())
+        .then(|body: Value, options: ReplyOptions| async move {
+            let result = async {
+                let (Some(wallet), Some(webhook_url)) = (body["wallet"].as_str(), body["webhook_url"].as_str()) else {
+                    return Err(ApiError::BadRequest("Expected {\"wallet\": \"...\", \"webhook_url\": \"https://...\"}".to_string()));
+                };
+                let wallet = resolve_wallet(wallet).await?;
+                if parse_pubkey(&wallet).is_none() {
+                    return Err(ApiError::InvalidAddress("Invalid address".to_string()));
+                }
+                if BLOCKED_ADDRESSES.contains(&wallet) {
+                    return Err(ApiError::Blocked);
+                }
+                check_webhook_url(webhook_url)?;
+                let track_tokens = body_flag(&body, "tokens")?;
+                register_watch(wallet, webhook_url.to_string(), track_tokens).await
+            }
+            .await;
+            respond(result, "Failed to register watch", &options)
+        });
+
+    let unwatch_route = warp::path!("watch" / String)
+        .and(warp::delete())
+        .and(typed_query())
+        .then(|id: String, options: ReplyOptions| async move {
+            respond(unregister_watch(&id).await, "Failed to remove watch", &options)
+        });
+
+    let decode_transaction_route = warp::path!("decode-transaction")
+        .and(warp::post())
+        .and(json_body())
+        .and(typed_query())
+        .then(|body: Value, options: ReplyOptions| async move {
+            let result = async {
+                let bytes = body_transaction(&body)?;
+                decode_transaction(&bytes).ok_or_else(|| ApiError::BadRequest("Malformed or unsupported transaction".to_string()))
+            }
+            .await;
+            respond(result, "Failed to decode transaction", &options)
+        });
+
+    let tx_simulate_route = warp::path!("tx" / "simulate")
+        .and(warp::post())
+        .and(json_body())
+        .and(typed_query())
+        .then(|body: Value, options: ReplyOptions| async move {
+            let result = async {
+                let bytes = body_transaction(&body)?;
+                let sig_verify = body_flag(&body, "sig_verify")?;
+                let replace_recent_blockhash = body_flag(&body, "replace_recent_blockhash")?;
+                if sig_verify && replace_recent_blockhash {
+                    return Err(ApiError::BadRequest("sig_verify and replace_recent_blockhash can't both be set".to_string()));
+                }
+                simulate_transaction(&bytes, sig_verify, replace_recent_blockhash).await
+            }
+            .await;
+            respond(result, "Failed to simulate transaction", &options)
+        });
+
+    let tx_send_route = warp::path!("tx" / "send")
+        .and(warp::post())
+        .and(json_body())
+        .and(typed_query())
+        .then(|body: Value, options: ReplyOptions| async move {
+            let result = async {
+                let bytes = body_transaction(&body)?;
+                let skip_preflight = body_flag(&body, "skip_preflight")?;
+                let max_retries = match &body["max_retries"] {
+                    Value::Null => None,
+                    value => Some(value.as_u64().ok_or_else(|| ApiError::BadRequest("max_retries must be a whole number".to_string()))?),
+                };
+                let confirm = match &body["confirm"] {
+                    Value::Null => None,
+                    value => Some(value.as_str().and_then(Commitment::parse).ok_or_else(|| {
+                        ApiError::BadRequest("confirm must be processed, confirmed or finalized".to_string())
+                    })?),
+                };
+                send_transaction(&bytes, skip_preflight, max_retries, confirm).await
+            }
+            .await;
+            respond(result, "Failed to send transaction", &options)
+        });
+
+    let rpc_proxy_route = warp::path!("rpc")
+        .and(warp::post())
+        .and(json_body())
+        .and(typed_query())
+        .then(|body: Value, options: ReplyOptions| async move {
+            let result = async {
+                let Some(method) = body["method"].as_str() else {
+                    return Err(ApiError::BadRequest("Expected a JSON-RPC request {\"method\": \"...\", \"params\": [...]}".to_string()));
+                };
+                check_proxy_method(method)?;
+                proxy_rpc(method, body["params"].clone(), body["id"].clone()).await
+            }
+            .await;
+            respond(result, "Failed to proxy RPC request", &options)
+        });
+
+    // The chain is split into boxed halves: left as one nested `Or`, the combined future is
+    // large enough to overflow a worker's stack in debug builds.
+    let account_routes = endpoint("tokens-meta", tokens_meta_route)
+        .or(endpoint("tokens-count", tokens_count_route))
+        .or(endpoint("tokens-batch", tokens_batch_route))
+        .or(endpoint("tokens", tokens_route))
+        .or(endpoint("balance-batch", balance_batch_route))
+        .or(endpoint("balance", balance_route))
+        .or(endpoint("sse-balance", balance_stream_route))
+        .or(endpoint("ws-balance", balance_socket_route))
+        .or(endpoint("resolve", resolve_route))
+        .or(endpoint("resolve-batch", resolve_batch_route))
+        .or(endpoint("reverse", reverse_route))
+        .or(endpoint("block", block_route))
+        .or(endpoint("vote-accounts", vote_accounts_route))
+        .or(endpoint("stake-minimum-delegation", minimum_delegation_route))
+        .or(endpoint("stake", stake_accounts_route))
+        .or(endpoint("leader-schedule", leader_schedule_route))
+        .or(endpoint("epoch-schedule", epoch_schedule_route))
+        .or(endpoint("label", label_route))
+        .or(endpoint("signature-statuses", signature_statuses_route))
+        .or(endpoint("node-status", node_status_route))
+        .or(endpoint("health", health_route))
+        .or(endpoint("ready", ready_route))
+        .or(endpoint("account", account_route))
+        .or(endpoint("accounts", accounts_route))
+        .map(Reply::into_response)
+        .boxed();
+    let other_routes = endpoint("search", search_route)
+        .or(endpoint("holdings-change", holdings_change_route))
+        .or(endpoint("balance-history", balance_history_route))
+        .or(endpoint("transactions", transactions_route))
+        .or(endpoint("token-holders-count", holder_count_route))
+        .or(endpoint("token-authorities", token_authorities_route))
+        .or(endpoint("token", token_route))
+        .or(endpoint("token-holders", token_holders_route))
+        .or(endpoint("fee-for-message", fee_for_message_route))
+        .or(endpoint("fees", fees_route))
+        .or(endpoint("network-fees", network_fees_route))
+        .or(endpoint("blockhash", blockhash_route))
+        .or(endpoint("token-metadata", token_metadata_route))
+        .or(endpoint("nfts", nfts_route))
+        .or(endpoint("portfolio", portfolio_route))
+        .or(endpoint("cluster-info", cluster_info_route))
+        .or(endpoint("constants", constants_route))
+        .or(endpoint("whoami", whoami_route))
+        .or(endpoint("positions", positions_route))
+        .or(endpoint("metrics", metrics_route))
+        .or(endpoint("airdrop", airdrop_route))
+        .or(endpoint("richlist", richlist_route))
+        .or(endpoint("watch", watch_route.or(unwatch_route)))
+        .or(endpoint("decode-transaction", decode_transaction_route))
+        .or(endpoint("tx-simulate", tx_simulate_route))
+        .or(endpoint("tx-send", tx_send_route))
+        .or(endpoint("rpc", rpc_proxy_route))
+        .or(endpoint("openapi", openapi_route))
+        .or(endpoint("docs", docs_route))
+        .map(Reply::into_response)
+        .boxed();
+    let routes = blocklist_filter()
+        .and(account_routes.or(other_routes))
+        .recover(handle_rejection);
+
+    Lazy::force(&OPENAPI);
+    if *BACKGROUND_REFRESH {
+        spawn_background_refresh();
+    }
+    if CLUSTERS_FILE.is_some() {
+        let names: Vec<&str> = clusters().iter().map(|cluster| cluster.name.as_str()).collect();
+        tracing::info!("Serving {} clusters: {}", names.len(), names.join(", "));
+    }
+    for cluster in clusters().iter().filter(|cluster| cluster.endpoints.len() > 1 && !*MOCK_MODE) {
+        let hosts: Vec<&str> = cluster.endpoints.iter().map(|endpoint| endpoint.host.as_str()).collect();
+        tracing::info!("Spreading {} RPC requests over {} endpoints: {}", cluster.name, hosts.len(), hosts.join(", "));
+        spawn_cluster_job(cluster, "RPC endpoint health", RPC_HEALTH_INTERVAL, probe_rpc_endpoints);
+    }
+    if !WEBHOOK_ALLOWLIST.is_empty() {
+        load_watches().await;
+        spawn_cluster_refresh_loop("watches", WATCH_POLL_INTERVAL, poll_watches);
+    }
+    if std::env::var("REDIS_URL").is_ok() {
+        if cfg!(feature = "redis") {
+            tracing::info!("Sharing caches through Redis");
+        } else {
+            tracing::warn!("REDIS_URL is set but this build lacks the redis feature; caches stay in memory");
+        }
+    }
+    if let Some(keys) = API_KEYS.as_ref() {
+        tracing::info!("Requiring an X-API-Key header ({} keys)", keys.len());
+    }
+    if !BLOCKED_ADDRESSES.is_empty() {
+        tracing::info!("Blocking requests for {} addresses", BLOCKED_ADDRESSES.len());
+    }
+    if !RPC_HEADERS.is_empty() {
+        let names: Vec<&str> = RPC_HEADERS.keys().map(|name| name.as_str()).collect();
+        tracing::info!("Sending extra RPC headers: {}", names.join(", "));
+    }
+    for name in ENABLED_ENDPOINTS.iter().flatten().chain(DISABLED_ENDPOINTS.iter()) {
+        if !ENDPOINT_NAMES.contains(&name.as_str()) {
+            tracing::warn!("Unknown endpoint name in endpoint configuration: {}", name);
+        }
+    }
+    let disabled: Vec<&str> = ENDPOINT_NAMES.into_iter().filter(|name| !endpoint_enabled(name)).collect();
+    if !disabled.is_empty() {
+        tracing::info!("Disabled endpoints: {}", disabled.join(", "));
+    }
+    if *MOCK_MODE {
+        tracing::info!("MOCK_MODE enabled: serving fixture data, no RPC calls will be made");
+    }
+    tracing::info!("Solana API running at http://{}", addr);
+    let service = warp::service(routes);
+    let make_service = hyper::service::make_service_fn(move |conn: &hyper::server::conn::AddrStream| {
+        let service = service.clone();
+        let remote = conn.remote_addr();
+        async move {
+            Ok::<_, std::convert::Infallible>(hyper::service::service_fn(move |request| handle_request(service.clone(), remote, request)))
+        }
+    });
+    let (draining, drain_started) = tokio::sync::oneshot::channel();
+    let server = hyper::Server::bind(&addr).serve(make_service).with_graceful_shutdown(async move {
+        shutdown.await;
+        tracing::info!("Shutting down: waiting up to {}s for open requests", SHUTDOWN_TIMEOUT.as_secs());
+        let _ = draining.send(());
+    });
+    // Streams such as /sse/balance never finish on their own, so draining is bounded.
+    let deadline = async {
+        if drain_started.await.is_ok() {
+            tokio::time::sleep(*SHUTDOWN_TIMEOUT).await;
+        } else {
+            std::future::pending::<()>().await;
+        }
+    };
+    tokio::select! {
+        result = server => match result {
+            Ok(()) => tracing::info!("Shut down cleanly"),
+            Err(e) => tracing::error!("Server error: {}", e),
+        },
+        _ = deadline => tracing::warn!("Shutdown timed out with requests still open; exiting anyway"),
+    }
+}
+
+// Resolves on SIGINT (Ctrl-C) or, on Unix, SIGTERM, which is what container runtimes send.
+pub(crate) async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::warn!("Cannot listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!("Cannot listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
+
+// Prometheus text exposition format.
+pub(crate) fn render_metrics() -> String {
+    let in_flight = *MAX_CONCURRENT_REQUESTS - REQUEST_SLOTS.available_permits();
+    format!(
+        "# HELP in_flight_requests Requests currently being handled.\n\
+         # TYPE in_flight_requests gauge\n\
+         in_flight_requests {}\n\
+         # HELP max_concurrent_requests Configured limit on in-flight requests.\n\
+         # TYPE max_concurrent_requests gauge\n\
+         max_concurrent_requests {}\n\
+         # HELP rejected_requests_total Requests refused with 503 because the limit was reached.\n\
+         # TYPE rejected_requests_total counter\n\
+         rejected_requests_total {}\n\
+         {}",
+        in_flight,
+        *MAX_CONCURRENT_REQUESTS,
+        REJECTED_REQUESTS.load(Ordering::Relaxed),
+        render_counters()
+    )
+}
+
+// Wraps every routed request. Requests over MAX_CONCURRENT_REQUESTS are refused with a 503 rather
+// than queued, and a panicking handler, which would otherwise drop the connection with no
+// response, is answered with a plain 500. The peer address is put in the request's extensions
+// for routes that want it, and encode_response adds ETags and compression on the way out.
+pub(crate) async fn handle_request<S>(
+    service: S,
+    remote: std::net::SocketAddr,
+    request: hyper::Request<hyper::Body>,
+) -> Result<warp::reply::Response, std::convert::Infallible>
+where
+    S: Service<hyper::Request<hyper::Body>, Response = warp::reply::Response, Error = std::convert::Infallible>,
+{
+    let request_id = request_id(request.headers());
+    let handled = handle_identified_request(service, remote, request, &request_id);
+    let Ok(mut response) = REQUEST_ID.scope(request_id.clone(), handled).await;
+    if let Ok(value) = warp::http::HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert("x-request-id", value);
+    }
+    Ok(response)
+}
+
+pub(crate) async fn handle_identified_request<S>(
+    mut service: S,
+    remote: std::net::SocketAddr,
+    mut request: hyper::Request<hyper::Body>,
+    request_id: &str,
+) -> Result<warp::reply::Response, std::convert::Infallible>
+where
+    S: Service<hyper::Request<hyper::Body>, Response = warp::reply::Response, Error = std::convert::Infallible>,
+{
+    // Resolved first so that /{cluster}/health is exempt from the slot limit like /health.
+    let cluster = match request_cluster(&mut request) {
+        Ok(cluster) => cluster,
+        Err(e) => return Ok(api_error_reply(&e, false)),
+    };
+    // Checked before taking a slot, so callers without a usable key can't crowd out those with one.
+    let authorized = authorize(request.uri().path(), request.headers());
+    let slot = match authorized {
+        Ok(()) => Some(REQUEST_SLOTS.clone().try_acquire_owned()),
+        Err(_) => None,
+    };
+    if matches!(slot, Some(Err(_))) && request.uri().path() != LIVENESS_PATH {
+        REJECTED_REQUESTS.fetch_add(1, Ordering::Relaxed);
+        let reply = error_reply(ERROR_SERVER_BUSY, "Server busy, try again shortly", StatusCode::SERVICE_UNAVAILABLE, false);
+        return Ok(warp::reply::with_header(reply, "retry-after", "1").into_response());
+    }
+    let slot = RequestSlot::new(slot.and_then(Result::ok));
+    request.extensions_mut().insert(slot.clone());
+    let query = request.uri().query().unwrap_or_default();
+    let (commitment, min_context_slot) = match request_commitment(query).and_then(|c| Ok((c, request_min_context_slot(query)?))) {
+        Ok(settings) => settings,
+        Err(e) => return Ok(api_error_reply(&e, false)),
+    };
+    request.extensions_mut().insert(remote);
+    let trace_parent = request
+        .headers()
+        .get("traceparent")
+        .and_then(|value| value.to_str().ok())
+        .and_then(TraceParent::parse)
+        .unwrap_or_else(TraceParent::new_root);
+    let (method, path) = (request.method().to_string(), request.uri().path().to_string());
+    let route = format!("{} {}", method, path);
+    let negotiation = Negotiation::from_request(&request);
+    let span = request_span(&route, request_id, &trace_parent);
+    let handled = async move {
+        let started = Instant::now();
+        let response = match authorized {
+            Err(e) => Ok(api_error_reply(&e, false)),
+            Ok(()) => match std::panic::AssertUnwindSafe(service.call(request)).catch_unwind().await {
+                Ok(response) => response,
+                Err(panic) => {
+                    let message = panic
+                        .downcast_ref::<&str>()
+                        .copied()
+                        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                        .unwrap_or("non-string panic payload");
+                    tracing::error!("Handler panicked on {}: {}", route, message);
+                    Ok(error_reply(ERROR_INTERNAL, "internal error", StatusCode::INTERNAL_SERVER_ERROR, false))
+                }
+            },
+        };
+        let Ok(response) = response;
+        let response = hold_slot_while_streaming(response, slot.take());
+        let mut response = encode_response(response, negotiation).await;
+        let elapsed = started.elapsed();
+        log_if_slow(&route, elapsed);
+        let endpoint = response.extensions().get::<Endpoint>().map_or("none", |endpoint| endpoint.0);
+        record_request(endpoint, response.status(), elapsed);
+        log_request(&method, &path, endpoint, response.status(), elapsed);
+        if let Ok(timing) = warp::http::HeaderValue::from_str(&server_timing(elapsed)) {
+            response.headers_mut().insert("server-timing", timing);
+        }
+        if let Some(slot) = REQUEST_TRACE.with(|trace| trace.borrow().context_slot) {
+            response.headers_mut().insert("x-context-slot", slot.into());
+        }
+        Ok(response)
+    };
+    let handled = tracing::Instrument::instrument(handled, span);
+    let handled = TRACE_PARENT.scope(trace_parent, handled);
+    let handled = REQUEST_MIN_CONTEXT_SLOT.scope(min_context_slot, handled);
+    let handled = REQUEST_COMMITMENT.scope(commitment, REQUEST_TRACE.scope(Default::default(), handled));
+    in_cluster(cluster, handled).await
+}
+
+// A request's MAX_CONCURRENT_REQUESTS permit, None for requests that run without one (/health
+// at the limit, or refused by authorize). It normally ends with the response; a route whose
+// work outlives it, like the /ws/balance socket, takes the permit to hold until that ends.
+#[derive(Clone)]
+pub(crate) struct RequestSlot(std::sync::Arc<std::sync::Mutex<Option<tokio::sync::OwnedSemaphorePermit>>>);
+
+impl RequestSlot {
+    pub(crate) fn new(permit: Option<tokio::sync::OwnedSemaphorePermit>) -> Self {
+        RequestSlot(std::sync::Arc::new(std::sync::Mutex::new(permit)))
+    }
+
+    pub(crate) fn take(&self) -> Option<tokio::sync::OwnedSemaphorePermit> {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take()
+    }
+}
+
+// Event streams such as /sse/balance keep sending after the response head, so their permit is
+// moved into the body and released when the client disconnects.
+pub(crate) fn hold_slot_while_streaming(
+    response: warp::reply::Response,
+    permit: Option<tokio::sync::OwnedSemaphorePermit>,
+) -> warp::reply::Response {
+    let streaming = response
+        .headers()
+        .get("content-type")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/event-stream"));
+    let Some(permit) = permit.filter(|_| streaming) else {
+        return response;
+    };
+    let (parts, body) = response.into_parts();
+    let body = body.map(move |chunk| {
+        let _permit = &permit;
+        chunk
+    });
+    warp::reply::Response::from_parts(parts, hyper::Body::wrap_stream(body))
+}
+
+// ?commitment= applies to any endpoint, so it is read here instead of by each route's query.
+pub(crate) fn request_commitment(query: &str) -> Result<Option<Commitment>, ApiError> {
+    let Some((_, value)) = form_urlencoded::parse(query.as_bytes()).find(|(key, _)| key == "commitment") else {
+        return Ok(None);
+    };
+    Commitment::parse(&value).map(Some).ok_or_else(|| ApiError::InvalidParam {
+        field: "commitment".to_string(),
+        message: format!("expected processed, confirmed or finalized, got {:?}", value),
+    })
+}
+
+// Like ?commitment=, ?min_context_slot= applies to every endpoint.
+pub(crate) fn request_min_context_slot(query: &str) -> Result<Option<u64>, ApiError> {
+    let Some((_, value)) = form_urlencoded::parse(query.as_bytes()).find(|(key, _)| key == "min_context_slot") else {
+        return Ok(None);
+    };
+    value.parse().map(Some).map_err(|_| ApiError::InvalidParam {
+        field: "min_context_slot".to_string(),
+        message: format!("expected a slot number, got {:?}", value),
+    })
+}
+
+// W3C Server-Timing, in milliseconds. rpc sums every RPC call, so it can exceed total when
+// calls ran concurrently.
+pub(crate) fn server_timing(total: Duration) -> String {
+    let (rpc, token_map) = REQUEST_TRACE.with(|trace| {
+        let trace = trace.borrow();
+        (trace.rpc_calls.iter().map(|(_, took, _)| *took).sum::<Duration>(), trace.token_map)
+    });
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    format!("rpc;dur={:.1}, token_map;dur={:.1}, total;dur={:.1}", ms(rpc), ms(token_map), ms(total))
+}
+
+// The route path carries the wallet for per-wallet endpoints, so it is logged as-is.
+pub(crate) fn log_if_slow(route: &str, elapsed: Duration) {
+    if *SLOW_REQUEST_MS == 0 || elapsed < Duration::from_millis(*SLOW_REQUEST_MS) {
+        return;
+    }
+    let calls = REQUEST_TRACE.with(|trace| {
+        trace
+            .borrow()
+            .rpc_calls
+            .iter()
+            .map(|(method, took, _)| format!("{} {}ms", method, took.as_millis()))
+            .collect::<Vec<_>>()
+    });
+    let calls = if calls.is_empty() { "none".to_string() } else { calls.join(", ") };
+    tracing::warn!("slow request: {} took {}ms (rpc calls: {})", route, elapsed.as_millis(), calls);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn panicking_handler_returns_a_json_500() {
+        let service = hyper::service::service_fn(|_: hyper::Request<hyper::Body>| async {
+            if true {
+                panic!("handler blew up");
+            }
+            Ok::<_, std::convert::Infallible>(warp::reply().into_response())
+        });
+        let request = hyper::Request::get("/balance/11111111111111111111111111111111")
+            .header("x-request-id", "panic-test")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let Ok(response) = handle_request(service, ([127, 0, 0, 1], 40000).into(), request).await;
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.headers()["x-request-id"], "panic-test");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body.code, ERROR_INTERNAL);
+        assert_eq!(body.message, "internal error");
+        assert_eq!(body.request_id.as_deref(), Some("panic-test"));
+    }
+
+    #[test]
+    fn event_streams_hold_their_slot_until_the_body_is_dropped() {
+        let slots = std::sync::Arc::new(tokio::sync::Semaphore::new(1));
+        let stream = warp::reply::with_header(warp::reply(), "content-type", "text/event-stream").into_response();
+        let response = hold_slot_while_streaming(stream, slots.clone().try_acquire_owned().ok());
+        assert_eq!(slots.available_permits(), 0);
+        drop(response);
+        assert_eq!(slots.available_permits(), 1);
+
+        let json = warp::reply::json(&serde_json::json!({})).into_response();
+        let response = hold_slot_while_streaming(json, slots.clone().try_acquire_owned().ok());
+        assert_eq!(slots.available_permits(), 1);
+        drop(response);
+    }
+}
@@ -0,0 +1,869 @@
+//! The JSON-RPC client: endpoint rotation, retries, commitment and trace propagation.
+
+use crate::*;
+
+// Connection tuning for the shared client, all optional:
+//   RPC_TCP_KEEPALIVE_SECS      TCP keepalive interval (default 60, 0 disables)
+//   RPC_POOL_IDLE_TIMEOUT_SECS  how long idle pooled connections are kept (default 300)
+//   RPC_DNS_CACHE=1             use the hickory-dns resolver, which caches lookups per their TTL
+//   RPC_CONNECT_TIMEOUT_SECS    how long connecting may take (default 5, 0 disables)
+//   RPC_TIMEOUT_SECS            how long a whole request may take, body included (default 30, 0 disables)
+pub(crate) static CLIENT: Lazy<Client> = Lazy::new(|| {
+    let env_secs = |key: &str, default: u64| {
+        std::env::var(key).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+    };
+    let keepalive = env_secs("RPC_TCP_KEEPALIVE_SECS", 60);
+    let mut builder = Client::builder()
+        .tcp_keepalive((keepalive > 0).then(|| Duration::from_secs(keepalive)))
+        .pool_idle_timeout(Duration::from_secs(env_secs("RPC_POOL_IDLE_TIMEOUT_SECS", 300)))
+        .hickory_dns(std::env::var("RPC_DNS_CACHE").map(|v| v == "1").unwrap_or(false));
+    let (connect_timeout, timeout) = (env_secs("RPC_CONNECT_TIMEOUT_SECS", 5), env_secs("RPC_TIMEOUT_SECS", 30));
+    if connect_timeout > 0 {
+        builder = builder.connect_timeout(Duration::from_secs(connect_timeout));
+    }
+    if timeout > 0 {
+        builder = builder.timeout(Duration::from_secs(timeout));
+    }
+    builder.build().expect("failed to build HTTP client")
+});
+
+// Extra headers sent with every RPC request, e.g. a provider API key:
+//   RPC_HEADERS="Authorization: Bearer xyz; x-api-key: abc"
+// Values are marked sensitive and never logged; only header names are printed at startup.
+pub(crate) static RPC_HEADERS: Lazy<reqwest::header::HeaderMap> = Lazy::new(|| {
+    let mut headers = reqwest::header::HeaderMap::new();
+    let Ok(setting) = std::env::var("RPC_HEADERS") else {
+        return headers;
+    };
+    for entry in setting.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let parsed = entry.split_once(':').and_then(|(name, value)| {
+            let name = reqwest::header::HeaderName::from_bytes(name.trim().as_bytes()).ok()?;
+            let mut value = reqwest::header::HeaderValue::from_str(value.trim()).ok()?;
+            value.set_sensitive(true);
+            Some((name, value))
+        });
+        match parsed {
+            Some((name, value)) => {
+                headers.append(name, value);
+            }
+            None => tracing::warn!("Ignoring malformed RPC_HEADERS entry (expected \"Name: value\")"),
+        }
+    }
+    headers
+});
+// Transient RPC failures are retried up to RPC_MAX_RETRIES times (default 3, 0 disables).
+pub(crate) static RPC_MAX_RETRIES: Lazy<u32> =
+    Lazy::new(|| std::env::var("RPC_MAX_RETRIES").ok().and_then(|v| v.parse().ok()).unwrap_or(3));
+// RPC_CIRCUIT_FAILURES calls in a row failing against the upstream open a cluster's circuit
+// breaker (default 5, 0 disables it); it stays open for RPC_CIRCUIT_OPEN_SECS (default 30).
+pub(crate) static RPC_CIRCUIT_FAILURES: Lazy<u32> =
+    Lazy::new(|| std::env::var("RPC_CIRCUIT_FAILURES").ok().and_then(|v| v.parse().ok()).unwrap_or(5));
+pub(crate) static RPC_CIRCUIT_OPEN: Lazy<Duration> = Lazy::new(|| {
+    Duration::from_secs(std::env::var("RPC_CIRCUIT_OPEN_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(30))
+});
+// The Retry-After of calls refused while a half-open breaker's probe is in flight.
+pub(crate) const RPC_CIRCUIT_PROBE_WAIT: Duration = Duration::from_secs(1);
+// RPC_RETRY_JITTER=0 turns off jitter so retry delays are exactly the exponential schedule;
+// RPC_RETRY_SEED fixes the jitter RNG's seed.
+pub(crate) static RPC_RETRY_JITTER: Lazy<bool> = Lazy::new(|| std::env::var("RPC_RETRY_JITTER").map(|v| v != "0").unwrap_or(true));
+pub(crate) static RETRY_RNG: Lazy<std::sync::Mutex<rand::rngs::StdRng>> = Lazy::new(|| {
+    let seed = std::env::var("RPC_RETRY_SEED").ok().and_then(|v| v.parse().ok()).unwrap_or_else(|| {
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0)
+    });
+    std::sync::Mutex::new(rand::SeedableRng::seed_from_u64(seed))
+});
+
+// Separates the three shapes a JSON-RPC response can take: an error object, a null (or
+// missing) result, and an actual result.
+pub(crate) fn extract_result(resp: &Value) -> Result<&Value, ApiError> {
+    if let Some(error) = resp.get("error") {
+        return Err(ApiError::Rpc {
+            code: error["code"].as_i64().unwrap_or(0),
+            message: error["message"].as_str().unwrap_or("unknown error").to_string(),
+        });
+    }
+    match resp.get("result") {
+        None | Some(Value::Null) => Err(ApiError::NotFound("Not found".to_string())),
+        Some(result) => Ok(result),
+    }
+}
+
+// Like extract_result for methods that wrap their answer as {context, value}.
+pub(crate) fn extract_value(resp: &Value) -> Result<&Value, ApiError> {
+    match &extract_result(resp)?["value"] {
+        Value::Null => Err(ApiError::NotFound("Not found".to_string())),
+        value => Ok(value),
+    }
+}
+
+pub(crate) fn mock_rpc_response(method: &str, params: &Value) -> Value {
+    let fixture = match method {
+        // The fixture wallet only holds legacy SPL tokens.
+        "getTokenAccountsByOwner" if params[1]["programId"] == TOKEN_2022_PROGRAM_ID => {
+            return serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": { "context": { "slot": 1 }, "value": [] } });
+        }
+        // Amount-only slices (see count_token_accounts) are cut from the parsed fixture.
+        "getTokenAccountsByOwner" if params[2]["encoding"] == "base64" => {
+            let parsed: Value = serde_json::from_str(include_str!("fixtures/getTokenAccountsByOwner.json")).expect("embedded fixture is valid JSON");
+            let accounts: Vec<Value> = parsed["result"]["value"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|account| {
+                    let info = &account["account"]["data"]["parsed"]["info"];
+                    let amount: u64 = info["tokenAmount"]["amount"].as_str().and_then(|a| a.parse().ok()).unwrap_or(0);
+                    let data = base64::engine::general_purpose::STANDARD.encode(amount.to_le_bytes());
+                    serde_json::json!({ "pubkey": account["pubkey"], "account": { "data": [data, "base64"] } })
+                })
+                .collect();
+            return serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": { "context": { "slot": 1 }, "value": accounts } });
+        }
+        "getHealth" => return serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": "ok" }),
+        "getSlot" => return serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": 250000000 }),
+        "getEpochInfo" => {
+            let info = serde_json::json!({
+                "absoluteSlot": 250000000, "blockHeight": 230000000, "epoch": 578, "slotIndex": 304000,
+                "slotsInEpoch": 432000, "transactionCount": null
+            });
+            return serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": info });
+        }
+        // Recent slots paid 0 to 162000 micro-lamports per compute unit.
+        "getRecentPrioritizationFees" => {
+            let fees: Vec<Value> = (0..150u64)
+                .map(|i| serde_json::json!({ "slot": 249999850 + i, "prioritizationFee": (i % 10) * (i % 10) * 2000 }))
+                .collect();
+            return serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": fees });
+        }
+        "getRecentPerformanceSamples" => {
+            let sample = serde_json::json!({
+                "slot": 250000000, "numSlots": 150, "numTransactions": 240000, "numNonVoteTransactions": 60000, "samplePeriodSecs": 60
+            });
+            let count = params[0].as_u64().unwrap_or(1) as usize;
+            return serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": vec![sample; count] });
+        }
+        // Sent transactions land at once and finalized; simulations succeed.
+        "sendTransaction" => return serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": "1".repeat(88) }),
+        "getSignatureStatuses" => {
+            let status = serde_json::json!({ "slot": 1, "confirmations": null, "err": null, "confirmationStatus": "finalized" });
+            let statuses: Vec<Value> = params[0].as_array().map(|s| s.iter().map(|_| status.clone()).collect()).unwrap_or_default();
+            return serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": { "context": { "slot": 1 }, "value": statuses } });
+        }
+        "simulateTransaction" => {
+            let value = serde_json::json!({ "err": null, "logs": [], "accounts": null, "unitsConsumed": 150, "returnData": null });
+            return serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": { "context": { "slot": 1 }, "value": value } });
+        }
+        "getBalance" => include_str!("fixtures/getBalance.json"),
+        "getTokenAccountsByOwner" => include_str!("fixtures/getTokenAccountsByOwner.json"),
+        "getTokenSupply" => include_str!("fixtures/getTokenSupply.json"),
+        // Every requested account is a system account with the fixture wallet's balance.
+        "getMultipleAccounts" => {
+            let balance: Value = serde_json::from_str(include_str!("fixtures/getBalance.json")).expect("embedded fixture is valid JSON");
+            let account = serde_json::json!({
+                "lamports": balance["result"]["value"],
+                "owner": "11111111111111111111111111111111",
+                "executable": false,
+                "rentEpoch": 18446744073709551615u64,
+                "space": 0,
+                "data": ["", "base64"]
+            });
+            let accounts: Vec<Value> = params[0]
+                .as_array()
+                .map(|addresses| addresses.iter().map(|_| account.clone()).collect())
+                .unwrap_or_default();
+            return serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": { "context": { "slot": 1 }, "value": accounts } });
+        }
+        _ => return serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": null }),
+    };
+    serde_json::from_str(fixture).expect("embedded fixture is valid JSON")
+}
+
+// Per-request record of upstream work, set up by handle_request.
+#[derive(Default)]
+pub(crate) struct RequestTrace {
+    // Method, time taken and whether it succeeded.
+    pub(crate) rpc_calls: Vec<(String, Duration, bool)>,
+    pub(crate) token_map: Duration,
+    // The lowest context.slot among the RPC answers, sent back as X-Context-Slot.
+    pub(crate) context_slot: Option<u64>,
+}
+
+// W3C trace context (https://www.w3.org/TR/trace-context/) of the request being handled: the
+// caller's traceparent, or a new sampled trace when it sent none or an invalid one.
+#[derive(Clone)]
+pub(crate) struct TraceParent {
+    pub(crate) trace_id: String,
+    // The caller's span; only the otlp feature has spans of its own to parent to it.
+    #[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+    pub(crate) parent_id: Option<String>,
+    pub(crate) sampled: bool,
+}
+
+impl TraceParent {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        let fields: Vec<&str> = value.trim().split('-').collect();
+        let hex = |field: &str, len: usize| field.len() == len && field.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+        let nonzero = |field: &str| field.bytes().any(|b| b != b'0');
+        // Versions after 00 may append fields, so only 00 is held to exactly four.
+        let [version, trace_id, parent_id, flags, ..] = fields[..] else {
+            return None;
+        };
+        if !hex(version, 2) || version == "ff" || (version == "00" && fields.len() != 4) {
+            return None;
+        }
+        if !hex(trace_id, 32) || !hex(parent_id, 16) || !hex(flags, 2) || !nonzero(trace_id) || !nonzero(parent_id) {
+            return None;
+        }
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        Some(TraceParent { trace_id: trace_id.to_string(), parent_id: Some(parent_id.to_string()), sampled: flags & 1 == 1 })
+    }
+
+    pub(crate) fn new_root() -> Self {
+        TraceParent { trace_id: format!("{:032x}", rand::random::<u128>().max(1)), parent_id: None, sampled: true }
+    }
+
+    // The traceparent for an outgoing call made as `span_id` within this trace.
+    pub(crate) fn child(&self, span_id: &str) -> String {
+        format!("00-{}-{}-{}", self.trace_id, span_id, if self.sampled { "01" } else { "00" })
+    }
+}
+
+tokio::task_local! {
+    pub(crate) static REQUEST_TRACE: std::cell::RefCell<RequestTrace>;
+    // The request's ?commitment=, if it gave one.
+    pub(crate) static REQUEST_COMMITMENT: Option<Commitment>;
+    // The request's ?min_context_slot=, if it gave one.
+    pub(crate) static REQUEST_MIN_CONTEXT_SLOT: Option<u64>;
+    pub(crate) static TRACE_PARENT: TraceParent;
+    // Echoed as X-Request-Id and put in error bodies.
+    pub(crate) static REQUEST_ID: String;
+}
+
+// Sent with every RPC call made for a request, so the provider's traces join the caller's. With
+// the otlp feature exporting, the call's own span is the parent; otherwise a fresh span id is
+// used for each call. Background refreshes run outside any request and send none.
+pub(crate) fn outgoing_traceparent() -> Option<String> {
+    #[cfg(feature = "otlp")]
+    {
+        use opentelemetry::trace::TraceContextExt;
+        let context = tracing_opentelemetry::OpenTelemetrySpanExt::context(&tracing::Span::current());
+        let span = context.span();
+        let span = span.span_context();
+        if span.is_valid() {
+            return Some(format!("00-{}-{}-{:02x}", span.trace_id(), span.span_id(), span.trace_flags().to_u8()));
+        }
+    }
+    TRACE_PARENT.try_with(|trace| trace.child(&format!("{:016x}", rand::random::<u64>().max(1)))).ok()
+}
+
+// Spans go to the OTLP endpoint in OTEL_EXPORTER_OTLP_ENDPOINT (the exporter also reads the other
+// standard OTEL_* variables). init_logging adds the layer next to the log output.
+#[cfg(feature = "otlp")]
+pub(crate) type OtlpLayer = Box<dyn tracing_subscriber::Layer<tracing_subscriber::Registry> + Send + Sync>;
+
+#[cfg(feature = "otlp")]
+pub(crate) fn otlp_layer() -> Option<(OtlpLayer, opentelemetry_sdk::trace::SdkTracerProvider)> {
+    use opentelemetry::trace::TracerProvider;
+    use tracing_subscriber::layer::Layer;
+
+    std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok()?;
+    let exporter = match opentelemetry_otlp::SpanExporter::builder().with_http().build() {
+        Ok(exporter) => exporter,
+        Err(e) => {
+            tracing::warn!("OTLP exporter could not be built, tracing stays off: {}", e);
+            return None;
+        }
+    };
+    let resource = opentelemetry_sdk::Resource::builder().with_service_name("solana-rust-api").build();
+    let provider =
+        opentelemetry_sdk::trace::SdkTracerProvider::builder().with_batch_exporter(exporter).with_resource(resource).build();
+    // hyper and warp are instrumented too; only this crate's request and rpc spans are exported.
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer("solana-rust-api"))
+        .with_filter(tracing_subscriber::filter::Targets::new().with_target(module_path!(), tracing::Level::INFO))
+        .boxed();
+    Some((layer, provider))
+}
+
+// The request's span: log lines written while handling it carry its id. With the otlp feature
+// it is exported, parented to the caller's span when it sent a traceparent.
+#[cfg_attr(not(feature = "otlp"), allow(unused_variables))]
+pub(crate) fn request_span(route: &str, request_id: &str, trace: &TraceParent) -> tracing::Span {
+    let span = tracing::info_span!("request", request_id, http.route = route);
+    #[cfg(feature = "otlp")]
+    {
+        use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+
+        if let Some(parent_id) = &trace.parent_id
+            && let (Ok(trace_id), Ok(parent_id)) = (TraceId::from_hex(&trace.trace_id), SpanId::from_hex(parent_id))
+        {
+            let flags = if trace.sampled { TraceFlags::SAMPLED } else { TraceFlags::default() };
+            let parent = SpanContext::new(trace_id, parent_id, flags, true, TraceState::default());
+            let _ = tracing_opentelemetry::OpenTelemetrySpanExt::set_parent(
+                &span,
+                opentelemetry::Context::new().with_remote_span_context(parent),
+            );
+        }
+    }
+    span
+}
+
+// Index of the config object in each method's params, for methods that take a commitment. The
+// flagged ones don't accept processed, so confirmed is sent instead.
+pub(crate) const COMMITMENT_CONFIG_INDEX: [(&str, usize, bool); 24] = [
+    ("getAccountInfo", 1, false),
+    ("getBalance", 1, false),
+    ("getBlock", 1, true),
+    ("getBlockHeight", 0, false),
+    ("getEpochInfo", 0, false),
+    ("getFeeForMessage", 1, false),
+    ("getInflationReward", 1, true),
+    ("getLatestBlockhash", 0, false),
+    ("getLeaderSchedule", 1, false),
+    ("getMinimumBalanceForRentExemption", 1, false),
+    ("getMultipleAccounts", 1, false),
+    ("getProgramAccounts", 1, false),
+    ("getSignaturesForAddress", 1, true),
+    ("getSlot", 0, false),
+    ("getStakeMinimumDelegation", 0, false),
+    ("getSupply", 0, false),
+    ("getTokenAccountBalance", 1, false),
+    ("getTokenAccountsByOwner", 2, false),
+    ("getTokenLargestAccounts", 1, false),
+    ("getTokenSupply", 1, false),
+    ("getTransaction", 1, true),
+    ("getVoteAccounts", 0, false),
+    ("isBlockhashValid", 1, false),
+    ("simulateTransaction", 1, false),
+];
+
+// The methods above whose config also takes a minContextSlot, below which the node refuses to
+// answer.
+pub(crate) const MIN_CONTEXT_SLOT_METHODS: [&str; 14] = [
+    "getAccountInfo",
+    "getBalance",
+    "getBlockHeight",
+    "getEpochInfo",
+    "getFeeForMessage",
+    "getInflationReward",
+    "getLatestBlockhash",
+    "getMultipleAccounts",
+    "getProgramAccounts",
+    "getSignaturesForAddress",
+    "getSlot",
+    "getTokenAccountsByOwner",
+    "isBlockhashValid",
+    "simulateTransaction",
+];
+
+// The JSON-RPC error a node answers with while it is behind the requested minContextSlot.
+pub(crate) const RPC_MIN_CONTEXT_SLOT_NOT_REACHED: i64 = -32016;
+
+// Done here rather than by each caller so no call can go out without one. A commitment the
+// caller already put in the config is left alone. The request's ?min_context_slot= goes along
+// where the method takes one.
+pub(crate) fn apply_commitment(method: &str, params: &mut Value) {
+    let Some(&(_, index, confirmed_at_least)) = COMMITMENT_CONFIG_INDEX.iter().find(|(name, ..)| *name == method) else {
+        return;
+    };
+    let Some(params) = params.as_array_mut() else {
+        return;
+    };
+    let mut commitment = request_commitment();
+    if confirmed_at_least && matches!(commitment, Commitment::Processed) {
+        commitment = Commitment::Confirmed;
+    }
+    if params.len() <= index {
+        params.resize(index + 1, Value::Null);
+    }
+    if params[index].is_null() {
+        params[index] = serde_json::json!({});
+    }
+    if let Some(config) = params[index].as_object_mut() {
+        config.entry("commitment").or_insert_with(|| commitment.as_str().into());
+        if let Some(slot) = request_min_context_slot()
+            && MIN_CONTEXT_SLOT_METHODS.contains(&method)
+        {
+            config.entry("minContextSlot").or_insert_with(|| slot.into());
+        }
+    }
+}
+
+pub(crate) fn request_min_context_slot() -> Option<u64> {
+    REQUEST_MIN_CONTEXT_SLOT.try_with(|slot| *slot).ok().flatten()
+}
+
+// The request's ?commitment=, or DEFAULT_COMMITMENT.
+pub(crate) fn request_commitment() -> Commitment {
+    REQUEST_COMMITMENT.try_with(|commitment| *commitment).ok().flatten().unwrap_or(*DEFAULT_COMMITMENT)
+}
+
+pub(crate) async fn rpc_call(method: &str, mut params: Value) -> Result<Value, ApiError> {
+    apply_commitment(method, &mut params);
+    let started = Instant::now();
+    let call = rpc_call_with_retries(method, params);
+    #[cfg(feature = "otlp")]
+    let call = tracing::Instrument::instrument(call, tracing::info_span!("rpc", rpc.method = method));
+    let result = call.await;
+    record_rpc_call(method, result.is_ok(), started.elapsed());
+    if let Err(e) = &result {
+        tracing::warn!(rpc.method = method, elapsed_ms = started.elapsed().as_millis() as u64, error = %e, "RPC call failed");
+    }
+    let context_slot = result.as_ref().ok().and_then(|resp| resp["result"]["context"]["slot"].as_u64());
+    let _ = REQUEST_TRACE.try_with(|trace| {
+        let mut trace = trace.borrow_mut();
+        trace.rpc_calls.push((method.to_string(), started.elapsed(), result.is_ok()));
+        if let Some(slot) = context_slot {
+            trace.context_slot = Some(trace.context_slot.map_or(slot, |lowest| lowest.min(slot)));
+        }
+    });
+    result
+}
+
+pub(crate) async fn rpc_call_with_retries(method: &str, params: Value) -> Result<Value, ApiError> {
+    if *MOCK_MODE {
+        return Ok(mock_rpc_response(method, &params));
+    }
+
+    let circuit = &cluster().circuit;
+    // Held until the call is recorded, so a half-open breaker lets no other call through first.
+    let probe = circuit.admit()?;
+    let body = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params
+    });
+
+    let mut attempt = 0;
+    let result = loop {
+        match send_rpc(&body).await {
+            Err(e) if attempt < *RPC_MAX_RETRIES && is_retryable(&e) && is_safe_to_retry(method, &e) => {
+                let delay = backoff_for(&e, attempt);
+                tracing::warn!("{} failed ({}), retrying in {:?}", method, e, delay);
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            // A node behind the request's ?min_context_slot= may catch up, or the next endpoint
+            // may already be there.
+            Ok(resp) if attempt < *RPC_MAX_RETRIES && resp["error"]["code"] == RPC_MIN_CONTEXT_SLOT_NOT_REACHED => {
+                let delay = retry_delay(attempt);
+                tracing::warn!("{} below the minimum context slot, retrying in {:?}", method, delay);
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => break result,
+        }
+    };
+    circuit.record(&result, probe.is_some());
+    drop(probe);
+    result
+}
+
+pub(crate) fn is_overloaded(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+pub(crate) struct RpcEndpoint {
+    pub(crate) url: String,
+    pub(crate) host: String,
+    pub(crate) consecutive_failures: AtomicU32,
+    pub(crate) healthy: AtomicBool,
+}
+
+impl RpcEndpoint {
+    pub(crate) fn new(url: &str) -> Self {
+        let host = reqwest::Url::parse(url)
+            .ok()
+            .and_then(|url| {
+                let host = url.host_str()?.to_string();
+                Some(url.port().map_or(host.clone(), |port| format!("{}:{}", host, port)))
+            })
+            .unwrap_or_default();
+        RpcEndpoint { url: url.to_string(), host, consecutive_failures: AtomicU32::new(0), healthy: AtomicBool::new(true) }
+    }
+
+    pub(crate) fn record_failure(&self) {
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= RPC_UNHEALTHY_AFTER {
+            self.set_healthy(false);
+        }
+    }
+
+    pub(crate) fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+        self.set_healthy(true);
+    }
+
+    pub(crate) fn set_healthy(&self, healthy: bool) {
+        if self.healthy.swap(healthy, Ordering::Relaxed) != healthy && cluster().endpoints.len() > 1 {
+            let state = if healthy { "back in rotation" } else { "taken out of rotation" };
+            tracing::warn!("RPC endpoint {} {}", self.host, state);
+        }
+    }
+}
+
+// Fails a cluster's RPC calls fast while its upstream keeps failing. RPC_CIRCUIT_FAILURES calls
+// in a row ending (after their retries) in a connection error, timeout or 5xx open it. Open, calls
+// are refused without being sent; after RPC_CIRCUIT_OPEN it is half-open and lets one call
+// through as a probe, refusing the rest until the probe closes it again or, failing, reopens it.
+// A 429 doesn't count, since a provider that is rate limiting us is up and says when to come back.
+#[derive(Default)]
+pub(crate) struct CircuitBreaker {
+    pub(crate) consecutive_failures: AtomicU32,
+    // When the breaker last opened, in milliseconds since the Unix epoch; 0 while closed.
+    pub(crate) opened_at_ms: AtomicU64,
+    pub(crate) trips: AtomicU64,
+    pub(crate) probe_in_flight: AtomicBool,
+}
+
+// The half-open breaker's one call in flight. Dropping it, also when the call is cancelled, lets
+// the next call probe.
+pub(crate) struct CircuitProbe<'a>(&'a AtomicBool);
+
+impl Drop for CircuitProbe<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum CircuitState {
+    Closed,
+    // With how long until it is half-open.
+    Open(Duration),
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open(_) => "open",
+            CircuitState::HalfOpen => "half_open",
+        }
+    }
+}
+
+impl CircuitBreaker {
+    pub(crate) fn state(&self) -> CircuitState {
+        let opened_at = self.opened_at_ms.load(Ordering::Relaxed);
+        if opened_at == 0 {
+            return CircuitState::Closed;
+        }
+        let open_for = Duration::from_millis(unix_millis().saturating_sub(opened_at));
+        match RPC_CIRCUIT_OPEN.checked_sub(open_for) {
+            Some(remaining) if !remaining.is_zero() => CircuitState::Open(remaining),
+            _ => CircuitState::HalfOpen,
+        }
+    }
+
+    // Some while half-open: the caller is the probe and must hold it until its call is recorded.
+    pub(crate) fn admit(&self) -> Result<Option<CircuitProbe<'_>>, ApiError> {
+        match self.state() {
+            CircuitState::Closed => Ok(None),
+            CircuitState::Open(remaining) => Err(ApiError::CircuitOpen { retry_after: remaining }),
+            CircuitState::HalfOpen => match self.probe_in_flight.compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => Ok(Some(CircuitProbe(&self.probe_in_flight))),
+                Err(_) => Err(ApiError::CircuitOpen { retry_after: RPC_CIRCUIT_PROBE_WAIT }),
+            },
+        }
+    }
+
+    // `probe` says whether the call was admitted as the half-open probe. Only the probe decides
+    // whether an open breaker closes: a call admitted before the breaker opened may still finish
+    // after, and its outcome says nothing about the upstream now.
+    pub(crate) fn record(&self, result: &Result<Value, ApiError>, probe: bool) {
+        if *RPC_CIRCUIT_FAILURES == 0 {
+            return;
+        }
+        if !probe && self.state() != CircuitState::Closed {
+            return;
+        }
+        let failed = match result {
+            Err(ApiError::RpcUnavailable { status, .. }) => *status != StatusCode::TOO_MANY_REQUESTS,
+            Err(ApiError::Http(_)) => true,
+            _ => false,
+        };
+        if !failed {
+            self.consecutive_failures.store(0, Ordering::Relaxed);
+            if probe && self.opened_at_ms.swap(0, Ordering::Relaxed) != 0 {
+                tracing::info!("RPC circuit breaker for {} closed", cluster().name);
+            }
+            return;
+        }
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        let reopen = probe || failures >= *RPC_CIRCUIT_FAILURES;
+        if reopen {
+            self.opened_at_ms.store(unix_millis().max(1), Ordering::Relaxed);
+            self.trips.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!(
+                "RPC circuit breaker for {} opened after {} failed calls; failing fast for {}s",
+                cluster().name,
+                failures,
+                RPC_CIRCUIT_OPEN.as_secs()
+            );
+        }
+    }
+}
+
+pub(crate) fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+// Round-robin over the current cluster's healthy endpoints. When none are healthy the rotation carries on over
+// all of them, since asking a struggling endpoint beats failing without asking.
+pub(crate) fn next_rpc_endpoint() -> &'static RpcEndpoint {
+    let endpoints = &cluster().endpoints;
+    let start = cluster().next_endpoint.fetch_add(1, Ordering::Relaxed);
+    (0..endpoints.len())
+        .map(|offset| &endpoints[(start + offset) % endpoints.len()])
+        .find(|endpoint| endpoint.healthy.load(Ordering::Relaxed))
+        .unwrap_or(&endpoints[start % endpoints.len()])
+}
+
+// Every attempt picks an endpoint afresh, so a retry after a failure goes to the next one.
+pub(crate) async fn send_rpc(body: &Value) -> Result<Value, ApiError> {
+    let endpoint = next_rpc_endpoint();
+    let result = send_rpc_to(&endpoint.url, body).await;
+    match &result {
+        Err(ApiError::Http(_) | ApiError::RpcUnavailable { .. }) => endpoint.record_failure(),
+        _ => endpoint.record_success(),
+    }
+    result
+}
+
+// getHealth against each endpoint directly, bypassing retries and rotation. A node that has
+// fallen behind answers with an RPC error and is taken out of rotation like one that is down.
+pub(crate) async fn probe_rpc_endpoints() -> Result<(), ApiError> {
+    let body = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "getHealth", "params": [] });
+    let probes = cluster().endpoints.iter().map(|endpoint| async {
+        match send_rpc_to(&endpoint.url, &body).await {
+            Ok(resp) if resp["result"] == "ok" => endpoint.record_success(),
+            _ => endpoint.set_healthy(false),
+        }
+    });
+    futures::future::join_all(probes).await;
+    Ok(())
+}
+
+pub(crate) fn rpc_endpoint_status() -> Value {
+    let endpoints: Vec<Value> = cluster()
+        .endpoints
+        .iter()
+        .map(|endpoint| {
+            serde_json::json!({
+                "host": endpoint.host,
+                "healthy": endpoint.healthy.load(Ordering::Relaxed),
+                "consecutive_failures": endpoint.consecutive_failures.load(Ordering::Relaxed)
+            })
+        })
+        .collect();
+    Value::Array(endpoints)
+}
+
+// The subscription handshake, with RPC_HEADERS. Without RPC_WS_URL the HTTP endpoint is used with a
+// ws(s) scheme; Solana's own validators serve WebSockets one port above HTTP, so an explicit port
+// is bumped as well.
+pub(crate) fn rpc_ws_request() -> Result<tokio_tungstenite::tungstenite::handshake::client::Request, String> {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::http::{HeaderName, HeaderValue};
+
+    let url = match cluster().ws_url.as_deref() {
+        Some(url) => url.to_string(),
+        None => {
+            let mut url = reqwest::Url::parse(&next_rpc_endpoint().url).map_err(|e| e.to_string())?;
+            let scheme = if url.scheme() == "http" { "ws" } else { "wss" };
+            let _ = url.set_scheme(scheme);
+            if let Some(port) = url.port() {
+                let _ = url.set_port(Some(port.saturating_add(1)));
+            }
+            url.to_string()
+        }
+    };
+    let mut request = url.into_client_request().map_err(|e| format!("invalid RPC WebSocket URL: {}", e))?;
+    for (name, value) in RPC_HEADERS.iter() {
+        if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_str().as_bytes()), HeaderValue::from_bytes(value.as_bytes())) {
+            request.headers_mut().insert(name, value);
+        }
+    }
+    Ok(request)
+}
+
+// 429 and 5xx answers, and anything that isn't JSON, become RpcUnavailable with a snippet of the
+// body logged, since that is usually an HTML error page from the provider's proxy. Other
+// statuses with a JSON body are left for the JSON-RPC response to explain.
+pub(crate) async fn send_rpc_to(url: &str, body: &Value) -> Result<Value, ApiError> {
+    let mut headers = RPC_HEADERS.clone();
+    if let Some(traceparent) = outgoing_traceparent().and_then(|value| reqwest::header::HeaderValue::from_str(&value).ok()) {
+        headers.insert("traceparent", traceparent);
+    }
+    let resp = CLIENT.post(url).headers(headers).json(body).send().await?;
+    let status = resp.status();
+    let retry_after = resp.headers().get(reqwest::header::RETRY_AFTER).and_then(|v| v.to_str().ok()).and_then(parse_retry_after);
+    let content_type = resp.headers().get(reqwest::header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).unwrap_or("none").to_string();
+    let text = resp.text().await?;
+    match serde_json::from_str(&text) {
+        Ok(value) if !is_overloaded(status) => Ok(value),
+        _ => {
+            let snippet: String = text.split_whitespace().collect::<Vec<_>>().join(" ").chars().take(200).collect();
+            tracing::warn!("RPC answered HTTP {} ({}) without a JSON-RPC response: {}", status, content_type, snippet);
+            Err(ApiError::RpcUnavailable { status, retry_after })
+        }
+    }
+}
+
+// Retry-After is either a number of seconds or an HTTP date; a date in the past means now.
+pub(crate) fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = httpdate::parse_http_date(value).ok()?;
+    Some(at.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO))
+}
+
+pub(crate) fn is_retryable(e: &ApiError) -> bool {
+    match e {
+        ApiError::Http(e) => e.is_connect() || e.is_timeout(),
+        ApiError::RpcUnavailable { retry_after: Some(wait), .. } if *wait > RPC_RETRY_AFTER_MAX => false,
+        ApiError::RpcUnavailable { status, .. } => is_overloaded(*status),
+        _ => false,
+    }
+}
+
+// Whether a failed call can be sent again without risking the upstream applying it twice.
+pub(crate) fn is_safe_to_retry(method: &str, e: &ApiError) -> bool {
+    if !NON_IDEMPOTENT_RPC_METHODS.contains(&method) {
+        return true;
+    }
+    match e {
+        ApiError::Http(e) => e.is_connect(),
+        ApiError::RpcUnavailable { status, .. } => *status == StatusCode::TOO_MANY_REQUESTS,
+        _ => false,
+    }
+}
+
+// Exponential backoff with full jitter: a uniformly random delay up to the exponential bound,
+// so callers that failed together don't all retry at the same moment.
+pub(crate) fn retry_delay(attempt: u32) -> Duration {
+    let bound = RPC_RETRY_BASE_DELAY.saturating_mul(1 << attempt.min(16)).min(RPC_RETRY_MAX_DELAY);
+    if !*RPC_RETRY_JITTER {
+        return bound;
+    }
+    let mut rng = RETRY_RNG.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    Duration::from_millis(rand::Rng::random_range(&mut *rng, 0..=bound.as_millis() as u64))
+}
+
+// The provider's Retry-After is a floor under the backoff schedule.
+pub(crate) fn backoff_for(e: &ApiError, attempt: u32) -> Duration {
+    match e {
+        ApiError::RpcUnavailable { retry_after: Some(wait), .. } => retry_delay(attempt).max(*wait),
+        _ => retry_delay(attempt),
+    }
+}
+
+pub(crate) fn check_proxy_method(method: &str) -> Result<(), ApiError> {
+    let subscription = method.ends_with("Subscribe") || method.ends_with("Unsubscribe");
+    if subscription || ALWAYS_BLOCKED_PROXY_METHODS.contains(&method) || !RPC_PROXY_ALLOWED_METHODS.contains(method) {
+        return Err(ApiError::MethodNotAllowed(method.to_string()));
+    }
+    Ok(())
+}
+
+// JSON-RPC errors from the node are passed through as-is rather than turned into a 502, so
+// the proxy behaves like the node for any client library pointed at it.
+pub(crate) async fn proxy_rpc(method: &str, params: Value, id: Value) -> Result<Value, ApiError> {
+    let params = if params.is_null() { serde_json::json!([]) } else { params };
+    let mut resp = rpc_call(method, params).await?;
+    resp["id"] = id;
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_after_in_seconds() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 "), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn retry_after_as_http_date() {
+        let at = SystemTime::now() + Duration::from_secs(90);
+        let wait = parse_retry_after(&httpdate::fmt_http_date(at)).unwrap();
+        // HTTP dates have whole-second resolution.
+        assert!(wait <= Duration::from_secs(90) && wait >= Duration::from_secs(88), "{:?}", wait);
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn retry_after_garbage() {
+        assert_eq!(parse_retry_after(""), None);
+        assert_eq!(parse_retry_after("soon"), None);
+        assert_eq!(parse_retry_after("-5"), None);
+        assert_eq!(parse_retry_after("1.5"), None);
+    }
+
+    #[test]
+    fn backoff_never_undercuts_retry_after() {
+        let wait = RPC_RETRY_MAX_DELAY + Duration::from_secs(1);
+        let e = ApiError::RpcUnavailable { status: StatusCode::TOO_MANY_REQUESTS, retry_after: Some(wait) };
+        for attempt in 0..20 {
+            assert!(backoff_for(&e, attempt) >= wait);
+        }
+        let e = ApiError::RpcUnavailable { status: StatusCode::TOO_MANY_REQUESTS, retry_after: Some(Duration::ZERO) };
+        for attempt in 0..20 {
+            assert!(backoff_for(&e, attempt) <= RPC_RETRY_MAX_DELAY);
+        }
+    }
+
+    fn half_open_breaker() -> CircuitBreaker {
+        let breaker = CircuitBreaker::default();
+        let opened_at = unix_millis() - RPC_CIRCUIT_OPEN.as_millis() as u64 - 1;
+        breaker.opened_at_ms.store(opened_at, Ordering::Relaxed);
+        breaker
+    }
+
+    #[test]
+    fn half_open_breaker_admits_one_probe() {
+        let breaker = half_open_breaker();
+        assert!(breaker.state() == CircuitState::HalfOpen);
+        let probe = breaker.admit().unwrap();
+        assert!(probe.is_some());
+        assert!(matches!(breaker.admit(), Err(ApiError::CircuitOpen { retry_after }) if retry_after == RPC_CIRCUIT_PROBE_WAIT));
+
+        // A probe that ends without being recorded, e.g. a cancelled request, frees the slot.
+        drop(probe);
+        let probe = breaker.admit().unwrap();
+        assert!(probe.is_some());
+        breaker.record(&Ok(Value::Null), true);
+        drop(probe);
+        assert!(breaker.state() == CircuitState::Closed);
+        assert!(breaker.admit().unwrap().is_none());
+    }
+
+    #[test]
+    fn only_the_probe_closes_the_breaker() {
+        // Calls admitted while the breaker was still closed can finish after it opened.
+        let breaker = half_open_breaker();
+        breaker.record(&Ok(Value::Null), false);
+        assert!(breaker.state() == CircuitState::HalfOpen);
+
+        let breaker = CircuitBreaker::default();
+        breaker.opened_at_ms.store(unix_millis(), Ordering::Relaxed);
+        breaker.record(&Ok(Value::Null), false);
+        assert!(matches!(breaker.state(), CircuitState::Open(_)));
+    }
+
+    #[test]
+    fn failed_probe_reopens_the_breaker() {
+        let breaker = half_open_breaker();
+        let probe = breaker.admit().unwrap();
+        breaker.record(&Err(ApiError::RpcUnavailable { status: StatusCode::BAD_GATEWAY, retry_after: None }), true);
+        drop(probe);
+        assert!(matches!(breaker.state(), CircuitState::Open(_)));
+        assert!(breaker.admit().is_err());
+    }
+}
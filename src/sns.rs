@@ -0,0 +1,194 @@
+//! Solana Name Service lookups: .sol domains to owners and back.
+
+use crate::*;
+
+// Recent domain owners and primary domains, with the time they were looked up. A wallet with no
+// primary domain is remembered as None; a domain that doesn't exist is not remembered.
+pub(crate) type SnsCache<T> = PerCluster<RwLock<HashMap<String, (T, SystemTime)>>>;
+pub(crate) static SNS_OWNERS: SnsCache<String> = PerCluster::new(|| RwLock::new(HashMap::new()));
+pub(crate) static PRIMARY_DOMAINS: SnsCache<Option<String>> = PerCluster::new(|| RwLock::new(HashMap::new()));
+
+pub(crate) async fn cached_sns<T: Clone>(cache: &RwLock<HashMap<String, (T, SystemTime)>>, key: &str) -> Option<T> {
+    let hit = cache
+        .read()
+        .await
+        .get(key)
+        .filter(|(_, fetched)| fetched.elapsed().unwrap_or(SNS_CACHE_TTL) < SNS_CACHE_TTL)
+        .map(|(value, _)| value.clone());
+    record_cache_lookup("sns", hit.is_some());
+    hit
+}
+
+pub(crate) async fn remember_sns<T>(cache: &RwLock<HashMap<String, (T, SystemTime)>>, key: &str, value: T) {
+    let mut cache = cache.write().await;
+    cache.retain(|_, (_, fetched)| fetched.elapsed().unwrap_or(SNS_CACHE_TTL) < SNS_CACHE_TTL);
+    cache.insert(key.to_string(), (value, SystemTime::now()));
+}
+
+pub(crate) fn sns_name_account(label: &str, parent: &[u8; 32]) -> Option<[u8; 32]> {
+    sns_class_account(label, &[0u8; 32], parent)
+}
+pub(crate) fn sns_class_account(label: &str, class: &[u8; 32], parent: &[u8; 32]) -> Option<[u8; 32]> {
+    let hashed_name = Sha256::digest(format!("{}{}", NAME_HASH_PREFIX, label));
+    let program_id = parse_pubkey(NAME_PROGRAM_ID)?;
+    find_program_address(&[hashed_name.as_slice(), class, parent], &program_id)
+}
+
+// "bonfida.sol" hangs off the .sol TLD; "dex.bonfida.sol" is a subdomain of "bonfida.sol",
+// and subdomain labels are hashed with a leading NUL byte.
+pub(crate) fn sns_domain_key(domain: &str) -> Option<[u8; 32]> {
+    let name = domain.strip_suffix(".sol")?;
+    let mut labels = name.split('.').rev();
+    let root = labels.next().filter(|l| !l.is_empty())?;
+    let mut key = sns_name_account(root, &parse_pubkey(SOL_TLD_AUTHORITY)?)?;
+    for label in labels {
+        if label.is_empty() {
+            return None;
+        }
+        key = sns_name_account(&format!("\0{}", label), &key)?;
+    }
+    Some(key)
+}
+
+// Name registry accounts start with a 96-byte header: parent name, owner, class.
+pub(crate) async fn resolve_sns(domain: &str) -> Result<String, ApiError> {
+    if let Some(owner) = cached_sns(&SNS_OWNERS, domain).await {
+        return Ok(owner);
+    }
+    let not_found = || ApiError::NotFound("Domain not found".to_string());
+    let name = domain.to_string();
+    let key = run_blocking(move || sns_domain_key(&name)).await.ok_or_else(not_found)?;
+    let data = fetch_account_data(&bs58::encode(key).into_string()).await.map_err(|e| match e {
+        ApiError::NotFound(_) => not_found(),
+        e => e,
+    })?;
+    let owner = data.get(32..64).map(|owner| bs58::encode(owner).into_string()).ok_or_else(not_found)?;
+    remember_sns(&SNS_OWNERS, domain, owner.clone()).await;
+    Ok(owner)
+}
+
+// Resolves many .sol names with one getMultipleAccounts call. Names that don't derive a key or
+// whose name account doesn't exist map to null; pubkeys resolve to themselves as in /resolve.
+pub(crate) async fn resolve_sns_batch(names: Vec<String>) -> Result<Value, ApiError> {
+    let mut domains: Vec<String> = names.iter().filter(|name| name.ends_with(".sol")).cloned().collect();
+    domains.sort();
+    domains.dedup();
+    let derived = run_blocking(move || {
+        domains
+            .into_iter()
+            .filter_map(|domain| sns_domain_key(&domain).map(|key| (domain, bs58::encode(key).into_string())))
+            .collect::<Vec<_>>()
+    })
+    .await;
+
+    let mut owners = serde_json::Map::new();
+    for name in &names {
+        let owner = if parse_pubkey(name).is_some() { Value::String(name.clone()) } else { Value::Null };
+        owners.insert(name.clone(), owner);
+    }
+    if derived.is_empty() {
+        return Ok(Value::Object(owners));
+    }
+    let keys: Vec<String> = derived.iter().map(|(_, key)| key.clone()).collect();
+    let accounts = get_multiple_accounts(&keys, true).await?;
+    for ((domain, _), account) in derived.iter().zip(accounts.as_array().into_iter().flatten()) {
+        let owner = account["data"]
+            .as_str()
+            .and_then(|data| base64::engine::general_purpose::STANDARD.decode(data).ok())
+            .and_then(|data| data.get(32..64).map(|owner| bs58::encode(owner).into_string()));
+        if let Some(owner) = owner {
+            owners.insert(domain.clone(), Value::String(owner));
+        }
+    }
+    Ok(Value::Object(owners))
+}
+
+// Text of a name account from its reverse lookup account: a 96-byte name header followed by a
+// length-prefixed string. Subdomain reverse accounts are parented to the parent domain.
+pub(crate) async fn sns_reverse_name(name_account: [u8; 32], parent: [u8; 32]) -> Result<Option<String>, ApiError> {
+    let Some(class) = parse_pubkey(REVERSE_LOOKUP_CLASS) else {
+        return Ok(None);
+    };
+    let label = bs58::encode(name_account).into_string();
+    let Some(reverse_key) = run_blocking(move || sns_class_account(&label, &class, &parent)).await else {
+        return Ok(None);
+    };
+    let data = match fetch_account_data(&bs58::encode(reverse_key).into_string()).await {
+        Ok(data) => data,
+        Err(ApiError::NotFound(_)) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let Some(len) = data.get(96..100).map(|len| u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize) else {
+        return Ok(None);
+    };
+    Ok(data.get(100..100 + len).map(|name| String::from_utf8_lossy(name).trim_start_matches('\0').to_string()))
+}
+
+// The wallet's primary ("favourite") .sol domain, or None when it has not set one or no longer
+// owns the domain it set.
+pub(crate) async fn reverse_sns(wallet: &str) -> Result<Option<String>, ApiError> {
+    if let Some(domain) = cached_sns(&PRIMARY_DOMAINS, wallet).await {
+        return Ok(domain);
+    }
+    let domain = lookup_primary_domain(wallet).await?;
+    remember_sns(&PRIMARY_DOMAINS, wallet, domain.clone()).await;
+    Ok(domain)
+}
+
+pub(crate) async fn lookup_primary_domain(wallet: &str) -> Result<Option<String>, ApiError> {
+    let (Some(owner), Some(offers_program)) = (parse_pubkey(wallet), parse_pubkey(NAME_OFFERS_PROGRAM_ID)) else {
+        return Ok(None);
+    };
+    let Some(favourite_key) =
+        run_blocking(move || find_program_address(&[b"favourite_domain", &owner], &offers_program)).await
+    else {
+        return Ok(None);
+    };
+    let not_found_as_none = |result: Result<Vec<u8>, ApiError>| match result {
+        Ok(data) => Ok(Some(data)),
+        Err(ApiError::NotFound(_)) => Ok(None),
+        Err(e) => Err(e),
+    };
+
+    let Some(favourite) = not_found_as_none(fetch_account_data(&bs58::encode(favourite_key).into_string()).await)? else {
+        return Ok(None);
+    };
+    let Some(name_account) = favourite.get(1..33).and_then(|key| <[u8; 32]>::try_from(key).ok()) else {
+        return Ok(None);
+    };
+    let Some(name_data) = not_found_as_none(fetch_account_data(&bs58::encode(name_account).into_string()).await)? else {
+        return Ok(None);
+    };
+    let (Some(parent), Some(domain_owner)) = (name_data.get(0..32), name_data.get(32..64)) else {
+        return Ok(None);
+    };
+    if domain_owner != owner.as_slice() {
+        return Ok(None);
+    }
+    let parent = <[u8; 32]>::try_from(parent).unwrap_or([0u8; 32]);
+
+    if Some(parent) == parse_pubkey(SOL_TLD_AUTHORITY) {
+        let name = sns_reverse_name(name_account, [0u8; 32]).await?;
+        return Ok(name.map(|name| format!("{}.sol", name)));
+    }
+    let (Some(sub), Some(parent_name)) =
+        (sns_reverse_name(name_account, parent).await?, sns_reverse_name(parent, [0u8; 32]).await?)
+    else {
+        return Ok(None);
+    };
+    Ok(Some(format!("{}.{}.sol", sub, parent_name)))
+}
+
+// Accepts either a pubkey or a .sol domain. Every result is checked against the blocklist: the
+// filter in front of the routes only sees path segments, not a domain's owner or a wallet given
+// in a request body.
+pub(crate) async fn resolve_wallet(input: &str) -> Result<String, ApiError> {
+    let wallet = match input.ends_with(".sol") {
+        true => resolve_sns(input).await?,
+        false => input.to_string(),
+    };
+    if BLOCKED_ADDRESSES.contains(&wallet) {
+        return Err(ApiError::Blocked);
+    }
+    Ok(wallet)
+}
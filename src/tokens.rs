@@ -0,0 +1,1793 @@
+//! SPL token balances, the token list, prices, holder counts and NFT metadata.
+
+use crate::*;
+
+// Set by the first successful token map refresh; /ready reports not ready until then.
+pub(crate) static TOKEN_MAP_LOADED: PerCluster<AtomicBool> = PerCluster::new(|| AtomicBool::new(false));
+
+// (tokens by mint, last refresh, generation). The generation only moves when a refresh
+// actually changes the map, so clients can use it to invalidate data derived from it.
+pub(crate) type TokenMapCache = (HashMap<String, Value>, SystemTime, u64);
+
+pub(crate) static TOKEN_MAP: PerCluster<RwLock<TokenMapCache>> =
+    PerCluster::new(|| RwLock::new((HashMap::new(), SystemTime::now(), 0)));
+
+// Mint decimals never change, so entries are kept for the lifetime of the process.
+pub(crate) static DECIMALS_CACHE: PerCluster<RwLock<HashMap<String, u8>>> = PerCluster::new(|| RwLock::new(HashMap::new()));
+
+// Holder counts by mint. Each one is a full getProgramAccounts scan, so they are kept for
+// HOLDER_COUNT_TTL. A count asked for within STALE_WHILE_REVALIDATE after that is served stale
+// and recounted in the background; there is no refresh loop, so unrequested counts just expire.
+pub(crate) static HOLDER_COUNTS: PerCluster<RwLock<HashMap<String, (u64, SystemTime)>>> = PerCluster::new(|| RwLock::new(HashMap::new()));
+// Mints being recounted in the background, so a burst of stale hits starts only one scan.
+pub(crate) static HOLDER_COUNT_REFRESHES: RefreshSet = PerCluster::new(Default::default);
+
+// USD prices by mint with the time they were fetched, per cluster since a mint address only
+// names a priced token on the cluster it was looked up for. Mints the source has no price for
+// are not cached and are asked for again on the next request.
+pub(crate) static PRICE_CACHE: PerCluster<RwLock<HashMap<String, (Price, SystemTime)>>> = PerCluster::new(|| RwLock::new(HashMap::new()));
+pub(crate) static PRICE_SOURCE: Lazy<PriceSources> = Lazy::new(PriceSources::from_env);
+
+// Token metadata by mint from the on-chain and DAS sources, None where no source had any.
+pub(crate) type MetadataCache = PerCluster<RwLock<HashMap<String, (Option<Value>, SystemTime)>>>;
+pub(crate) static TOKEN_METADATA_CACHE: MetadataCache = PerCluster::new(|| RwLock::new(HashMap::new()));
+
+// Fetches the off-chain JSON of NFTs. The URIs come from whoever minted the NFT, so this gets the
+// same no-redirect, bounded-time treatment as webhook deliveries.
+pub(crate) static OFFCHAIN_METADATA_CLIENT: Lazy<Client> = Lazy::new(|| {
+    Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(WEBHOOK_TIMEOUT)
+        .build()
+        .expect("failed to build off-chain metadata client")
+});
+// NFT_SOURCE=das reads NFTs through the Digital Asset Standard API (getAssetsByOwner), which only
+// some RPC providers serve. The default derives Metaplex metadata accounts with plain RPC calls.
+pub(crate) static NFT_SOURCE: Lazy<NftSource> = Lazy::new(NftSource::from_env);
+
+pub(crate) const MOCK_TOKEN_LIST: &str = include_str!("fixtures/tokenlist.json");
+pub(crate) const MOCK_PRICES: &str = include_str!("fixtures/prices.json");
+
+// GitHub answers rate-limited requests with an error page or an error JSON object, so a
+// response only replaces the cached map when it really contains a non-empty token list.
+pub(crate) fn parse_token_list(token_list: &Value) -> Option<HashMap<String, Value>> {
+    let tokens = token_list["tokens"].as_array().filter(|tokens| !tokens.is_empty())?;
+    let mut token_map = HashMap::new();
+    for token in tokens {
+        if let Some(mint) = token["address"].as_str() {
+            token_map.insert(mint.to_string(), token.clone());
+        }
+    }
+    Some(token_map).filter(|map| !map.is_empty())
+}
+
+pub(crate) async fn fetch_token_list() -> Result<Option<Value>, reqwest::Error> {
+    if *MOCK_MODE {
+        return Ok(Some(serde_json::from_str(MOCK_TOKEN_LIST).expect("embedded fixture is valid JSON")));
+    }
+
+    let resp = CLIENT.get(&cluster().token_list_url).send().await?;
+    let status = resp.status();
+    if !status.is_success() {
+        tracing::warn!("token list refresh failed: HTTP {}", status);
+        return Ok(None);
+    }
+    match resp.json().await {
+        Ok(token_list) => Ok(Some(token_list)),
+        Err(e) => {
+            tracing::warn!("token list refresh failed: invalid JSON body: {}", e);
+            Ok(None)
+        }
+    }
+}
+
+// Fields in an override entry replace the upstream entry's fields; everything else is kept. The
+// file is re-read on each refresh, and a missing or invalid file leaves the upstream map as is.
+pub(crate) async fn apply_token_overrides(token_map: &mut HashMap<String, Value>, path: &str) {
+    let overrides = match tokio::fs::read_to_string(path).await {
+        Ok(contents) => serde_json::from_str::<serde_json::Map<String, Value>>(&contents).map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    let overrides = match overrides {
+        Ok(overrides) => overrides,
+        Err(e) => {
+            tracing::warn!("token overrides from {} not applied: {}", path, e);
+            return;
+        }
+    };
+    for (mint, fields) in overrides {
+        let Value::Object(fields) = fields else {
+            tracing::warn!("token override for {} is not an object, skipping", mint);
+            continue;
+        };
+        let entry = token_map.entry(mint.clone()).or_insert_with(|| serde_json::json!({ "address": mint }));
+        for (field, value) in fields {
+            entry[field] = value;
+        }
+    }
+}
+
+// A token list another replica fetched less than TOKEN_MAP_TTL ago is taken from the shared
+// cache instead, so replicas behind one Redis download it once per TTL between them. Overrides
+// are applied on top by each replica, from its own TOKEN_OVERRIDES_FILE.
+pub(crate) async fn refresh_token_map() -> Result<(), ApiError> {
+    let shared = shared_json_get("token-list", TOKEN_MAP_TTL).await;
+    record_cache_lookup("token_list", shared.is_some());
+    let (token_list, shared_fetched) = match shared {
+        Some((token_list, fetched)) => (token_list, Some(fetched)),
+        None => match fetch_token_list().await {
+            Ok(Some(token_list)) => (token_list, None),
+            Ok(None) => {
+                TOKEN_MAP_REFRESH_FAILURES.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+            Err(e) => {
+                TOKEN_MAP_REFRESH_FAILURES.fetch_add(1, Ordering::Relaxed);
+                return Err(e.into());
+            }
+        },
+    };
+    store_token_list(&token_list, shared_fetched).await;
+    Ok(())
+}
+
+// Replaces TOKEN_MAP with `token_list`, unless it holds no tokens (a rate-limit or error body),
+// in which case the cached map is kept. `shared_fetched` is when another replica fetched it.
+pub(crate) async fn store_token_list(token_list: &Value, shared_fetched: Option<SystemTime>) {
+    let Some(mut token_map) = parse_token_list(token_list) else {
+        tracing::warn!("token list refresh failed: response has no tokens, keeping the cached map");
+        TOKEN_MAP_REFRESH_FAILURES.fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+    let fetched = match shared_fetched {
+        Some(fetched) => fetched,
+        None => {
+            let now = SystemTime::now();
+            shared_json_set("token-list", token_list, now, TOKEN_MAP_TTL).await;
+            now
+        }
+    };
+    if let Some(path) = TOKEN_OVERRIDES_FILE.as_deref() {
+        apply_token_overrides(&mut token_map, path).await;
+    }
+
+    let decimals = token_map
+        .iter()
+        .filter_map(|(mint, token)| token["decimals"].as_u64().and_then(valid_decimals).map(|d| (mint.clone(), d)))
+        .collect();
+    let mut cache = TOKEN_MAP.write().await;
+    let generation = if cache.0 == token_map { cache.2 } else { cache.2 + 1 };
+    *cache = (token_map, fetched, generation);
+    drop(cache);
+    TOKEN_MAP_LOADED.store(true, Ordering::Relaxed);
+    cache_decimals(decimals).await;
+}
+
+pub(crate) async fn get_token_map() -> Result<HashMap<String, Value>, ApiError> {
+    let started = Instant::now();
+    let token_map = load_token_map().await;
+    let _ = REQUEST_TRACE.try_with(|trace| trace.borrow_mut().token_map += started.elapsed());
+    token_map
+}
+
+pub(crate) async fn load_token_map() -> Result<HashMap<String, Value>, ApiError> {
+    let cache = TOKEN_MAP.read().await;
+    let stale = cache.0.is_empty() || cache.1.elapsed().unwrap_or(Duration::from_secs(0)) > TOKEN_MAP_TTL;
+    if stale && !*BACKGROUND_REFRESH {
+        drop(cache);
+        refresh_token_map().await?;
+    }
+    Ok(TOKEN_MAP.read().await.0.clone())
+}
+
+pub(crate) async fn token_map_generation() -> u64 {
+    TOKEN_MAP.read().await.2
+}
+
+pub(crate) async fn get_token_map_meta() -> Value {
+    let cache = TOKEN_MAP.read().await;
+    let last_refresh = cache.1.duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    serde_json::json!({
+        "token_count": cache.0.len(),
+        "last_refresh": if cache.2 == 0 { Value::Null } else { last_refresh.into() },
+        "generation": cache.2
+    })
+}
+
+pub(crate) async fn cache_decimals(entries: Vec<(String, u8)>) {
+    if entries.is_empty() {
+        return;
+    }
+    let mut cache = DECIMALS_CACHE.write().await;
+    for (mint, decimals) in entries {
+        cache.entry(mint).or_insert(decimals);
+    }
+}
+
+pub(crate) fn valid_decimals(decimals: u64) -> Option<u8> {
+    (decimals <= MAX_TOKEN_DECIMALS).then_some(decimals as u8)
+}
+
+pub(crate) async fn decimals_for(mint: &str) -> Option<u8> {
+    if let Some(decimals) = DECIMALS_CACHE.read().await.get(mint) {
+        return Some(*decimals);
+    }
+
+    let resp = rpc_call("getTokenSupply", serde_json::json!([mint])).await;
+
+    // Failures are not cached so the next lookup retries the RPC.
+    match resp.ok().and_then(|r| extract_value(&r).ok().and_then(|v| v["decimals"].as_u64()).and_then(valid_decimals)) {
+        Some(decimals) => {
+            DECIMALS_CACHE.write().await.insert(mint.to_string(), decimals);
+            Some(decimals)
+        }
+        None => None,
+    }
+}
+
+// Only the 8-byte amount of each account is fetched, from both token programs; no metadata,
+// decimals or fees are looked up.
+pub(crate) async fn count_token_accounts(wallet: &str) -> Result<Value, ApiError> {
+    let params = |program_id: &str| {
+        serde_json::json!([
+            wallet,
+            { "programId": program_id },
+            { "encoding": "base64", "dataSlice": { "offset": TOKEN_ACCOUNT_AMOUNT_OFFSET, "length": 8 } }
+        ])
+    };
+    let (legacy_resp, token_2022_resp) = tokio::join!(
+        rpc_call("getTokenAccountsByOwner", params(TOKEN_PROGRAM_ID)),
+        rpc_call("getTokenAccountsByOwner", params(TOKEN_2022_PROGRAM_ID))
+    );
+
+    let (mut total, mut nonzero) = (0, 0);
+    for resp in [legacy_resp?, token_2022_resp?] {
+        for account in extract_value(&resp)?.as_array().into_iter().flatten() {
+            total += 1;
+            let amount = account["account"]["data"][0]
+                .as_str()
+                .and_then(|data| base64::engine::general_purpose::STANDARD.decode(data).ok());
+            if amount.is_some_and(|amount| amount.iter().any(|b| *b != 0)) {
+                nonzero += 1;
+            }
+        }
+    }
+    Ok(serde_json::json!({ "wallet": wallet, "total_accounts": total, "nonzero_accounts": nonzero }))
+}
+
+// Holdings across both the legacy SPL Token program and Token-2022.
+// Returns the enriched token accounts and how many accounts were skipped for missing fields.
+pub(crate) async fn get_spl_tokens(wallet: &str) -> Result<(Value, usize), ApiError> {
+    let params = |program_id: &str| {
+        serde_json::json!([
+            wallet,
+            { "programId": program_id },
+            { "encoding": "jsonParsed" }
+        ])
+    };
+
+    let (legacy_resp, token_2022_resp) = tokio::join!(
+        rpc_call("getTokenAccountsByOwner", params(TOKEN_PROGRAM_ID)),
+        rpc_call("getTokenAccountsByOwner", params(TOKEN_2022_PROGRAM_ID))
+    );
+
+    let mut token_accounts = Vec::new();
+    for resp in [legacy_resp?, token_2022_resp?] {
+        token_accounts.extend(extract_value(&resp)?.as_array().cloned().unwrap_or_default());
+    }
+
+    let mut mints: Vec<String> = token_accounts
+        .iter()
+        .filter_map(|account| account["account"]["data"]["parsed"]["info"]["mint"].as_str().map(str::to_string))
+        .collect();
+    mints.sort();
+    mints.dedup();
+    let token_map = token_metadata(&mints).await?;
+
+    let mut token_2022_mints: Vec<String> = token_accounts
+        .iter()
+        .filter(|account| account["account"]["owner"] == TOKEN_2022_PROGRAM_ID)
+        .filter_map(|account| account["account"]["data"]["parsed"]["info"]["mint"].as_str().map(str::to_string))
+        .collect();
+    token_2022_mints.sort();
+    token_2022_mints.dedup();
+    // Fees are extra detail; a failed lookup shouldn't cost the caller their balances.
+    let transfer_fees = if token_2022_mints.is_empty() {
+        HashMap::new()
+    } else {
+        transfer_fees(&token_2022_mints).await.unwrap_or_else(|e| {
+            tracing::warn!("Failed to fetch Token-2022 transfer fees: {}", e);
+            HashMap::new()
+        })
+    };
+
+    // Parsed accounts almost always carry decimals; resolve the rare gaps up front so the
+    // enrichment itself can run on the blocking pool.
+    let mut fallback_decimals = HashMap::new();
+    for account in &token_accounts {
+        let info = &account["account"]["data"]["parsed"]["info"];
+        if let (Some(mint), None) = (info["mint"].as_str(), info["tokenAmount"]["decimals"].as_u64())
+            && !fallback_decimals.contains_key(mint)
+        {
+            fallback_decimals.insert(mint.to_string(), decimals_for(mint).await);
+        }
+    }
+
+    let (enriched_tokens, seen_decimals, skipped) =
+        run_blocking(move || enrich_token_accounts(token_accounts, &token_map, &fallback_decimals, &transfer_fees)).await;
+    cache_decimals(seen_decimals).await;
+
+    Ok((serde_json::json!(enriched_tokens), skipped))
+}
+
+// The transfer fee that applies to each Token-2022 mint with the transfer-fee extension. A fee
+// change is scheduled by epoch, so the older fee still applies until the newer one's epoch.
+pub(crate) async fn transfer_fees(mints: &[String]) -> Result<HashMap<String, Value>, ApiError> {
+    let epoch = get_epoch_info().await.ok().and_then(|info| info["epoch"].as_u64());
+    let mut fees = HashMap::new();
+    for chunk in mints.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        let resp = rpc_call("getMultipleAccounts", serde_json::json!([chunk, { "encoding": "jsonParsed" }])).await?;
+        let accounts = extract_value(&resp)?.as_array().cloned().unwrap_or_default();
+        for (mint, account) in chunk.iter().zip(accounts) {
+            let extensions = account["data"]["parsed"]["info"]["extensions"].as_array().cloned().unwrap_or_default();
+            let Some(config) = extensions.iter().find(|e| e["extension"] == "transferFeeConfig") else {
+                continue;
+            };
+            let newer = &config["state"]["newerTransferFee"];
+            let fee = match (epoch, newer["epoch"].as_u64()) {
+                (Some(epoch), Some(starts)) if epoch < starts => &config["state"]["olderTransferFee"],
+                _ => newer,
+            };
+            fees.insert(
+                mint.clone(),
+                serde_json::json!({
+                    "basis_points": fee["transferFeeBasisPoints"],
+                    "maximum_fee": fee["maximumFee"]
+                }),
+            );
+        }
+    }
+    Ok(fees)
+}
+
+// Accounts without a mint, a raw amount, or known decimals are skipped rather than reported as
+// a zero balance; the number skipped is returned alongside the tokens.
+pub(crate) fn enrich_token_accounts(
+    token_accounts: Vec<Value>,
+    token_map: &HashMap<String, Value>,
+    fallback_decimals: &HashMap<String, Option<u8>>,
+    transfer_fees: &HashMap<String, Value>,
+) -> (Vec<Value>, Vec<(String, u8)>, usize) {
+    let mut enriched_tokens = Vec::with_capacity(token_accounts.len());
+    let mut seen_decimals = Vec::new();
+    let mut skipped = 0;
+    for account in token_accounts {
+        let info = &account["account"]["data"]["parsed"]["info"];
+        let mint = info["mint"].as_str();
+        let raw_amount = info["tokenAmount"]["amount"].as_str().and_then(|amount| amount.parse::<f64>().ok());
+        let decimals = match (mint, info["tokenAmount"]["decimals"].as_u64()) {
+            (Some(mint), Some(d)) => {
+                if let Some(d) = valid_decimals(d) {
+                    seen_decimals.push((mint.to_string(), d));
+                }
+                Some(d)
+            }
+            (Some(mint), None) => fallback_decimals.get(mint).copied().flatten().map(u64::from),
+            (None, _) => None,
+        };
+        let (Some(mint), Some(raw_amount), Some(decimals)) = (mint, raw_amount, decimals) else {
+            skipped += 1;
+            continue;
+        };
+        // The node's uiAmountString is exact; dividing the raw amount ourselves is only a fallback.
+        // With out-of-range decimals neither can be trusted, so the raw amount is reported as is.
+        let decimals_valid = valid_decimals(decimals).is_some();
+        let ui_amount_string = info["tokenAmount"]["uiAmountString"].as_str().filter(|_| decimals_valid);
+        let amount = match ui_amount_string.and_then(|ui| ui.parse::<f64>().ok()) {
+            Some(amount) => amount,
+            None if decimals_valid => raw_amount / 10f64.powi(decimals as i32),
+            None => raw_amount,
+        };
+
+        let program = if account["account"]["owner"] == TOKEN_2022_PROGRAM_ID { "token-2022" } else { "token" };
+        if !decimals_valid {
+            tracing::warn!("token account for mint {} claims {} decimals, reporting the raw amount", mint, decimals);
+        }
+        let metadata = token_map.get(mint);
+        let metadata_field = |key: &str| metadata.and_then(|metadata| metadata[key].as_str()).map(str::to_string);
+        let token_info = TokenBalance {
+            mint: mint.to_string(),
+            amount,
+            decimals,
+            program: program.to_string(),
+            ui_amount_string: ui_amount_string.map(str::to_string),
+            symbol: metadata_field("symbol"),
+            name: metadata_field("name"),
+            logo_uri: metadata_field("logoURI"),
+            extensions: info["extensions"].as_array().map(|extensions| extensions.iter().map(|e| e["extension"].clone()).collect()),
+            transfer_fee: transfer_fees.get(mint).cloned(),
+            warning: (!decimals_valid).then(|| format!("decimals {} is out of range; amount is in raw base units", decimals)),
+        };
+        let token_info = serde_json::json!(token_info);
+
+        enriched_tokens.push(token_info);
+    }
+    (enriched_tokens, seen_decimals, skipped)
+}
+
+// A USD price and, when the source reports one, the change over the last 24 hours in percent.
+#[derive(Clone, Copy)]
+pub(crate) struct Price {
+    pub(crate) usd: f64,
+    pub(crate) change_24h_percent: Option<f64>,
+}
+
+// A provider of USD prices. Mints the source has no price for are left out of the result.
+pub(crate) trait PriceSource {
+    async fn prices(&self, mints: &[String]) -> Result<HashMap<String, Price>, ApiError>;
+}
+
+pub(crate) struct Jupiter;
+
+impl PriceSource for Jupiter {
+    async fn prices(&self, mints: &[String]) -> Result<HashMap<String, Price>, ApiError> {
+        let mut prices = HashMap::new();
+        for chunk in mints.chunks(JUPITER_MAX_IDS) {
+            let resp: Value = CLIENT
+                .get(JUPITER_PRICE_URL)
+                .query(&[("ids", chunk.join(","))])
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            for mint in chunk {
+                if let Some(usd) = resp[mint]["usdPrice"].as_f64() {
+                    prices.insert(mint.clone(), Price { usd, change_24h_percent: resp[mint]["priceChange24h"].as_f64() });
+                }
+            }
+        }
+        Ok(prices)
+    }
+}
+
+pub(crate) struct CoinGecko;
+
+impl PriceSource for CoinGecko {
+    async fn prices(&self, mints: &[String]) -> Result<HashMap<String, Price>, ApiError> {
+        let mut prices = HashMap::new();
+        for chunk in mints.chunks(COINGECKO_MAX_ADDRESSES) {
+            let resp: Value = CLIENT
+                .get(COINGECKO_PRICE_URL)
+                .query(&[
+                    ("contract_addresses", chunk.join(",").as_str()),
+                    ("vs_currencies", "usd"),
+                    ("include_24hr_change", "true"),
+                ])
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            // Addresses may come back lowercased, which base58 mints don't survive.
+            for mint in chunk {
+                let entry = resp.get(mint.as_str()).or_else(|| resp.get(mint.to_lowercase().as_str()));
+                if let Some(entry) = entry
+                    && let Some(usd) = entry["usd"].as_f64()
+                {
+                    prices.insert(mint.clone(), Price { usd, change_24h_percent: entry["usd_24h_change"].as_f64() });
+                }
+            }
+        }
+        Ok(prices)
+    }
+}
+
+// Serves src/fixtures/prices.json in MOCK_MODE.
+pub(crate) struct MockPrices;
+
+impl PriceSource for MockPrices {
+    async fn prices(&self, mints: &[String]) -> Result<HashMap<String, Price>, ApiError> {
+        let fixture: Value = serde_json::from_str(MOCK_PRICES).expect("embedded fixture is valid JSON");
+        Ok(mints
+            .iter()
+            .filter_map(|mint| {
+                let price = Price { usd: fixture[mint]["usd"].as_f64()?, change_24h_percent: fixture[mint]["change_24h_percent"].as_f64() };
+                Some((mint.clone(), price))
+            })
+            .collect())
+    }
+}
+
+pub(crate) enum PriceProvider {
+    Jupiter(Jupiter),
+    CoinGecko(CoinGecko),
+    Mock(MockPrices),
+}
+
+impl PriceProvider {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            PriceProvider::Jupiter(_) => "jupiter",
+            PriceProvider::CoinGecko(_) => "coingecko",
+            PriceProvider::Mock(_) => "mock",
+        }
+    }
+}
+
+impl PriceSource for PriceProvider {
+    async fn prices(&self, mints: &[String]) -> Result<HashMap<String, Price>, ApiError> {
+        match self {
+            PriceProvider::Jupiter(source) => source.prices(mints).await,
+            PriceProvider::CoinGecko(source) => source.prices(mints).await,
+            PriceProvider::Mock(source) => source.prices(mints).await,
+        }
+    }
+}
+
+// PRICE_SOURCE lists providers to try in order, comma-separated (default "jupiter,coingecko").
+// Mints one provider has no price for, or all of them if it fails, are asked of the next.
+pub(crate) struct PriceSources(pub(crate) Vec<PriceProvider>);
+
+impl PriceSources {
+    pub(crate) fn from_env() -> Self {
+        if *MOCK_MODE {
+            return PriceSources(vec![PriceProvider::Mock(MockPrices)]);
+        }
+        let names = std::env::var("PRICE_SOURCE").unwrap_or_else(|_| "jupiter,coingecko".to_string());
+        let mut providers = Vec::new();
+        for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match name {
+                "jupiter" => providers.push(PriceProvider::Jupiter(Jupiter)),
+                "coingecko" => providers.push(PriceProvider::CoinGecko(CoinGecko)),
+                other => tracing::warn!("Unknown PRICE_SOURCE {:?}, ignored", other),
+            }
+        }
+        if providers.is_empty() {
+            providers.push(PriceProvider::Jupiter(Jupiter));
+        }
+        PriceSources(providers)
+    }
+}
+
+impl PriceSource for PriceSources {
+    async fn prices(&self, mints: &[String]) -> Result<HashMap<String, Price>, ApiError> {
+        let mut prices = HashMap::new();
+        let mut last_error = None;
+        for provider in &self.0 {
+            let missing: Vec<String> = mints.iter().filter(|mint| !prices.contains_key(*mint)).cloned().collect();
+            if missing.is_empty() {
+                break;
+            }
+            match provider.prices(&missing).await {
+                Ok(found) => prices.extend(found),
+                Err(e) => {
+                    tracing::warn!("Failed to fetch prices from {}: {}", provider.name(), e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        match last_error {
+            Some(e) if prices.is_empty() => Err(e),
+            _ => Ok(prices),
+        }
+    }
+}
+
+// Cached prices where fresh; the rest are fetched from `source` in one call.
+pub(crate) async fn usd_prices(source: &impl PriceSource, mints: &[String]) -> Result<HashMap<String, Price>, ApiError> {
+    let mut prices = HashMap::new();
+    let mut missing = Vec::new();
+    {
+        let cache = PRICE_CACHE.read().await;
+        for mint in mints {
+            match cache.get(mint) {
+                Some((price, fetched)) if fetched.elapsed().unwrap_or(PRICE_TTL) < PRICE_TTL => {
+                    record_cache_lookup("prices", true);
+                    prices.insert(mint.clone(), *price);
+                }
+                _ => {
+                    record_cache_lookup("prices", false);
+                    missing.push(mint.clone());
+                }
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        let fetched = source.prices(&missing).await?;
+        let now = SystemTime::now();
+        let mut cache = PRICE_CACHE.write().await;
+        for (mint, price) in fetched {
+            cache.insert(mint.clone(), (price, now));
+            prices.insert(mint, price);
+        }
+    }
+    Ok(prices)
+}
+
+// Refetches every mint in PRICE_CACHE, for BACKGROUND_REFRESH. Mints the source has no price
+// for anymore keep their old entry and expire with it.
+pub(crate) async fn refresh_prices() -> Result<(), ApiError> {
+    let mints: Vec<String> = PRICE_CACHE.read().await.keys().cloned().collect();
+    if mints.is_empty() {
+        return Ok(());
+    }
+    let fetched = PRICE_SOURCE.prices(&mints).await?;
+    let now = SystemTime::now();
+    let mut cache = PRICE_CACHE.write().await;
+    for (mint, price) in fetched {
+        cache.insert(mint, (price, now));
+    }
+    Ok(())
+}
+
+// A UI amount string such as "10.5" as raw base units, given the token's decimals.
+pub(crate) fn ui_amount_to_raw(ui: &str, decimals: u32) -> Option<u128> {
+    let (whole, fraction) = ui.split_once('.').unwrap_or((ui, ""));
+    if fraction.len() > decimals as usize {
+        return None;
+    }
+    let digits = format!("{}{:0<width$}", whole, fraction, width = decimals as usize);
+    digits.parse().ok()
+}
+
+// Raw base units as a UI amount string without trailing zeros, e.g. 10500000 at 6 decimals -> "10.5".
+pub(crate) fn raw_to_ui_amount(raw: u128, decimals: u32) -> String {
+    let scale = 10u128.pow(decimals);
+    let (whole, fraction) = (raw / scale, raw % scale);
+    if fraction == 0 {
+        return whole.to_string();
+    }
+    let fraction = format!("{:0width$}", fraction, width = decimals as usize);
+    format!("{}.{}", whole, fraction.trim_end_matches('0'))
+}
+
+// Merges token accounts of the same mint (e.g. an ATA plus an older auxiliary account) into one
+// entry with the summed amount and an account_count. Entries keep the order of each mint's
+// first account. When every account reported a uiAmountString the sum is exact; otherwise
+// amount is a float sum and uiAmountString is dropped.
+pub(crate) fn aggregate_by_mint(tokens: Value) -> Value {
+    let mut merged: Vec<Value> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let mut raw_sums: Vec<Option<u128>> = Vec::new();
+    for mut token in tokens.as_array().cloned().unwrap_or_default() {
+        let mint = token["mint"].as_str().unwrap_or_default().to_string();
+        let decimals = token["decimals"].as_u64().and_then(valid_decimals).map(u32::from);
+        let raw = decimals.and_then(|decimals| token["uiAmountString"].as_str().and_then(|ui| ui_amount_to_raw(ui, decimals)));
+        match positions.get(&mint) {
+            Some(&index) => {
+                let entry = &mut merged[index];
+                entry["amount"] = (entry["amount"].as_f64().unwrap_or(0.0) + token["amount"].as_f64().unwrap_or(0.0)).into();
+                entry["account_count"] = (entry["account_count"].as_u64().unwrap_or(1) + 1).into();
+                raw_sums[index] = raw_sums[index].zip(raw).map(|(sum, raw)| sum + raw);
+            }
+            None => {
+                token["account_count"] = 1.into();
+                positions.insert(mint, merged.len());
+                merged.push(token);
+                raw_sums.push(raw);
+            }
+        }
+    }
+    for (entry, raw) in merged.iter_mut().zip(raw_sums) {
+        let decimals = entry["decimals"].as_u64().unwrap_or(0) as u32;
+        match (raw, entry.as_object_mut()) {
+            (Some(raw), _) => {
+                let ui = raw_to_ui_amount(raw, decimals);
+                if let Ok(amount) = ui.parse::<f64>() {
+                    entry["amount"] = amount.into();
+                }
+                entry["uiAmountString"] = Value::String(ui);
+            }
+            (None, Some(fields)) => {
+                fields.remove("uiAmountString");
+            }
+            (None, None) => {}
+        }
+    }
+    Value::Array(merged)
+}
+
+// Rounds a non-negative decimal string to `places` fractional digits, half to even, working on
+// the digits so that e.g. "0.125" rounds to "0.12" exactly rather than via a binary float.
+pub(crate) fn round_half_even(amount: &str, places: usize) -> Option<String> {
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    if whole.is_empty() || !whole.bytes().chain(fraction.bytes()).all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    if fraction.len() <= places {
+        return Some(amount.to_string());
+    }
+    let mut digits: Vec<u8> = whole.bytes().chain(fraction[..places].bytes()).collect();
+    let (next, rest) = (fraction.as_bytes()[places], &fraction.as_bytes()[places + 1..]);
+    let last_odd = digits.last().is_some_and(|d| (d - b'0') % 2 == 1);
+    let round_up = next > b'5' || (next == b'5' && (rest.iter().any(|&d| d != b'0') || last_odd));
+    if round_up {
+        let mut carry = true;
+        for digit in digits.iter_mut().rev() {
+            if *digit == b'9' {
+                *digit = b'0';
+            } else {
+                *digit += 1;
+                carry = false;
+                break;
+            }
+        }
+        if carry {
+            digits.insert(0, b'1');
+        }
+    }
+    let digits = String::from_utf8(digits).ok()?;
+    let (whole, fraction) = digits.split_at(digits.len() - places);
+    Some(if places == 0 { whole.to_string() } else { format!("{}.{}", whole, fraction) })
+}
+
+// ?sort= for /tokens: largest amount first, or by symbol A-Z with unnamed tokens last. Ties,
+// and the default order, are by mint so pages stay stable between requests.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum TokenSort {
+    Amount,
+    Symbol,
+}
+
+// ?mint= keeps one mint's entries (several when it is held in more than one account) and
+// ?min_amount= drops dust below that UI amount.
+pub(crate) fn filter_tokens(tokens: &mut Value, mint: Option<&str>, min_amount: Option<f64>) {
+    let Some(tokens) = tokens.as_array_mut() else {
+        return;
+    };
+    tokens.retain(|token| {
+        mint.is_none_or(|mint| token["mint"] == mint) && min_amount.is_none_or(|min| token["amount"].as_f64().unwrap_or(0.0) >= min)
+    });
+}
+
+pub(crate) fn sort_tokens(tokens: &mut Value, sort: Option<TokenSort>) {
+    let Some(tokens) = tokens.as_array_mut() else {
+        return;
+    };
+    let mint = |token: &Value| token["mint"].as_str().unwrap_or_default().to_string();
+    match sort {
+        None => tokens.sort_by_key(mint),
+        Some(TokenSort::Amount) => tokens.sort_by(|a, b| {
+            let amount = |token: &Value| token["amount"].as_f64().unwrap_or(0.0);
+            amount(b).total_cmp(&amount(a)).then_with(|| mint(a).cmp(&mint(b)))
+        }),
+        Some(TokenSort::Symbol) => tokens.sort_by_key(|token| {
+            let symbol = token["symbol"].as_str().filter(|symbol| !symbol.is_empty()).map(str::to_lowercase);
+            (symbol.is_none(), symbol, mint(token))
+        }),
+    }
+}
+
+// ?offset= and ?limit= for /tokens. Returns how many tokens there were before paging.
+pub(crate) fn page_tokens(tokens: &mut Value, offset: usize, limit: Option<usize>) -> usize {
+    let Some(tokens) = tokens.as_array_mut() else {
+        return 0;
+    };
+    let total = tokens.len();
+    tokens.drain(..offset.min(total));
+    if let Some(limit) = limit {
+        tokens.truncate(limit);
+    }
+    total
+}
+
+// ?round= for /tokens: amount is rounded for display and the unrounded value kept in amount_raw.
+// The node's uiAmountString is rounded where present, as it is exact. A token with no more
+// decimals than `places` has nothing to round and keeps its amount.
+pub(crate) fn round_amounts(tokens: &mut Value, places: u32) {
+    let Some(tokens) = tokens.as_array_mut() else {
+        return;
+    };
+    for token in tokens {
+        let Some(amount) = token["amount"].as_f64() else {
+            continue;
+        };
+        token["amount_raw"] = amount.into();
+        if token["decimals"].as_u64().is_some_and(|decimals| decimals <= u64::from(places)) {
+            continue;
+        }
+        let exact = token["uiAmountString"].as_str().map_or_else(|| amount.to_string(), str::to_string);
+        if let Some(rounded) = round_half_even(&exact, places as usize).and_then(|rounded| rounded.parse::<f64>().ok()) {
+            token["amount"] = rounded.into();
+        }
+    }
+}
+
+// Adds price_usd and value_usd to the tokens the price source knows, and price_change_24h_percent
+// where it also knows the 24h change. Prices are best effort: if the source fails the balances
+// are still returned, just unpriced.
+pub(crate) async fn add_prices(tokens: &mut Value) {
+    let Some(tokens) = tokens.as_array_mut() else {
+        return;
+    };
+    let mut mints: Vec<String> = tokens.iter().filter_map(|t| t["mint"].as_str().map(str::to_string)).collect();
+    mints.sort();
+    mints.dedup();
+
+    let prices = match usd_prices(&*PRICE_SOURCE, &mints).await {
+        Ok(prices) => prices,
+        Err(e) => {
+            tracing::warn!("Failed to fetch prices: {}", e);
+            return;
+        }
+    };
+    for token in tokens {
+        if let Some(price) = token["mint"].as_str().and_then(|mint| prices.get(mint)).copied() {
+            token["value_usd"] = (token["amount"].as_f64().unwrap_or(0.0) * price.usd).into();
+            token["price_usd"] = price.usd.into();
+            if let Some(change) = price.change_24h_percent {
+                token["price_change_24h_percent"] = change.into();
+            }
+        }
+    }
+}
+
+// Sum of value_usd over priced tokens, from either a bare /tokens array or its ?debug=true form.
+pub(crate) fn total_value_usd(tokens: &Value) -> f64 {
+    let tokens = tokens.get("tokens").unwrap_or(tokens);
+    tokens.as_array().map(|tokens| tokens.iter().filter_map(|token| token["value_usd"].as_f64()).sum()).unwrap_or(0.0)
+}
+
+// A null authority means it has been revoked: nobody can mint more (or freeze accounts).
+pub(crate) async fn get_token_authorities(mint: &str) -> Result<Value, ApiError> {
+    let not_a_mint = || ApiError::NotFound("Not a mint account".to_string());
+    let resp = rpc_call("getAccountInfo", serde_json::json!([mint, { "encoding": "jsonParsed" }])).await?;
+    let account = extract_value(&resp).map_err(|e| match e {
+        ApiError::NotFound(_) => not_a_mint(),
+        e => e,
+    })?;
+    let program = match account["owner"].as_str() {
+        Some(TOKEN_PROGRAM_ID) => "token",
+        Some(TOKEN_2022_PROGRAM_ID) => "token-2022",
+        _ => return Err(not_a_mint()),
+    };
+    let parsed = &account["data"]["parsed"];
+    if parsed["type"] != "mint" {
+        return Err(not_a_mint());
+    }
+    let info = &parsed["info"];
+    Ok(serde_json::json!({
+        "mint": mint,
+        "program": program,
+        "mint_authority": info["mintAuthority"],
+        "freeze_authority": info["freezeAuthority"],
+        "is_initialized": info["isInitialized"],
+        "supply": info["supply"],
+        "decimals": info["decimals"]
+    }))
+}
+
+// The mint account plus its metadata from the configured sources (null when none knows the
+// mint). A metadata failure leaves metadata null rather than failing the request.
+pub(crate) async fn get_token_info(mint: &str) -> Result<Value, ApiError> {
+    let mut info = get_token_authorities(mint).await?;
+    if let Some(decimals) = info["decimals"].as_u64().and_then(valid_decimals)
+        && let Some(supply) = info["supply"].as_str().and_then(|supply| supply.parse::<u128>().ok())
+    {
+        info["ui_supply"] = raw_to_ui_amount(supply, decimals.into()).into();
+    }
+    info["metadata"] = match get_token_metadata(&[mint.to_string()]).await {
+        Ok(mut metadata) => metadata[mint].take(),
+        Err(e) => {
+            tracing::warn!("Failed to fetch metadata for {}: {}", mint, e);
+            Value::Null
+        }
+    };
+    Ok(info)
+}
+
+// The mint's largest holders with their share of the supply, largest first. By default these
+// are the token accounts getTokenLargestAccounts reports (at most LARGEST_TOKEN_ACCOUNTS), with
+// their owners looked up; an owner with several accounts can appear more than once. `scan`
+// reads every token account of the mint with getProgramAccounts and sums them per owner
+// instead, which is exact but slow on popular mints, and many providers refuse it for them.
+pub(crate) async fn get_token_holders(mint: &str, limit: usize, scan: bool) -> Result<Value, ApiError> {
+    let info = get_token_authorities(mint).await?;
+    let decimals = info["decimals"].as_u64().and_then(valid_decimals).map(u32::from);
+    let supply = info["supply"].as_str().and_then(|supply| supply.parse::<u128>().ok()).unwrap_or(0);
+    let (holders, holder_count, source) = if scan {
+        let (holders, count) = scan_token_holders(mint, mint_program_id(&info), limit).await?;
+        (holders, Some(count), "program_accounts")
+    } else {
+        (largest_token_accounts(mint, limit).await?, None, "largest_accounts")
+    };
+    let holders: Vec<Value> = holders
+        .into_iter()
+        .map(|(owner, token_account, raw)| {
+            let ui_amount = decimals.map(|decimals| raw_to_ui_amount(raw, decimals));
+            serde_json::json!({
+                "owner": owner,
+                "token_account": token_account,
+                "amount": ui_amount.as_deref().and_then(|ui| ui.parse::<f64>().ok()),
+                "ui_amount_string": ui_amount,
+                "raw_amount": raw.to_string(),
+                "percentage": (supply > 0).then(|| raw as f64 * 100.0 / supply as f64)
+            })
+        })
+        .collect();
+    let mut reply = serde_json::json!({
+        "mint": mint,
+        "program": info["program"],
+        "decimals": info["decimals"],
+        "supply": info["supply"],
+        "source": source,
+        "holders": holders
+    });
+    if let Some(count) = holder_count {
+        reply["holder_count"] = count.into();
+    }
+    Ok(reply)
+}
+
+// Owner, token account (unknown when summed over an owner's accounts) and raw amount.
+pub(crate) type TokenHolder = (Option<String>, Option<String>, u128);
+
+// The mint's largest token accounts.
+pub(crate) async fn largest_token_accounts(mint: &str, limit: usize) -> Result<Vec<TokenHolder>, ApiError> {
+    let resp = rpc_call("getTokenLargestAccounts", serde_json::json!([mint])).await?;
+    let accounts: Vec<(String, u128)> = extract_value(&resp)?
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|account| Some((account["address"].as_str()?.to_string(), account["amount"].as_str()?.parse().ok()?)))
+        .filter(|(_, raw): &(String, u128)| *raw > 0)
+        .take(limit)
+        .collect();
+    if accounts.is_empty() {
+        return Ok(Vec::new());
+    }
+    let addresses: Vec<&str> = accounts.iter().map(|(address, _)| address.as_str()).collect();
+    let resp = rpc_call("getMultipleAccounts", serde_json::json!([addresses, { "encoding": "jsonParsed" }])).await?;
+    let owners = extract_value(&resp)?.as_array().cloned().unwrap_or_default();
+    Ok(accounts
+        .into_iter()
+        .enumerate()
+        .map(|(i, (address, raw))| {
+            let owner = owners.get(i).and_then(|account| account["data"]["parsed"]["info"]["owner"].as_str()).map(str::to_string);
+            (owner, Some(address), raw)
+        })
+        .collect())
+}
+
+// The token program owning a mint, from get_token_authorities.
+pub(crate) fn mint_program_id(authorities: &Value) -> &'static str {
+    match authorities["program"] == "token-2022" {
+        true => TOKEN_2022_PROGRAM_ID,
+        false => TOKEN_PROGRAM_ID,
+    }
+}
+
+// getProgramAccounts filters for the token accounts of `mint` under `program`. Token-2022
+// accounts carry extensions after the base layout, so they can't be filtered on size.
+pub(crate) fn token_account_filters(mint: &str, program: &str) -> Vec<Value> {
+    let mut filters = vec![serde_json::json!({ "memcmp": { "offset": 0, "bytes": mint } })];
+    if program == TOKEN_PROGRAM_ID {
+        filters.push(serde_json::json!({ "dataSize": TOKEN_ACCOUNT_SIZE }));
+    }
+    filters
+}
+
+// Every non-empty token account of the mint summed per owner: the top `limit` owners and how many
+// owners there are. Only the owner and amount of each account are fetched.
+pub(crate) async fn scan_token_holders(
+    mint: &str,
+    program: &str,
+    limit: usize,
+) -> Result<(Vec<TokenHolder>, usize), ApiError> {
+    let filters = token_account_filters(mint, program);
+    let params = serde_json::json!([program, {
+        "encoding": "base64",
+        "dataSlice": { "offset": TOKEN_ACCOUNT_OWNER_OFFSET, "length": TOKEN_ACCOUNT_AMOUNT_OFFSET - TOKEN_ACCOUNT_OWNER_OFFSET + 8 },
+        "filters": filters
+    }]);
+    let resp = rpc_call("getProgramAccounts", params).await?;
+    let accounts = extract_result(&resp)?.as_array().cloned().unwrap_or_default();
+    let holders = run_blocking(move || {
+        let mut by_owner: HashMap<String, u128> = HashMap::new();
+        for data in accounts.iter().filter_map(|account| account["account"]["data"][0].as_str()) {
+            let Ok(data) = base64::engine::general_purpose::STANDARD.decode(data) else {
+                continue;
+            };
+            let (Some(owner), Some(amount)) = (data.get(..32), data.get(32..40).and_then(|amount| amount.try_into().ok())) else {
+                continue;
+            };
+            let amount = u64::from_le_bytes(amount);
+            if amount > 0 {
+                *by_owner.entry(bs58::encode(owner).into_string()).or_default() += u128::from(amount);
+            }
+        }
+        let mut holders: Vec<(String, u128)> = by_owner.into_iter().collect();
+        holders.sort_by(|(a_owner, a), (b_owner, b)| b.cmp(a).then_with(|| a_owner.cmp(b_owner)));
+        holders
+    })
+    .await;
+    let count = holders.len();
+    let top = holders.into_iter().take(limit).map(|(owner, raw)| (Some(owner), None, raw)).collect();
+    Ok((top, count))
+}
+
+// The count and its X-Cache status: HIT, STALE (served past its TTL while a background recount
+// runs) or MISS.
+pub(crate) async fn get_holder_count(mint: &str) -> Result<(Value, &'static str), ApiError> {
+    let reply = |count: u64| serde_json::json!({"mint": mint, "holder_count": count});
+    let cached = HOLDER_COUNTS.read().await.get(mint).copied();
+    if let Some((count, fetched)) = cached {
+        let age = fetched.elapsed().unwrap_or(HOLDER_COUNT_TTL);
+        if age < HOLDER_COUNT_TTL {
+            record_cache_lookup("holder_counts", true);
+            return Ok((reply(count), "HIT"));
+        }
+        if age < HOLDER_COUNT_TTL + *STALE_WHILE_REVALIDATE {
+            if let Some(claim) = RefreshClaim::new(&HOLDER_COUNT_REFRESHES, mint) {
+                let mint = mint.to_string();
+                tokio::spawn(in_cluster(cluster(), async move {
+                    let _claim = claim;
+                    if let Err(e) = count_holders(&mint).await {
+                        tracing::warn!("background holder count refresh for {} failed: {}", mint, e);
+                    }
+                }));
+            }
+            record_cache_lookup("holder_counts", true);
+            return Ok((reply(count), "STALE"));
+        }
+    }
+    if let Some(count) = shared_cache_get(&format!("holder-count:{}", mint)).await.and_then(|count| count.parse::<u64>().ok()) {
+        record_cache_lookup("holder_counts", true);
+        return Ok((reply(count), "HIT"));
+    }
+    record_cache_lookup("holder_counts", false);
+    Ok((reply(count_holders(mint).await?), "MISS"))
+}
+
+// Counts token accounts of `mint` with a non-zero balance, under whichever token program owns
+// the mint. Only the 8 amount bytes of each account are requested, but the node still scans
+// every account of that program, so popular mints can take seconds and some providers reject
+// the call outright.
+pub(crate) async fn count_holders(mint: &str) -> Result<u64, ApiError> {
+    let program = mint_program_id(&get_token_authorities(mint).await?);
+    let params = serde_json::json!([program, {
+        "encoding": "base64",
+        "dataSlice": { "offset": TOKEN_ACCOUNT_AMOUNT_OFFSET, "length": 8 },
+        "filters": token_account_filters(mint, program)
+    }]);
+    let resp = rpc_call("getProgramAccounts", params).await?;
+    let accounts = extract_result(&resp)?.as_array().cloned().unwrap_or_default();
+    let count = run_blocking(move || {
+        accounts
+            .iter()
+            .filter_map(|account| account["account"]["data"][0].as_str())
+            .filter_map(|data| base64::engine::general_purpose::STANDARD.decode(data).ok())
+            .filter(|amount| amount.iter().any(|b| *b != 0))
+            .count() as u64
+    })
+    .await;
+
+    HOLDER_COUNTS.write().await.insert(mint.to_string(), (count, SystemTime::now()));
+    shared_cache_set(&format!("holder-count:{}", mint), &count.to_string(), HOLDER_COUNT_TTL).await;
+    Ok(count)
+}
+
+// Mints no metadata source knows map to null.
+pub(crate) async fn get_token_metadata(mints: &[String]) -> Result<Value, ApiError> {
+    let found = token_metadata(mints).await?;
+    let metadata: serde_json::Map<String, Value> = mints
+        .iter()
+        .map(|mint| {
+            let entry = found.get(mint).map(|token| {
+                serde_json::json!({
+                    "symbol": token["symbol"],
+                    "name": token["name"],
+                    "logoURI": token["logoURI"],
+                    "decimals": token["decimals"],
+                    "source": token["source"]
+                })
+            });
+            (mint.clone(), entry.unwrap_or(Value::Null))
+        })
+        .collect();
+    Ok(Value::Object(metadata))
+}
+
+// A provider of token names, symbols and logos, as token list style entries ({symbol, name,
+// logoURI, decimals, source}). Mints the source knows nothing about are left out of the result.
+pub(crate) trait MetadataSource {
+    async fn metadata(&self, mints: &[String]) -> Result<HashMap<String, Value>, ApiError>;
+}
+
+pub(crate) struct TokenListMetadata;
+
+impl MetadataSource for TokenListMetadata {
+    async fn metadata(&self, mints: &[String]) -> Result<HashMap<String, Value>, ApiError> {
+        let token_map = get_token_map().await?;
+        Ok(mints
+            .iter()
+            .filter_map(|mint| {
+                let token = token_map.get(mint)?;
+                let entry = serde_json::json!({
+                    "symbol": token["symbol"],
+                    "name": token["name"],
+                    "logoURI": token["logoURI"],
+                    "decimals": token["decimals"],
+                    "source": "token-list"
+                });
+                Some((mint.clone(), entry))
+            })
+            .collect())
+    }
+}
+
+// Reads each mint's Metaplex metadata account. It names the token but has no logo: that lives
+// in the off-chain JSON, which isn't fetched for fungible tokens.
+pub(crate) struct MetaplexMetadata;
+
+impl MetadataSource for MetaplexMetadata {
+    async fn metadata(&self, mints: &[String]) -> Result<HashMap<String, Value>, ApiError> {
+        let keys = metadata_addresses(mints.to_vec()).await;
+        let mut found = HashMap::new();
+        for (mints, keys) in mints.chunks(MAX_MULTIPLE_ACCOUNTS).zip(keys.chunks(MAX_MULTIPLE_ACCOUNTS)) {
+            let accounts = get_multiple_accounts(keys, true).await?;
+            for (mint, account) in mints.iter().zip(accounts.as_array().into_iter().flatten()) {
+                let metadata = account["data"]
+                    .as_str()
+                    .filter(|_| account["owner"] == METADATA_PROGRAM_ID)
+                    .and_then(|data| base64::engine::general_purpose::STANDARD.decode(data).ok())
+                    .and_then(|data| parse_metadata_account(&data));
+                let Some(metadata) = metadata else {
+                    continue;
+                };
+                let text = |field: &str| metadata[field].as_str().map(str::trim).filter(|text| !text.is_empty()).map(str::to_string);
+                let (name, symbol) = (text("name"), text("symbol"));
+                if name.is_none() && symbol.is_none() {
+                    continue;
+                }
+                let entry = serde_json::json!({ "symbol": symbol, "name": name, "logoURI": null, "decimals": null, "source": "metaplex" });
+                found.insert(mint.clone(), entry);
+            }
+        }
+        Ok(found)
+    }
+}
+
+// Asks a DAS-compatible RPC (getAsset) about each mint. Only some providers serve DAS; on others
+// every call fails and the lookup moves on to the next source.
+pub(crate) struct DasMetadata;
+
+impl MetadataSource for DasMetadata {
+    async fn metadata(&self, mints: &[String]) -> Result<HashMap<String, Value>, ApiError> {
+        let assets: Vec<(String, Result<Value, ApiError>)> = futures::stream::iter(mints.to_vec())
+            .map(|mint| async move {
+                let asset = match rpc_call("getAsset", serde_json::json!({ "id": mint })).await {
+                    Ok(resp) => extract_result(&resp).cloned(),
+                    Err(e) => Err(e),
+                };
+                (mint, asset)
+            })
+            .buffered(DAS_METADATA_CONCURRENCY)
+            .collect()
+            .await;
+        let mut found = HashMap::new();
+        for (mint, asset) in assets {
+            let asset = match asset {
+                Ok(asset) => asset,
+                Err(ApiError::NotFound(_)) => continue,
+                Err(e) => return Err(e),
+            };
+            let text = |value: &Value| value.as_str().map(str::trim).filter(|text| !text.is_empty()).map(str::to_string);
+            let (name, symbol) = (text(&asset["content"]["metadata"]["name"]), text(&asset["content"]["metadata"]["symbol"]));
+            if name.is_none() && symbol.is_none() {
+                continue;
+            }
+            let entry = serde_json::json!({
+                "symbol": symbol,
+                "name": name,
+                "logoURI": text(&asset["content"]["links"]["image"]),
+                "decimals": asset["token_info"]["decimals"],
+                "source": "das"
+            });
+            found.insert(mint, entry);
+        }
+        Ok(found)
+    }
+}
+
+pub(crate) enum MetadataProvider {
+    TokenList(TokenListMetadata),
+    Metaplex(MetaplexMetadata),
+    Das(DasMetadata),
+}
+
+impl MetadataProvider {
+    pub(crate) fn named(name: &str) -> Option<Self> {
+        match name {
+            "token-list" => Some(MetadataProvider::TokenList(TokenListMetadata)),
+            "metaplex" => Some(MetadataProvider::Metaplex(MetaplexMetadata)),
+            "das" => Some(MetadataProvider::Das(DasMetadata)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            MetadataProvider::TokenList(_) => "token-list",
+            MetadataProvider::Metaplex(_) => "metaplex",
+            MetadataProvider::Das(_) => "das",
+        }
+    }
+}
+
+impl MetadataSource for MetadataProvider {
+    async fn metadata(&self, mints: &[String]) -> Result<HashMap<String, Value>, ApiError> {
+        match self {
+            MetadataProvider::TokenList(source) => source.metadata(mints).await,
+            MetadataProvider::Metaplex(source) => source.metadata(mints).await,
+            MetadataProvider::Das(source) => source.metadata(mints).await,
+        }
+    }
+}
+
+// TOKEN_METADATA_SOURCES lists sources to try in order, comma-separated (default
+// "token-list,metaplex"; add das on providers that serve it). Mints one source doesn't know
+// are asked of the next. MOCK_MODE only uses the fixture token list.
+pub(crate) struct MetadataSources(pub(crate) Vec<MetadataProvider>);
+
+impl MetadataSources {
+    pub(crate) fn from_env() -> Self {
+        let names = std::env::var("TOKEN_METADATA_SOURCES").unwrap_or_else(|_| "token-list,metaplex".to_string());
+        let mut providers = Vec::new();
+        for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match MetadataProvider::named(name) {
+                Some(provider) => providers.push(provider),
+                None => tracing::warn!("Unknown TOKEN_METADATA_SOURCES entry {:?}, ignored", name),
+            }
+        }
+        MetadataSources::new(providers)
+    }
+
+    pub(crate) fn new(mut providers: Vec<MetadataProvider>) -> Self {
+        if *MOCK_MODE {
+            return MetadataSources(vec![MetadataProvider::TokenList(TokenListMetadata)]);
+        }
+        if providers.is_empty() {
+            providers.push(MetadataProvider::TokenList(TokenListMetadata));
+        }
+        MetadataSources(providers)
+    }
+}
+
+impl MetadataSource for MetadataSources {
+    // The token list is already in memory and is read each time, so a refresh shows up at once.
+    // What the other sources answer, including that none of them knows a mint, is cached for
+    // TOKEN_METADATA_TTL, in memory and in the shared cache. The caches are consulted just before
+    // the first of them, the shared one only for mints missing from memory.
+    async fn metadata(&self, mints: &[String]) -> Result<HashMap<String, Value>, ApiError> {
+        let mut found = HashMap::new();
+        let mut missing: Vec<String> = mints.to_vec();
+        let mut fetched = HashMap::new();
+        let mut cache_checked = false;
+        let (mut last_error, mut any_ok, mut slow_failed) = (None, false, false);
+        for provider in &self.0 {
+            let slow = !matches!(provider, MetadataProvider::TokenList(_));
+            if slow && !cache_checked {
+                cache_checked = true;
+                let cache = TOKEN_METADATA_CACHE.read().await;
+                missing.retain(|mint| match cache.get(mint) {
+                    Some((entry, fetched)) if fetched.elapsed().unwrap_or(TOKEN_METADATA_TTL) < TOKEN_METADATA_TTL => {
+                        record_cache_lookup("token_metadata", true);
+                        if let Some(entry) = entry {
+                            found.insert(mint.clone(), entry.clone());
+                        }
+                        false
+                    }
+                    _ => true,
+                });
+                drop(cache);
+                let keys: Vec<String> = missing.iter().map(|mint| format!("token-metadata:{}", mint)).collect();
+                let shared = shared_json_get_many(&keys, TOKEN_METADATA_TTL).await;
+                let mut cache = TOKEN_METADATA_CACHE.write().await;
+                let mut shared = shared.into_iter();
+                missing.retain(|mint| match shared.next().flatten() {
+                    Some((entry, fetched)) => {
+                        record_cache_lookup("token_metadata", true);
+                        let entry = Some(entry).filter(|entry| !entry.is_null());
+                        if let Some(entry) = &entry {
+                            found.insert(mint.clone(), entry.clone());
+                        }
+                        cache.insert(mint.clone(), (entry, fetched));
+                        false
+                    }
+                    None => {
+                        record_cache_lookup("token_metadata", false);
+                        true
+                    }
+                });
+            }
+            if missing.is_empty() {
+                break;
+            }
+            match provider.metadata(&missing).await {
+                Ok(entries) => {
+                    any_ok = true;
+                    missing.retain(|mint| !entries.contains_key(mint));
+                    if slow {
+                        fetched.extend(entries.iter().map(|(mint, entry)| (mint.clone(), entry.clone())));
+                    }
+                    found.extend(entries);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to fetch token metadata from {}: {}", provider.name(), e);
+                    slow_failed |= slow;
+                    last_error = Some(e);
+                }
+            }
+        }
+        if !any_ok && let Some(e) = last_error {
+            return Err(e);
+        }
+
+        if cache_checked {
+            let now = SystemTime::now();
+            // A miss only means something if every source got to answer.
+            let misses = if slow_failed { Vec::new() } else { missing };
+            for (mint, entry) in fetched.iter().map(|(mint, entry)| (mint, entry.clone())).chain(misses.iter().map(|mint| (mint, Value::Null))) {
+                shared_json_set(&format!("token-metadata:{}", mint), &entry, now, TOKEN_METADATA_TTL).await;
+            }
+            let mut cache = TOKEN_METADATA_CACHE.write().await;
+            cache.retain(|_, (_, fetched)| fetched.elapsed().unwrap_or(TOKEN_METADATA_TTL) < TOKEN_METADATA_TTL);
+            for (mint, entry) in fetched {
+                cache.insert(mint, (Some(entry), now));
+            }
+            for mint in misses {
+                cache.insert(mint, (None, now));
+            }
+        }
+        Ok(found)
+    }
+}
+
+// Metadata for each mint from the first of the cluster's metadata sources that has any.
+pub(crate) async fn token_metadata(mints: &[String]) -> Result<HashMap<String, Value>, ApiError> {
+    cluster().metadata_sources.metadata(mints).await
+}
+
+// Metaplex metadata account addresses of `mints`, in order; empty for a mint that isn't a pubkey.
+pub(crate) async fn metadata_addresses(mints: Vec<String>) -> Vec<String> {
+    run_blocking(move || {
+        mints
+            .iter()
+            .map(|mint| {
+                let program = parse_pubkey(METADATA_PROGRAM_ID)?;
+                let mint = parse_pubkey(mint)?;
+                find_program_address(&[b"metadata", &program, &mint], &program)
+                    .map(|key| bs58::encode(key).into_string())
+            })
+            .map(Option::unwrap_or_default)
+            .collect()
+    })
+    .await
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum NftSource {
+    Metaplex,
+    Das,
+}
+
+impl NftSource {
+    pub(crate) fn from_env() -> Self {
+        match std::env::var("NFT_SOURCE").as_deref() {
+            Ok("das") => NftSource::Das,
+            Ok("metaplex") | Err(_) => NftSource::Metaplex,
+            Ok(other) => {
+                tracing::warn!("Unknown NFT_SOURCE {:?}, using metaplex", other);
+                NftSource::Metaplex
+            }
+        }
+    }
+}
+
+pub(crate) const TOKEN_STANDARDS: [&str; 6] = [
+    "NonFungible",
+    "FungibleAsset",
+    "Fungible",
+    "NonFungibleEdition",
+    "ProgrammableNonFungible",
+    "ProgrammableNonFungibleEdition",
+];
+
+// Metaplex metadata account (Borsh): key, update authority, mint, name, symbol, uri, royalty
+// basis points, optional creators and two flags. The edition nonce, token standard and collection
+// were appended later, so accounts written by old program versions simply end early.
+pub(crate) fn parse_metadata_account(data: &[u8]) -> Option<Value> {
+    let mut reader = WireReader { bytes: data, pos: 0 };
+    if reader.byte()? != METADATA_V1_KEY {
+        return None;
+    }
+    let update_authority = reader.pubkey()?;
+    reader.pubkey()?;
+    let name = reader.borsh_string()?;
+    let symbol = reader.borsh_string()?;
+    let uri = reader.borsh_string()?;
+    let seller_fee_basis_points = reader.u16_le()?;
+    let creators: Vec<Value> = if reader.option()? {
+        let count = reader.u32_le()?;
+        (0..count)
+            .map(|_| {
+                let address = reader.pubkey()?;
+                let verified = reader.byte()? == 1;
+                let share = reader.byte()?;
+                Some(serde_json::json!({ "address": address, "verified": verified, "share": share }))
+            })
+            .collect::<Option<_>>()?
+    } else {
+        Vec::new()
+    };
+    // primary_sale_happened, is_mutable
+    let flags = reader.take(2)?;
+    let is_mutable = flags[1] == 1;
+
+    let mut token_standard = None;
+    let mut collection = Value::Null;
+    let mut trailer = || -> Option<()> {
+        if reader.option()? {
+            reader.byte()?;
+        }
+        if reader.option()? {
+            token_standard = TOKEN_STANDARDS.get(reader.byte()? as usize).copied();
+        }
+        if reader.option()? {
+            let verified = reader.byte()? == 1;
+            collection = serde_json::json!({ "key": reader.pubkey()?, "verified": verified });
+        }
+        Some(())
+    };
+    trailer();
+
+    Some(serde_json::json!({
+        "name": name,
+        "symbol": symbol,
+        "uri": uri,
+        "collection": collection,
+        "royalty": { "basis_points": seller_fee_basis_points, "creators": creators },
+        "update_authority": update_authority,
+        "is_mutable": is_mutable,
+        "token_standard": token_standard
+    }))
+}
+
+// The "image" of an NFT's off-chain JSON. Only https URIs are fetched and oversized bodies are
+// dropped; any failure just leaves the image out.
+pub(crate) async fn offchain_image(uri: &str) -> Option<String> {
+    let url = reqwest::Url::parse(uri).ok().filter(|url| url.scheme() == "https")?;
+    let mut resp = OFFCHAIN_METADATA_CLIENT.get(url).send().await.ok()?.error_for_status().ok()?;
+    let mut body = Vec::new();
+    while let Some(chunk) = resp.chunk().await.ok()? {
+        body.extend_from_slice(&chunk);
+        if body.len() > MAX_OFFCHAIN_METADATA_BYTES {
+            return None;
+        }
+    }
+    let json: Value = serde_json::from_slice(&body).ok()?;
+    json["image"].as_str().map(str::to_string)
+}
+
+// NFTs held by `wallet`: token accounts holding exactly one unit of a 0-decimal mint. Each mint's
+// Metaplex metadata account is derived and read; mints without one are listed with null fields.
+pub(crate) async fn get_nfts(wallet: &str, with_images: bool) -> Result<Value, ApiError> {
+    if *NFT_SOURCE == NftSource::Das && !*MOCK_MODE {
+        return get_nfts_das(wallet).await;
+    }
+    let (tokens, _) = get_spl_tokens(wallet).await?;
+    let mints: Vec<String> = tokens
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|token| is_nft(token))
+        .filter_map(|token| token["mint"].as_str().map(str::to_string))
+        .collect();
+    let metadata_keys = metadata_addresses(mints.clone()).await;
+
+    let mut nfts = Vec::with_capacity(mints.len());
+    for (mints, keys) in mints.chunks(MAX_MULTIPLE_ACCOUNTS).zip(metadata_keys.chunks(MAX_MULTIPLE_ACCOUNTS)) {
+        let accounts = get_multiple_accounts(keys, true).await?;
+        for ((mint, key), account) in mints.iter().zip(keys).zip(accounts.as_array().into_iter().flatten()) {
+            let metadata = account["data"]
+                .as_str()
+                .filter(|_| account["owner"] == METADATA_PROGRAM_ID)
+                .and_then(|data| base64::engine::general_purpose::STANDARD.decode(data).ok())
+                .and_then(|data| parse_metadata_account(&data));
+            let mut nft = serde_json::json!({
+                "mint": mint,
+                "metadata_account": key,
+                "name": null,
+                "symbol": null,
+                "uri": null,
+                "image": null,
+                "collection": null,
+                "royalty": null
+            });
+            if let (Some(fields), Some(Value::Object(metadata))) = (nft.as_object_mut(), metadata) {
+                fields.extend(metadata);
+            }
+            nfts.push(nft);
+        }
+    }
+
+    if with_images {
+        let uris: Vec<String> = nfts.iter().map(|nft| nft["uri"].as_str().unwrap_or_default().to_string()).collect();
+        let images: Vec<Option<String>> = futures::stream::iter(uris)
+            .map(|uri| async move { offchain_image(&uri).await })
+            .buffered(OFFCHAIN_METADATA_CONCURRENCY)
+            .collect()
+            .await;
+        for (nft, image) in nfts.iter_mut().zip(images) {
+            if let Some(image) = image {
+                nft["image"] = Value::String(image);
+            }
+        }
+    }
+    Ok(serde_json::json!({ "wallet": wallet, "source": "metaplex", "nfts": nfts }))
+}
+
+// The same listing from a DAS-compatible RPC, which has already indexed the off-chain JSON and
+// also knows about compressed NFTs. Fungible assets are left out.
+pub(crate) async fn get_nfts_das(wallet: &str) -> Result<Value, ApiError> {
+    let mut nfts = Vec::new();
+    for page in 1..=DAS_MAX_PAGES {
+        let params = serde_json::json!({ "ownerAddress": wallet, "page": page, "limit": DAS_PAGE_LIMIT });
+        let resp = rpc_call("getAssetsByOwner", params).await?;
+        let items = extract_result(&resp)?["items"].as_array().cloned().unwrap_or_default();
+        for item in &items {
+            let interface = item["interface"].as_str().unwrap_or_default();
+            if interface == "FungibleToken" || interface == "FungibleAsset" {
+                continue;
+            }
+            let content = &item["content"];
+            let image = content["links"]["image"]
+                .as_str()
+                .or_else(|| content["files"][0]["uri"].as_str())
+                .map_or(Value::Null, |image| Value::String(image.to_string()));
+            let collection = item["grouping"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .find(|group| group["group_key"] == "collection")
+                .map_or(Value::Null, |group| {
+                    serde_json::json!({ "key": group["group_value"], "verified": group["verified"].as_bool().unwrap_or(true) })
+                });
+            nfts.push(serde_json::json!({
+                "mint": item["id"],
+                "name": content["metadata"]["name"],
+                "symbol": content["metadata"]["symbol"],
+                "uri": content["json_uri"],
+                "image": image,
+                "collection": collection,
+                "royalty": { "basis_points": item["royalty"]["basis_points"], "creators": item["creators"] },
+                "compressed": item["compression"]["compressed"].as_bool().unwrap_or(false)
+            }));
+        }
+        if items.len() < DAS_PAGE_LIMIT {
+            break;
+        }
+    }
+    Ok(serde_json::json!({ "wallet": wallet, "source": "das", "nfts": nfts }))
+}
+
+// An NFT as /nfts finds one among the holdings: exactly one unit of a 0-decimal mint.
+pub(crate) fn is_nft(token: &Value) -> bool {
+    token["decimals"].as_u64() == Some(0) && token["amount"].as_f64() == Some(1.0)
+}
+
+// GET /portfolio: the SOL balance, token holdings, stake and NFT count in one answer, fetched
+// concurrently. NFTs are counted from the holdings and left out of "tokens", or counted by DAS
+// with NFT_SOURCE=das, which also knows compressed NFTs. Stake and the DAS count are extra
+// detail: a failed lookup nulls its field and adds a warning instead of failing the portfolio.
+pub(crate) async fn get_portfolio(wallet: &str, with_prices: bool) -> Result<Value, ApiError> {
+    let das = *NFT_SOURCE == NftSource::Das && !*MOCK_MODE;
+    let (balance, tokens, stake, das_nfts) = tokio::join!(
+        balance_at_slot(wallet),
+        get_spl_tokens(wallet),
+        get_stake_accounts(wallet),
+        async {
+            match das {
+                true => Some(get_nfts_das(wallet).await),
+                false => None,
+            }
+        }
+    );
+    let (lamports, _) = balance?;
+    let (tokens, _) = tokens?;
+    let (nfts, tokens): (Vec<Value>, Vec<Value>) = tokens.as_array().cloned().unwrap_or_default().into_iter().partition(is_nft);
+    let mut tokens = Value::Array(tokens);
+
+    let mut warnings = Vec::new();
+    let nft_count = match das_nfts {
+        None => Some(nfts.len()),
+        Some(Ok(listing)) => listing["nfts"].as_array().map(Vec::len),
+        Some(Err(e)) => {
+            warnings.push(format!("nft_count: {}", e));
+            None
+        }
+    };
+    let stake = match stake {
+        Ok(stake) => Some(serde_json::json!({
+            "total": stake["total"],
+            "active": stake["active"],
+            "account_count": stake["stake_accounts"].as_array().map_or(0, Vec::len)
+        })),
+        Err(e) => {
+            warnings.push(format!("stake: {}", e));
+            None
+        }
+    };
+    let staked = stake.as_ref().and_then(|stake| stake["total"]["lamports"].as_u64()).unwrap_or(0);
+    let total_lamports = lamports.saturating_add(staked);
+
+    let mut totals = serde_json::json!({
+        "sol": sol_amount(total_lamports),
+        "token_count": tokens.as_array().map_or(0, Vec::len),
+        "nft_count": nft_count
+    });
+    if with_prices {
+        let sol_mint = [WRAPPED_SOL_MINT.to_string()];
+        let (sol_price, ()) = tokio::join!(usd_prices(&*PRICE_SOURCE, &sol_mint), add_prices(&mut tokens));
+        let sol_usd = match sol_price {
+            Ok(prices) => prices.get(WRAPPED_SOL_MINT).map(|price| price.usd * lamports_to_sol(total_lamports)),
+            Err(e) => {
+                warnings.push(format!("sol_usd: {}", e));
+                None
+            }
+        };
+        let tokens_usd = total_value_usd(&tokens);
+        totals["sol_usd"] = sol_usd.into();
+        totals["tokens_usd"] = tokens_usd.into();
+        totals["usd"] = (sol_usd.unwrap_or(0.0) + tokens_usd).into();
+    }
+
+    let mut portfolio = serde_json::json!({
+        "wallet": wallet,
+        "sol": sol_amount(lamports),
+        "tokens": tokens,
+        "stake": stake,
+        "nft_count": nft_count,
+        "totals": totals
+    });
+    if !warnings.is_empty() {
+        portfolio["warnings"] = warnings.into();
+    }
+    Ok(portfolio)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token_account(mint: &str, amount: &str, decimals: u64, ui_amount_string: &str) -> Value {
+        serde_json::json!({
+            "account": {
+                "owner": TOKEN_PROGRAM_ID,
+                "data": { "parsed": { "info": {
+                    "mint": mint,
+                    "tokenAmount": { "amount": amount, "decimals": decimals, "uiAmountString": ui_amount_string },
+                } } },
+            },
+        })
+    }
+
+    #[test]
+    fn out_of_range_decimals_report_the_raw_amount() {
+        assert_eq!(valid_decimals(255), None);
+        let accounts = vec![token_account("Mint255", "1000", 255, "0.0")];
+        let (tokens, seen_decimals, skipped) = enrich_token_accounts(accounts, &HashMap::new(), &HashMap::new(), &HashMap::new());
+
+        assert_eq!(skipped, 0);
+        assert!(seen_decimals.is_empty(), "out-of-range decimals must not be cached");
+        let amount = tokens[0]["amount"].as_f64().unwrap();
+        assert!(amount.is_finite());
+        assert_eq!(amount, 1000.0);
+        assert_eq!(tokens[0]["decimals"], 255);
+        assert!(tokens[0].get("uiAmountString").is_none());
+        assert!(tokens[0]["warning"].as_str().unwrap().contains("255"));
+    }
+
+    #[tokio::test]
+    async fn error_bodies_keep_the_cached_token_map() {
+        let token_list = serde_json::json!({ "tokens": [{ "address": "MintA", "symbol": "AAA", "decimals": 6 }] });
+        store_token_list(&token_list, None).await;
+        let generation = token_map_generation().await;
+
+        let rate_limited = serde_json::json!({
+            "message": "API rate limit exceeded for 203.0.113.7.",
+            "documentation_url": "https://docs.github.com/rest/overview/resources-in-the-rest-api#rate-limiting",
+        });
+        let html = Value::String("<!DOCTYPE html><html><body>Too Many Requests</body></html>".to_string());
+        for body in [rate_limited, html, serde_json::json!({ "tokens": [] })] {
+            assert!(parse_token_list(&body).is_none());
+            store_token_list(&body, None).await;
+            let cache = TOKEN_MAP.read().await;
+            assert_eq!(cache.0.len(), 1);
+            assert_eq!(cache.0["MintA"]["symbol"], "AAA");
+            assert_eq!(cache.2, generation);
+        }
+    }
+
+    #[test]
+    fn token_2022_scans_skip_the_size_filter() {
+        let authorities = serde_json::json!({ "program": "token-2022" });
+        let program = mint_program_id(&authorities);
+        assert_eq!(program, TOKEN_2022_PROGRAM_ID);
+        let filters = token_account_filters("MintA", program);
+        assert_eq!(filters, vec![serde_json::json!({ "memcmp": { "offset": 0, "bytes": "MintA" } })]);
+
+        let program = mint_program_id(&serde_json::json!({ "program": "token" }));
+        assert_eq!(program, TOKEN_PROGRAM_ID);
+        assert!(token_account_filters("MintA", program).contains(&serde_json::json!({ "dataSize": TOKEN_ACCOUNT_SIZE })));
+    }
+
+    #[test]
+    fn aggregate_by_mint_sums_accounts_exactly() {
+        let tokens = serde_json::json!([
+            { "mint": "MintA", "amount": 0.1, "decimals": 9, "uiAmountString": "0.1" },
+            { "mint": "MintB", "amount": 5.0, "decimals": 0, "uiAmountString": "5" },
+            { "mint": "MintA", "amount": 0.2, "decimals": 9, "uiAmountString": "0.2" },
+        ]);
+        let merged = aggregate_by_mint(tokens);
+
+        let merged = merged.as_array().unwrap();
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0]["mint"], "MintA");
+        assert_eq!(merged[0]["account_count"], 2);
+        assert_eq!(merged[0]["uiAmountString"], "0.3");
+        assert_eq!(merged[0]["amount"], 0.3);
+        assert_eq!(merged[1]["account_count"], 1);
+    }
+
+    #[test]
+    fn ui_amounts_round_trip_through_raw_units() {
+        for (ui, decimals, raw) in [("0", 6, 0), ("10.5", 6, 10_500_000), ("0.000000001", 9, 1), ("18446744073.709551615", 9, u64::MAX as u128)] {
+            assert_eq!(ui_amount_to_raw(ui, decimals), Some(raw));
+            assert_eq!(raw_to_ui_amount(raw, decimals), ui);
+        }
+        assert_eq!(ui_amount_to_raw("1.2345", 2), None);
+        assert_eq!(raw_to_ui_amount(ui_amount_to_raw("7.50", 6).unwrap(), 6), "7.5");
+    }
+
+    #[test]
+    fn amounts_round_half_to_even() {
+        for (amount, places, rounded) in [
+            ("0.125", 2, "0.12"),
+            ("0.135", 2, "0.14"),
+            ("0.1251", 2, "0.13"),
+            ("9.995", 2, "10.00"),
+            ("2.5", 0, "2"),
+            ("3.5", 0, "4"),
+            ("2.51", 0, "3"),
+            ("0.4", 0, "0"),
+            ("1.5", 2, "1.5"),
+            ("7", 2, "7"),
+        ] {
+            assert_eq!(round_half_even(amount, places).as_deref(), Some(rounded), "{} at {}", amount, places);
+        }
+        assert_eq!(round_half_even(".5", 0), None);
+        assert_eq!(round_half_even("-1.5", 0), None);
+    }
+
+    #[test]
+    fn round_amounts_keeps_the_raw_amount() {
+        let mut tokens = serde_json::json!([
+            { "amount": 0.125, "uiAmountString": "0.125", "decimals": 6 },
+            { "amount": 1.25, "uiAmountString": "1.25", "decimals": 2 },
+            { "amount": 9.995, "decimals": 9 },
+        ]);
+        round_amounts(&mut tokens, 2);
+        assert_eq!(tokens[0]["amount"], 0.12);
+        assert_eq!(tokens[0]["amount_raw"], 0.125);
+        // Already at or below the requested places: left alone, but amount_raw is still there.
+        assert_eq!(tokens[1]["amount"], 1.25);
+        assert_eq!(tokens[1]["amount_raw"], 1.25);
+        assert_eq!(tokens[2]["amount"], 10.0);
+        assert_eq!(tokens[2]["amount_raw"], 9.995);
+    }
+}
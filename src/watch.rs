@@ -0,0 +1,388 @@
+//! Balance watches and their webhook deliveries.
+
+use crate::*;
+
+// Redirects are not followed, so an allowed host cannot bounce deliveries somewhere else.
+pub(crate) static WEBHOOK_CLIENT: Lazy<Client> = Lazy::new(|| {
+    Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(WEBHOOK_TIMEOUT)
+        .build()
+        .expect("failed to build webhook client")
+});
+
+// Registered watches by id. Lost when the process restarts unless WATCH_STORE is set.
+pub(crate) static WATCHES: Lazy<RwLock<HashMap<String, Watch>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+// Serializes writes to WATCH_STORE, so two saves can't interleave on the temporary file.
+pub(crate) static WATCH_STORE_LOCK: Lazy<tokio::sync::Mutex<()>> = Lazy::new(Default::default);
+
+#[derive(serde::Serialize, Deserialize)]
+pub(crate) struct Watch {
+    pub(crate) wallet: String,
+    pub(crate) webhook_url: String,
+    pub(crate) lamports: u64,
+    // Slot the baseline was read at.
+    pub(crate) slot: Option<u64>,
+    // Raw token amounts by mint, for watches registered with "tokens": true.
+    #[serde(default)]
+    pub(crate) tokens: Option<BTreeMap<String, u64>>,
+    // The cluster the wallet is watched on, None for the default one. Watches saved before there
+    // were clusters have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) cluster: Option<String>,
+}
+
+impl Watch {
+    pub(crate) fn in_current_cluster(&self) -> bool {
+        match &self.cluster {
+            Some(name) => *name == cluster().name,
+            None => cluster().index == 0,
+        }
+    }
+}
+
+pub(crate) fn check_webhook_url(webhook_url: &str) -> Result<(), ApiError> {
+    if WEBHOOK_ALLOWLIST.is_empty() {
+        return Err(ApiError::Forbidden("webhooks are disabled on this server".to_string()));
+    }
+    let url = reqwest::Url::parse(webhook_url).map_err(|_| ApiError::BadRequest("webhook_url is not a valid URL".to_string()))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(ApiError::BadRequest("webhook_url must be an http or https URL".to_string()));
+    }
+    match url.host_str() {
+        Some(host) if WEBHOOK_ALLOWLIST.contains(&host.to_lowercase()) => Ok(()),
+        _ => Err(ApiError::Forbidden("webhook_url host is not on the allowlist".to_string())),
+    }
+}
+
+// The current balance is the baseline, so the first webhook fires on the first change after
+// registration. A wallet and webhook URL pair is registered at most once: registering it again,
+// as a client retrying a timed-out request would, returns the existing watch instead of adding
+// one that would deliver every change a second time.
+// The existing watch is returned as it was registered, with or without token tracking.
+pub(crate) async fn register_watch(wallet: String, webhook_url: String, track_tokens: bool) -> Result<Value, ApiError> {
+    let registered = |id: &str, watch: &Watch, existing: bool| {
+        let mut reply = serde_json::json!({
+            "id": id,
+            "wallet": watch.wallet,
+            "webhook_url": watch.webhook_url,
+            "lamports": watch.lamports,
+            "existing": existing
+        });
+        if let Some(tokens) = &watch.tokens {
+            reply["tokens"] = token_amounts_json(tokens);
+        }
+        reply
+    };
+    {
+        let watches = WATCHES.read().await;
+        if let Some((id, watch)) = find_watch(&watches, &wallet, &webhook_url) {
+            return Ok(registered(id, watch, true));
+        }
+        if watches.len() >= MAX_WATCHES {
+            return Err(ApiError::Forbidden(format!("watch limit of {} reached", MAX_WATCHES)));
+        }
+    }
+    let resp = rpc_call("getBalance", serde_json::json!([wallet])).await?;
+    let lamports = extract_value(&resp)?.as_u64().unwrap_or(0);
+    let slot = extract_result(&resp)?["context"]["slot"].as_u64();
+    let tokens = match track_tokens {
+        true => Some(watched_token_amounts(&wallet).await?.0),
+        false => None,
+    };
+
+    // Checked again under the write lock, since a concurrent retry may have registered the pair
+    // while the balance was being fetched.
+    let mut watches = WATCHES.write().await;
+    if let Some((id, watch)) = find_watch(&watches, &wallet, &webhook_url) {
+        return Ok(registered(id, watch, true));
+    }
+    if watches.len() >= MAX_WATCHES {
+        return Err(ApiError::Forbidden(format!("watch limit of {} reached", MAX_WATCHES)));
+    }
+    let id = format!("{:016x}", rand::random::<u64>());
+    let cluster = (cluster().index != 0).then(|| cluster().name.clone());
+    let watch = Watch { wallet, webhook_url, lamports, slot, tokens, cluster };
+    let reply = registered(&id, &watch, false);
+    watches.insert(id, watch);
+    drop(watches);
+    save_watches().await;
+    Ok(reply)
+}
+
+// Amounts are strings: raw token amounts can exceed what a JSON number holds exactly.
+pub(crate) fn token_amounts_json(tokens: &BTreeMap<String, u64>) -> Value {
+    tokens.iter().map(|(mint, amount)| (mint.clone(), Value::String(amount.to_string()))).collect::<serde_json::Map<_, _>>().into()
+}
+
+// A wallet's raw token amounts by mint, summed over its accounts in both token programs, and
+// the older of the two slots they were read at.
+pub(crate) async fn watched_token_amounts(wallet: &str) -> Result<(BTreeMap<String, u64>, Option<u64>), ApiError> {
+    let params = |program_id: &str| serde_json::json!([wallet, { "programId": program_id }, { "encoding": "jsonParsed" }]);
+    let (legacy_resp, token_2022_resp) = tokio::join!(
+        rpc_call("getTokenAccountsByOwner", params(TOKEN_PROGRAM_ID)),
+        rpc_call("getTokenAccountsByOwner", params(TOKEN_2022_PROGRAM_ID))
+    );
+    let mut amounts = BTreeMap::new();
+    let mut slot: Option<u64> = None;
+    for resp in [legacy_resp?, token_2022_resp?] {
+        if let Some(read_at) = extract_result(&resp)?["context"]["slot"].as_u64() {
+            slot = Some(slot.map_or(read_at, |slot| slot.min(read_at)));
+        }
+        for account in extract_value(&resp)?.as_array().into_iter().flatten() {
+            let info = &account["account"]["data"]["parsed"]["info"];
+            let (Some(mint), Some(amount)) = (info["mint"].as_str(), info["tokenAmount"]["amount"].as_str().and_then(|a| a.parse::<u64>().ok())) else {
+                continue;
+            };
+            let total: &mut u64 = amounts.entry(mint.to_string()).or_default();
+            *total = total.saturating_add(amount);
+        }
+    }
+    Ok((amounts, slot))
+}
+
+// Mints whose amount moved, with a mint that came or went counting as "0" on that side.
+pub(crate) fn token_changes(old: &BTreeMap<String, u64>, new: &BTreeMap<String, u64>) -> Vec<Value> {
+    let mut mints: Vec<&String> = old.keys().chain(new.keys()).collect();
+    mints.sort();
+    mints.dedup();
+    mints
+        .into_iter()
+        .filter_map(|mint| {
+            let (old_amount, new_amount) = (old.get(mint).copied().unwrap_or(0), new.get(mint).copied().unwrap_or(0));
+            (old_amount != new_amount).then(|| {
+                serde_json::json!({ "mint": mint, "old_amount": old_amount.to_string(), "new_amount": new_amount.to_string() })
+            })
+        })
+        .collect()
+}
+
+// Writes every watch to WATCH_STORE through a temporary file, so a crash mid-write leaves the
+// previous copy intact. Failures are logged; the watches stay registered in memory.
+pub(crate) async fn save_watches() {
+    let Some(path) = WATCH_STORE.as_ref() else {
+        return;
+    };
+    let _guard = WATCH_STORE_LOCK.lock().await;
+    let json = serde_json::to_string(&*WATCHES.read().await).expect("watches serialize to JSON");
+    let temporary = format!("{}.tmp", path);
+    let saved = async {
+        tokio::fs::write(&temporary, json).await?;
+        tokio::fs::rename(&temporary, path).await
+    }
+    .await;
+    if let Err(e) = saved {
+        tracing::warn!("Failed to save watches to {}: {}", path, e);
+    }
+}
+
+// Restores the watches saved in WATCH_STORE. Each keeps its saved baseline, so a change made
+// while the server was down is delivered on the first poll. Watches whose webhook host is no
+// longer on the allowlist are dropped.
+pub(crate) async fn load_watches() {
+    let Some(path) = WATCH_STORE.as_ref() else {
+        return;
+    };
+    let saved: HashMap<String, Watch> = match tokio::fs::read_to_string(path).await {
+        Ok(raw) => match serde_json::from_str(&raw) {
+            Ok(saved) => saved,
+            Err(e) => {
+                tracing::warn!("Ignoring unreadable WATCH_STORE {}: {}", path, e);
+                return;
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => {
+            tracing::warn!("Failed to read WATCH_STORE {}: {}", path, e);
+            return;
+        }
+    };
+    let mut watches = WATCHES.write().await;
+    for (id, watch) in saved {
+        if let Err(e) = check_webhook_url(&watch.webhook_url) {
+            tracing::warn!("Dropping saved watch {}: {}", id, e);
+        } else if watches.len() < MAX_WATCHES {
+            watches.insert(id, watch);
+        }
+    }
+    tracing::info!("Restored {} watches from {}", watches.len(), path);
+}
+
+// HMAC-SHA256 (RFC 2104) of "<timestamp>.<body>" under WEBHOOK_SECRET, as lowercase hex.
+// The timestamp is signed too, so a receiver can refuse old deliveries replayed at it.
+pub(crate) fn webhook_signature(secret: &str, timestamp: u64, body: &str) -> String {
+    let mac = hmac_sha256(secret.as_bytes(), format!("{}.{}", timestamp, body).as_bytes());
+    mac.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Keys longer than the 64-byte block are hashed first; shorter ones are zero-padded.
+pub(crate) fn hmac_sha256(secret: &[u8], message: &[u8]) -> [u8; 32] {
+    const BLOCK_SIZE: usize = 64;
+    let mut key = [0u8; BLOCK_SIZE];
+    if secret.len() > BLOCK_SIZE {
+        key[..32].copy_from_slice(&Sha256::digest(secret));
+    } else {
+        key[..secret.len()].copy_from_slice(secret);
+    }
+    let pad = |byte: u8| key.map(|k| k ^ byte);
+    let inner = Sha256::new().chain_update(pad(0x36)).chain_update(message).finalize();
+    Sha256::new().chain_update(pad(0x5c)).chain_update(inner).finalize().into()
+}
+
+pub(crate) async fn deliver_webhook(url: String, payload: Value) {
+    let body = payload.to_string();
+    let mut request = WEBHOOK_CLIENT.post(&url).header("content-type", "application/json");
+    if let Some(secret) = WEBHOOK_SECRET.as_ref() {
+        let timestamp = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+        request = request
+            .header("x-webhook-timestamp", timestamp.to_string())
+            .header("x-webhook-signature", format!("sha256={}", webhook_signature(secret, timestamp, &body)));
+    }
+    let sent = request.body(body).send().await.and_then(|resp| resp.error_for_status());
+    if let Err(e) = sent {
+        tracing::warn!("webhook delivery to {} failed: {}", url, e);
+    }
+}
+
+pub(crate) fn find_watch<'a>(watches: &'a HashMap<String, Watch>, wallet: &str, webhook_url: &str) -> Option<(&'a str, &'a Watch)> {
+    watches
+        .iter()
+        .find(|(_, watch)| watch.wallet == wallet && watch.webhook_url == webhook_url && watch.in_current_cluster())
+        .map(|(id, watch)| (id.as_str(), watch))
+}
+
+pub(crate) async fn unregister_watch(id: &str) -> Result<Value, ApiError> {
+    if WATCHES.write().await.remove(id).is_none() {
+        return Err(ApiError::NotFound("Unknown watch id".to_string()));
+    }
+    save_watches().await;
+    Ok(serde_json::json!({ "id": id, "deleted": true }))
+}
+
+// One pass over every watched wallet: balances are fetched in getMultipleAccounts batches and
+// each watch whose balance moved gets a webhook. Token-tracking watches also have their token
+// accounts read, a few wallets at a time; a wallet whose read fails keeps its token baseline
+// until the next poll. Deliveries are fire-and-forget. An answer from
+// a node behind the one that set the baseline is ignored, so load-balanced RPC nodes at different
+// slots can't flip a balance back and forth and report the same change twice.
+pub(crate) async fn poll_watches() -> Result<(), ApiError> {
+    let (mut wallets, mut token_wallets): (Vec<String>, Vec<String>) = {
+        let watches = WATCHES.read().await;
+        let watches: Vec<&Watch> = watches.values().filter(|watch| watch.in_current_cluster()).collect();
+        let wallets = watches.iter().map(|watch| watch.wallet.clone()).collect();
+        let token_wallets = watches.iter().filter(|watch| watch.tokens.is_some()).map(|watch| watch.wallet.clone()).collect();
+        (wallets, token_wallets)
+    };
+    wallets.sort();
+    wallets.dedup();
+    token_wallets.sort();
+    token_wallets.dedup();
+
+    let config = serde_json::json!({ "encoding": "base64", "dataSlice": { "offset": 0, "length": 0 } });
+    let mut balances = HashMap::new();
+    for chunk in wallets.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        let resp = rpc_call("getMultipleAccounts", serde_json::json!([chunk, config])).await?;
+        let slot = extract_result(&resp)?["context"]["slot"].as_u64();
+        let accounts = extract_value(&resp)?.as_array().cloned().unwrap_or_default();
+        for (wallet, account) in chunk.iter().zip(accounts) {
+            balances.insert(wallet.clone(), (account["lamports"].as_u64().unwrap_or(0), slot));
+        }
+    }
+    let token_balances: HashMap<String, (BTreeMap<String, u64>, Option<u64>)> = futures::stream::iter(token_wallets)
+        .map(|wallet| async move {
+            let amounts = watched_token_amounts(&wallet).await;
+            if let Err(e) = &amounts {
+                tracing::warn!("Failed to read token balances of watched wallet {}: {}", wallet, e);
+            }
+            amounts.ok().map(|amounts| (wallet, amounts))
+        })
+        .buffer_unordered(WATCH_TOKEN_CONCURRENCY)
+        .filter_map(futures::future::ready)
+        .collect()
+        .await;
+
+    let mut deliveries = Vec::new();
+    for watch in WATCHES.write().await.values_mut().filter(|watch| watch.in_current_cluster()) {
+        let Some(&(lamports, slot)) = balances.get(&watch.wallet) else {
+            continue;
+        };
+        if let (Some(seen), Some(slot)) = (watch.slot, slot)
+            && slot < seen
+        {
+            continue;
+        }
+        let mut changes = None;
+        if let (Some(old), Some((new, token_slot))) = (&watch.tokens, token_balances.get(&watch.wallet))
+            && !matches!((watch.slot, token_slot), (Some(seen), Some(read_at)) if read_at < &seen)
+        {
+            changes = Some(token_changes(old, new));
+        }
+        let token_moved = changes.as_ref().is_some_and(|changes| !changes.is_empty());
+        if lamports != watch.lamports || token_moved {
+            let mut payload = serde_json::json!({
+                "wallet": watch.wallet,
+                "old_lamports": watch.lamports,
+                "new_lamports": lamports,
+                "slot": slot
+            });
+            if watch.tokens.is_some() {
+                payload["token_changes"] = changes.clone().unwrap_or_default().into();
+            }
+            deliveries.push((watch.webhook_url.clone(), payload));
+            watch.lamports = lamports;
+            if token_moved {
+                watch.tokens = token_balances.get(&watch.wallet).map(|(new, _)| new.clone());
+            }
+        }
+        watch.slot = slot.or(watch.slot);
+    }
+    if !deliveries.is_empty() {
+        save_watches().await;
+    }
+    for (url, payload) in deliveries {
+        tokio::spawn(deliver_webhook(url, payload));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    // The HMAC-SHA256 test cases of RFC 4231, section 4.
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        let long_key = [0xaa; 131];
+        let cases: [(&[u8], &[u8], &str); 7] = [
+            (&[0x0b; 20], b"Hi There", "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"),
+            (b"Jefe", b"what do ya want for nothing?", "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"),
+            (&[0xaa; 20], &[0xdd; 50], "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe"),
+            (
+                &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25],
+                &[0xcd; 50],
+                "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b",
+            ),
+            // Case 5 checks only the first 128 bits.
+            (&[0x0c; 20], b"Test With Truncation", "a3b6167473100ee06e0c796c2955552b"),
+            // Cases 6 and 7 use a key longer than the block size, which is hashed first.
+            (&long_key, b"Test Using Larger Than Block-Size Key - Hash Key First", "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"),
+            (
+                &long_key,
+                b"This is a test using a larger than block-size key and a larger than block-size data. The key needs to be hashed before being used by the HMAC algorithm.",
+                "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+            ),
+        ];
+        for (key, data, expected) in cases {
+            assert!(hex(&hmac_sha256(key, data)).starts_with(expected), "key {}", hex(key));
+        }
+    }
+
+    #[test]
+    fn webhook_signature_signs_timestamp_and_body() {
+        assert_eq!(webhook_signature("secret", 1700000000, r#"{"a":1}"#), "49f24e537407743fa4a0242bb63b94b9a47ee99cbbe071ccd8a22550ae411686");
+    }
+}
@@ -0,0 +1,278 @@
+//! Offline Solana formats: addresses, program derived addresses and serialized transactions.
+
+use crate::*;
+
+// CPU-heavy decoding and derivation runs on tokio's blocking pool so it can't stall the async
+// workers serving other requests. Panics are re-raised on the calling task.
+pub(crate) async fn run_blocking<T, F>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(value) => value,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+pub(crate) fn parse_pubkey(address: &str) -> Option<[u8; 32]> {
+    bs58::decode(address).into_vec().ok()?.try_into().ok()
+}
+
+// A 32-byte address is only a usable ed25519 public key (something that can sign) if it is a
+// point on the curve. Program derived addresses are deliberately off-curve.
+pub(crate) fn is_on_curve(pubkey: &[u8; 32]) -> bool {
+    curve25519_dalek::edwards::CompressedEdwardsY(*pubkey).decompress().is_some()
+}
+
+pub(crate) fn find_program_address(seeds: &[&[u8]], program_id: &[u8; 32]) -> Option<[u8; 32]> {
+    for bump in (0..=u8::MAX).rev() {
+        let mut hasher = Sha256::new();
+        for seed in seeds {
+            hasher.update(seed);
+        }
+        hasher.update([bump]);
+        hasher.update(program_id);
+        hasher.update(b"ProgramDerivedAddress");
+        let candidate: [u8; 32] = hasher.finalize().into();
+        if !is_on_curve(&candidate) {
+            return Some(candidate);
+        }
+    }
+    None
+}
+
+pub(crate) fn parse_signature(signature: &str) -> Option<[u8; 64]> {
+    bs58::decode(signature).into_vec().ok()?.try_into().ok()
+}
+
+// Cursor over a serialized transaction or account; every read returns None past the end of the input.
+pub(crate) struct WireReader<'a> {
+    pub(crate) bytes: &'a [u8],
+    pub(crate) pos: usize,
+}
+
+impl<'a> WireReader<'a> {
+    pub(crate) fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let slice = self.bytes.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(slice)
+    }
+
+    pub(crate) fn byte(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    pub(crate) fn pubkey(&mut self) -> Option<String> {
+        self.take(32).map(|key| bs58::encode(key).into_string())
+    }
+
+    pub(crate) fn u16_le(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub(crate) fn u32_le(&mut self) -> Option<u32> {
+        self.take(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    // Borsh string: a u32 length followed by UTF-8. Metaplex pads its fixed-size fields with NULs.
+    pub(crate) fn borsh_string(&mut self) -> Option<String> {
+        let len = self.u32_le()? as usize;
+        let text = std::str::from_utf8(self.take(len)?).ok()?;
+        Some(text.trim_end_matches('\0').to_string())
+    }
+
+    // Borsh Option<T> tag: true when a value follows.
+    pub(crate) fn option(&mut self) -> Option<bool> {
+        self.byte().map(|tag| tag == 1)
+    }
+
+    // Solana's compact-u16 length prefix: 7 bits per byte, low bits first, at most 3 bytes.
+    pub(crate) fn compact_u16(&mut self) -> Option<usize> {
+        let mut value = 0;
+        for shift in [0, 7, 14] {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+// Decodes a wire-format legacy or v0 transaction without touching the RPC. Accounts loaded
+// from address lookup tables can't be resolved offline, so they are shown as "<table>[<index>]".
+pub(crate) fn decode_transaction(bytes: &[u8]) -> Option<Value> {
+    let mut reader = WireReader { bytes, pos: 0 };
+    let signature_count = reader.compact_u16()?;
+    let signatures: Vec<String> =
+        (0..signature_count).map(|_| reader.take(64).map(|s| bs58::encode(s).into_string())).collect::<Option<_>>()?;
+
+    // Versioned messages set the top bit of the first byte; only version 0 exists so far.
+    let prefix = *bytes.get(reader.pos)?;
+    let versioned = prefix & 0x80 != 0;
+    if versioned {
+        reader.byte()?;
+        if prefix & 0x7f != 0 {
+            return None;
+        }
+    }
+
+    let header = reader.take(3)?;
+    let key_count = reader.compact_u16()?;
+    let account_keys: Vec<String> = (0..key_count).map(|_| reader.pubkey()).collect::<Option<_>>()?;
+    let recent_blockhash = reader.pubkey()?;
+
+    let instruction_count = reader.compact_u16()?;
+    let mut raw_instructions = Vec::new();
+    for _ in 0..instruction_count {
+        let program_index = reader.byte()?;
+        let account_count = reader.compact_u16()?;
+        let accounts = reader.take(account_count)?;
+        let data_len = reader.compact_u16()?;
+        let data = reader.take(data_len)?;
+        raw_instructions.push((program_index, accounts, data));
+    }
+
+    // Loaded accounts follow the static keys: every table's writable entries, then the readonly ones.
+    let mut lookups = Vec::new();
+    let mut loaded_writable = Vec::new();
+    let mut loaded_readonly = Vec::new();
+    if versioned {
+        let lookup_count = reader.compact_u16()?;
+        for _ in 0..lookup_count {
+            let table = reader.pubkey()?;
+            let writable_count = reader.compact_u16()?;
+            let writable = reader.take(writable_count)?;
+            let readonly_count = reader.compact_u16()?;
+            let readonly = reader.take(readonly_count)?;
+            loaded_writable.extend(writable.iter().map(|index| format!("{}[{}]", table, index)));
+            loaded_readonly.extend(readonly.iter().map(|index| format!("{}[{}]", table, index)));
+            lookups.push(serde_json::json!({
+                "account_key": table,
+                "writable_indexes": writable,
+                "readonly_indexes": readonly
+            }));
+        }
+    }
+    if reader.pos != bytes.len() {
+        return None;
+    }
+
+    let all_keys: Vec<&String> = account_keys.iter().chain(&loaded_writable).chain(&loaded_readonly).collect();
+    let key_at = |index: u8| all_keys.get(index as usize).map(|key| key.to_string());
+    let instructions: Vec<Value> = raw_instructions
+        .into_iter()
+        .map(|(program_index, accounts, data)| {
+            let accounts: Vec<String> = accounts.iter().map(|&index| key_at(index)).collect::<Option<_>>()?;
+            Some(serde_json::json!({
+                "program_id": account_keys.get(program_index as usize)?,
+                "accounts": accounts,
+                "data": bs58::encode(data).into_string()
+            }))
+        })
+        .collect::<Option<_>>()?;
+
+    let mut message = serde_json::json!({
+        "header": {
+            "num_required_signatures": header[0],
+            "num_readonly_signed_accounts": header[1],
+            "num_readonly_unsigned_accounts": header[2]
+        },
+        "account_keys": account_keys,
+        "recent_blockhash": recent_blockhash,
+        "instructions": instructions
+    });
+    if versioned {
+        message["address_table_lookups"] = Value::Array(lookups);
+    }
+    Some(serde_json::json!({
+        "version": if versioned { serde_json::json!(0) } else { serde_json::json!("legacy") },
+        "signatures": signatures,
+        "message": message
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> String {
+        bs58::encode([byte; 32]).into_string()
+    }
+
+    // A system transfer of 1_000_000 lamports from [1; 32] to [2; 32], signed by the sender.
+    fn legacy_transfer() -> Vec<u8> {
+        let mut bytes = vec![1];
+        bytes.extend([7; 64]);
+        bytes.extend([1, 0, 1, 3]);
+        bytes.extend([1; 32]);
+        bytes.extend([2; 32]);
+        bytes.extend([0; 32]);
+        bytes.extend([9; 32]);
+        bytes.extend([1, 2, 2, 0, 1, 12, 2, 0, 0, 0, 0x40, 0x42, 0x0f, 0, 0, 0, 0, 0]);
+        bytes
+    }
+
+    // A v0 transaction whose instruction uses one writable and one readonly account from the
+    // lookup table [5; 32].
+    fn v0_with_lookup() -> Vec<u8> {
+        let mut bytes = vec![1];
+        bytes.extend([7; 64]);
+        bytes.extend([0x80, 1, 0, 1, 2]);
+        bytes.extend([1; 32]);
+        bytes.extend([3; 32]);
+        bytes.extend([9; 32]);
+        bytes.extend([1, 1, 3, 0, 2, 3, 1, 1]);
+        bytes.extend([1]);
+        bytes.extend([5; 32]);
+        bytes.extend([1, 4, 1, 7]);
+        bytes
+    }
+
+    #[test]
+    fn decodes_a_legacy_transaction() {
+        let tx = decode_transaction(&legacy_transfer()).unwrap();
+        assert_eq!(tx["version"], "legacy");
+        assert_eq!(tx["signatures"], serde_json::json!([bs58::encode([7; 64]).into_string()]));
+        let message = &tx["message"];
+        assert_eq!(message["header"]["num_required_signatures"], 1);
+        assert_eq!(message["header"]["num_readonly_unsigned_accounts"], 1);
+        assert_eq!(message["account_keys"], serde_json::json!([key(1), key(2), key(0)]));
+        assert_eq!(message["recent_blockhash"], key(9));
+        assert_eq!(message["instructions"][0]["program_id"], key(0));
+        assert_eq!(message["instructions"][0]["accounts"], serde_json::json!([key(1), key(2)]));
+        assert_eq!(message["instructions"][0]["data"], bs58::encode([2, 0, 0, 0, 0x40, 0x42, 0x0f, 0, 0, 0, 0, 0]).into_string());
+        assert!(message.get("address_table_lookups").is_none());
+    }
+
+    #[test]
+    fn decodes_a_v0_transaction_with_lookup_tables() {
+        let tx = decode_transaction(&v0_with_lookup()).unwrap();
+        assert_eq!(tx["version"], 0);
+        let message = &tx["message"];
+        assert_eq!(message["account_keys"], serde_json::json!([key(1), key(3)]));
+        assert_eq!(message["instructions"][0]["program_id"], key(3));
+        assert_eq!(message["instructions"][0]["accounts"], serde_json::json!([key(1), format!("{}[4]", key(5)), format!("{}[7]", key(5))]));
+        assert_eq!(
+            message["address_table_lookups"],
+            serde_json::json!([{ "account_key": key(5), "writable_indexes": [4], "readonly_indexes": [7] }])
+        );
+    }
+
+    #[test]
+    fn truncated_or_padded_transactions_are_rejected() {
+        for bytes in [legacy_transfer(), v0_with_lookup()] {
+            for len in 0..bytes.len() {
+                assert!(decode_transaction(&bytes[..len]).is_none(), "truncated to {} bytes", len);
+            }
+            let mut padded = bytes.clone();
+            padded.push(0);
+            assert!(decode_transaction(&padded).is_none());
+        }
+        let mut v1 = v0_with_lookup();
+        v1[65] = 0x81;
+        assert!(decode_transaction(&v1).is_none());
+    }
+}